@@ -18,6 +18,7 @@ ssh $SSH_DEPLOY_TARGET -- "mkdir -p /opt/spacetraders_rs"
 echo "Deploying to $SSH_DEPLOY_TARGET"
 rsync -avzP target/release/run $SSH_DEPLOY_TARGET:/opt/spacetraders_rs
 rsync -avzP remote.env $SSH_DEPLOY_TARGET:/opt/spacetraders_rs/.env
+rsync -avzP agentconfig.toml $SSH_DEPLOY_TARGET:/opt/spacetraders_rs/agentconfig.toml
 rsync -avzP deploy/spacetraders_rs.service $SSH_DEPLOY_TARGET:/etc/systemd/system/spacetraders_rs.service
 
 echo "Restarting service"
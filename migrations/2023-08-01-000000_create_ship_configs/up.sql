@@ -0,0 +1,6 @@
+CREATE TABLE ship_configs (
+    symbol VARCHAR(255) PRIMARY KEY,
+    config JSON NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -98,6 +98,20 @@ CREATE TABLE public.markets (
 
 ALTER TABLE public.markets OWNER TO postgres;
 
+--
+-- Name: ship_configs; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.ship_configs (
+    symbol character varying(255) NOT NULL,
+    config json NOT NULL,
+    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+
+ALTER TABLE public.ship_configs OWNER TO postgres;
+
 --
 -- Name: surveys; Type: TABLE; Schema: public; Owner: postgres
 --
@@ -183,6 +197,14 @@ ALTER TABLE ONLY public.markets
     ADD CONSTRAINT markets_symbol_unique UNIQUE (symbol);
 
 
+--
+-- Name: ship_configs ship_configs_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.ship_configs
+    ADD CONSTRAINT ship_configs_pkey PRIMARY KEY (symbol);
+
+
 --
 -- Name: surveys surveys_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
 --
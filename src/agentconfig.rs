@@ -1,52 +1,516 @@
+use crate::database::DatabaseClient;
 use crate::shipconfig::*;
-use crate::util;
+use log::{error, info, warn};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
 
-// todo: load 'shipconfig' from postgres on startup
-// for now: define statically here
-// the code isn't supposed to have gameplay 'data' hardcoded in it, so lets keep it contrained to this file
+// The agent config is loaded on startup, either from the file at $AGENT_CONFIG
+// (.toml or .json), or from the 'ship_configs' table when that isn't set.
+// $AGENT_CALLSIGN, $AGENT_FACTION and $AGENT_EMAIL override whatever the source says.
+// The code isn't supposed to have gameplay 'data' hardcoded in it: see agentconfig.example.toml
+//
+// While running, the same source is polled by the ConfigWatcher, and changes are pushed
+// to each ship's executor, which picks them up at its next step.
 
-const UNITED_ASTEROID_FIELD: &str = "X1-HY12-60905F";
-const UNITED_SHIPYARD: &str = "X1-HY12-22347Z";
-lazy_static::lazy_static! {
-    pub static ref CONFIG: AgentConfig = {
-        let callsign: String = std::env::var("AGENT_CALLSIGN").expect("AGENT_CALLSIGN must be set");
-        let faction: String = std::env::var("AGENT_FACTION").expect("AGENT_FACTION must be set");
-        let email: Option<String> = std::env::var("AGENT_EMAIL").ok();
-        let mut config = AgentConfig {
-            callsign,
-            faction,
-            email,
-            ships: vec![],
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, String),
+    Invalid(String),
+    Database(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "failed to read {}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {}: {}", path, e),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {}", e),
+            ConfigError::Database(e) => write!(f, "failed to read ship_configs: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub async fn load() -> AgentConfig {
+    let config = try_load().await.unwrap_or_else(|e| panic!("{}", e));
+    info!(
+        "Loaded config for {} with {} ships",
+        config.callsign,
+        config.ships.len()
+    );
+    config
+}
+
+pub async fn try_load() -> Result<AgentConfig, ConfigError> {
+    ConfigSource::from_env().load().await
+}
+
+/// Where the config comes from: the file at AGENT_CONFIG, or else the database
+pub enum ConfigSource {
+    File(String),
+    Database(DatabaseClient),
+}
+
+impl ConfigSource {
+    pub fn from_env() -> Self {
+        match std::env::var("AGENT_CONFIG") {
+            Ok(path) => ConfigSource::File(path),
+            Err(_) => ConfigSource::Database(DatabaseClient::new()),
+        }
+    }
+
+    pub async fn load(&self) -> Result<AgentConfig, ConfigError> {
+        let mut config = match self {
+            ConfigSource::File(path) => from_file(path)?,
+            ConfigSource::Database(db_client) => AgentConfig {
+                callsign: String::new(),
+                faction: String::new(),
+                email: None,
+                ships: db_client.load_ship_configs().await?,
+            },
         };
-        // 20 ships
-        for i in 3..23 {
-            let ship_symbol = util::ship_symbol(&config.callsign, i);
-            let ship_config = ShipConfig {
-                symbol: ship_symbol.clone(),
-                shipyard: UNITED_SHIPYARD.into(),
-                module_config: Some(ModulesConfig {
-                    install_location: Some(UNITED_SHIPYARD.into()),
-                    modules: vec![
-                        ModuleConfig {
-                            module: "MOUNT_SURVEYOR_I".into(),
-                            source: None,
-                        },
-                        ModuleConfig {
-                            module: "MOUNT_MINING_LASER_II".into(),
-                            source: Some(UNITED_SHIPYARD.into()),
-                        },
-                        ModuleConfig {
-                            module: "MOUNT_MINING_LASER_II".into(),
-                            source: Some(UNITED_SHIPYARD.into()),
-                        },
-                    ],
-                }),
-                script: ShipScript::Mining(MiningConfig {
-                    asteroid_symbol: UNITED_ASTEROID_FIELD.into(),
-                }),
-            };
-            config.ships.push(ship_config);
-        }
-        config
+        apply_overrides(&mut config, |key| std::env::var(key).ok());
+        validate(&config)?;
+        Ok(config)
+    }
+}
+
+pub fn from_file(path: &str) -> Result<AgentConfig, ConfigError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+    let extension = Path::new(path).extension().and_then(|e| e.to_str());
+    match extension {
+        Some("toml") => {
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.into(), e.to_string()))
+        }
+        Some("json") => serde_json::from_str(&contents)
+            .map_err(|e| ConfigError::Parse(path.into(), e.to_string())),
+        _ => Err(ConfigError::Parse(
+            path.into(),
+            "expected a .toml or .json file".into(),
+        )),
+    }
+}
+
+/// Environment variables take precedence over the config source
+pub fn apply_overrides(config: &mut AgentConfig, var: impl Fn(&str) -> Option<String>) {
+    if let Some(callsign) = var("AGENT_CALLSIGN") {
+        config.callsign = callsign;
+    }
+    if let Some(faction) = var("AGENT_FACTION") {
+        config.faction = faction;
+    }
+    if let Some(email) = var("AGENT_EMAIL") {
+        config.email = (!email.is_empty()).then_some(email);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CALLSIGN_REGEX: Regex = Regex::new(r"^[A-Z0-9_-]{3,14}$").unwrap();
+    static ref FACTION_REGEX: Regex = Regex::new(r"^[A-Z_]+$").unwrap();
+    static ref WAYPOINT_REGEX: Regex = Regex::new(r"^[A-Z0-9]+-[A-Z0-9]+-[A-Z0-9]+$").unwrap();
+    static ref SHIP_REGEX: Regex = Regex::new(r"^(?P<callsign>.+)-[0-9A-F]+$").unwrap();
+}
+
+pub fn validate(config: &AgentConfig) -> Result<(), ConfigError> {
+    let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+
+    if !CALLSIGN_REGEX.is_match(&config.callsign) {
+        return invalid(format!("callsign '{}' is malformed", config.callsign));
+    }
+    if !FACTION_REGEX.is_match(&config.faction) {
+        return invalid(format!("faction '{}' is malformed", config.faction));
+    }
+
+    let mut seen = HashSet::new();
+    for ship in config.ships.iter() {
+        if !seen.insert(&ship.symbol) {
+            return invalid(format!("ship '{}' is configured twice", ship.symbol));
+        }
+        validate_ship(&config.callsign, ship)?;
+    }
+    Ok(())
+}
+
+pub fn validate_ship(callsign: &str, ship: &ShipConfig) -> Result<(), ConfigError> {
+    let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+    let check_waypoint = |ship: &str, field: &str, symbol: &str| {
+        if WAYPOINT_REGEX.is_match(symbol) {
+            Ok(())
+        } else {
+            invalid(format!(
+                "{}: {} '{}' is not a waypoint",
+                ship, field, symbol
+            ))
+        }
     };
+
+    let ship_callsign = SHIP_REGEX
+        .captures(&ship.symbol)
+        .map(|c| c.name("callsign").unwrap().as_str());
+    if ship_callsign != Some(callsign) {
+        return invalid(format!(
+            "ship '{}' doesn't belong to {}",
+            ship.symbol, callsign
+        ));
+    }
+    check_waypoint(&ship.symbol, "shipyard", &ship.shipyard)?;
+    if let Some(module_config) = &ship.module_config {
+        if let Some(install_location) = &module_config.install_location {
+            check_waypoint(&ship.symbol, "install_location", install_location)?;
+        }
+        for module in module_config.modules.iter() {
+            if !module.module.starts_with("MOUNT_") {
+                return invalid(format!(
+                    "{}: '{}' is not a mount",
+                    ship.symbol, module.module
+                ));
+            }
+            if let Some(source) = &module.source {
+                check_waypoint(&ship.symbol, "source", source)?;
+            }
+        }
+    }
+    match &ship.script {
+        ShipScript::None => {}
+        ShipScript::Mining(mining_config) => {
+            check_waypoint(
+                &ship.symbol,
+                "asteroid_symbol",
+                &mining_config.asteroid_symbol,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Polls the config source, and publishes per-ship changes to the subscribed executors
+#[derive(Clone)]
+pub struct ConfigWatcher {
+    // the config as last read from the source, shared between clones
+    current: Arc<std::sync::Mutex<AgentConfig>>,
+    senders: Arc<HashMap<String, watch::Sender<ShipConfig>>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(config: &AgentConfig) -> Self {
+        let senders = config
+            .ships
+            .iter()
+            .map(|ship| (ship.symbol.clone(), watch::channel(ship.clone()).0))
+            .collect();
+        Self {
+            current: Arc::new(std::sync::Mutex::new(config.clone())),
+            senders: Arc::new(senders),
+        }
+    }
+
+    pub fn subscribe(&self, ship_symbol: &str) -> Option<watch::Receiver<ShipConfig>> {
+        self.senders.get(ship_symbol).map(|tx| tx.subscribe())
+    }
+
+    /// The config the ship's executor is currently being asked to follow
+    pub fn ship_config(&self, ship_symbol: &str) -> Option<ShipConfig> {
+        self.senders.get(ship_symbol).map(|tx| tx.borrow().clone())
+    }
+
+    /// Change a ship's config in memory, eg. from the control api.
+    /// This lasts until the config source next changes that ship.
+    pub fn update(&self, ship_symbol: &str, f: impl FnOnce(&mut ShipConfig)) -> bool {
+        match self.senders.get(ship_symbol) {
+            Some(tx) => {
+                tx.send_if_modified(|current| {
+                    let prev = current.clone();
+                    f(current);
+                    *current != prev
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ships whose config was changed in memory, and differs from the config source
+    pub fn overridden(&self) -> Vec<ShipConfig> {
+        let mut ret = vec![];
+        let current = self.current.lock().unwrap();
+        for ship in current.ships.iter() {
+            if let Some(current) = self.ship_config(&ship.symbol) {
+                if &current != ship {
+                    ret.push(current);
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn apply(&self, config: AgentConfig) {
+        let mut source = self.current.lock().unwrap();
+        if config.callsign != source.callsign || config.faction != source.faction {
+            warn!("Agent changed in config: restart to pick it up");
+        }
+        // only ships whose entry in the source changed: the rest keep any in-memory update
+        let previous = |symbol: &str| source.ships.iter().find(|s| s.symbol == symbol);
+        for ship in config.ships.iter() {
+            if previous(&ship.symbol) == Some(ship) {
+                continue;
+            }
+            match self.senders.get(&ship.symbol) {
+                Some(tx) => {
+                    tx.send_if_modified(|current| {
+                        if current == ship {
+                            return false;
+                        }
+                        info!("Config changed for {}", ship.symbol);
+                        *current = ship.clone();
+                        true
+                    });
+                }
+                None => warn!("New ship {} in config: restart to pick it up", ship.symbol),
+            }
+        }
+        // ships that were just removed from the config are stopped
+        for (symbol, tx) in self.senders.iter() {
+            let removed = previous(symbol).is_some()
+                && !config.ships.iter().any(|ship| &ship.symbol == symbol);
+            if removed {
+                tx.send_if_modified(|current| {
+                    if current.paused {
+                        return false;
+                    }
+                    info!("{} removed from config: pausing", symbol);
+                    current.paused = true;
+                    true
+                });
+            }
+        }
+        *source = config;
+    }
+
+    pub async fn run(self, interval: Duration) {
+        // one database pool for every reload
+        self.poll(ConfigSource::from_env(), interval).await
+    }
+
+    /// A source that fails to load is logged and retried at the next interval
+    pub async fn poll(self, source: ConfigSource, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match source.load().await {
+                Ok(config) if config != *self.current.lock().unwrap() => self.apply(config),
+                Ok(_) => {}
+                Err(e) => error!("Ignoring config reload: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        callsign = "SOLARTRADE_INC"
+        faction = "UNITED"
+
+        [[ships]]
+        symbol = "SOLARTRADE_INC-3"
+        shipyard = "X1-HY12-22347Z"
+        script = { Mining = { asteroid_symbol = "X1-HY12-60905F" } }
+
+        [ships.module_config]
+        install_location = "X1-HY12-22347Z"
+        modules = [
+            { module = "MOUNT_SURVEYOR_I" },
+            { module = "MOUNT_MINING_LASER_II", source = "X1-HY12-22347Z" },
+        ]
+
+        [[ships]]
+        symbol = "SOLARTRADE_INC-A"
+        shipyard = "X1-HY12-22347Z"
+        script = "None"
+    "#;
+
+    #[test]
+    fn test_parse_toml() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        assert_eq!(config.ships.len(), 2);
+        assert_eq!(
+            config.ships[0].script,
+            ShipScript::Mining(MiningConfig {
+                asteroid_symbol: "X1-HY12-60905F".into()
+            })
+        );
+        let modules = &config.ships[0].module_config.as_ref().unwrap().modules;
+        assert_eq!(modules[0].source, None);
+        assert_eq!(config.ships[1].script, ShipScript::None);
+        assert!(validate(&config).is_ok());
+
+        // json is the same shape
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<AgentConfig>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config = from_file("agentconfig.example.toml").unwrap();
+        assert_eq!(config.ships.len(), 20);
+        assert!(validate(&config).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_script() {
+        let config = CONFIG.replace(r#"script = "None""#, r#"script = "Trading""#);
+        assert!(toml::from_str::<AgentConfig>(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+
+        let mut c = config.clone();
+        c.ships[1].symbol = "SOMEONE_ELSE-A".into();
+        assert!(validate(&c).is_err());
+
+        let mut c = config.clone();
+        c.ships[1].symbol = "SOLARTRADE_INC-3".into();
+        assert!(validate(&c).is_err());
+
+        let mut c = config.clone();
+        c.ships[0].shipyard = "X1-HY12".into();
+        assert!(validate(&c).is_err());
+
+        let mut c = config.clone();
+        c.ships[0].module_config.as_mut().unwrap().modules[0].module = "SURVEYOR".into();
+        assert!(validate(&c).is_err());
+    }
+
+    #[test]
+    fn test_watcher() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        let watcher = ConfigWatcher::new(&config);
+        let mut rx_3 = watcher.subscribe("SOLARTRADE_INC-3").unwrap();
+        let mut rx_a = watcher.subscribe("SOLARTRADE_INC-A").unwrap();
+        assert!(watcher.subscribe("SOLARTRADE_INC-B").is_none());
+
+        // unchanged config: nobody is notified
+        watcher.apply(config.clone());
+        assert!(!rx_3.has_changed().unwrap());
+        assert!(!rx_a.has_changed().unwrap());
+
+        // move ship 3 to another asteroid field
+        let mut c = config.clone();
+        c.ships[0].script = ShipScript::Mining(MiningConfig {
+            asteroid_symbol: "X1-HY12-00000A".into(),
+        });
+        watcher.apply(c.clone());
+        assert!(rx_3.has_changed().unwrap());
+        assert!(!rx_a.has_changed().unwrap());
+        assert_eq!(rx_3.borrow_and_update().script, c.ships[0].script);
+
+        // drop ship A from the config
+        c.ships.pop();
+        watcher.apply(c);
+        assert!(!rx_3.has_changed().unwrap());
+        assert!(rx_a.has_changed().unwrap());
+        assert!(rx_a.borrow_and_update().paused);
+    }
+
+    #[test]
+    fn test_watcher_overridden() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        let watcher = ConfigWatcher::new(&config);
+        assert!(watcher.overridden().is_empty());
+
+        assert!(watcher.update("SOLARTRADE_INC-A", |c| c.paused = true));
+        let overridden = watcher.overridden();
+        assert_eq!(overridden.len(), 1);
+        assert_eq!(overridden[0].symbol, "SOLARTRADE_INC-A");
+        assert!(overridden[0].paused);
+    }
+
+    #[test]
+    fn test_watcher_keeps_overrides() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        let watcher = ConfigWatcher::new(&config);
+        let rx_3 = watcher.subscribe("SOLARTRADE_INC-3").unwrap();
+        assert!(watcher.update("SOLARTRADE_INC-A", |c| c.paused = true));
+
+        // ship 3 changes in the source: ship A stays paused
+        let mut c = config.clone();
+        c.ships[0].script = ShipScript::None;
+        watcher.apply(c.clone());
+        assert!(rx_3.has_changed().unwrap());
+        assert!(watcher.ship_config("SOLARTRADE_INC-A").unwrap().paused);
+        assert_eq!(watcher.overridden().len(), 1);
+
+        // until ship A itself changes there
+        c.ships[1].shipyard = "X1-HY12-00000A".into();
+        watcher.apply(c.clone());
+        assert!(!watcher.ship_config("SOLARTRADE_INC-A").unwrap().paused);
+        assert!(watcher.overridden().is_empty());
+
+        // a ship removed from the source and resumed stays resumed
+        c.ships.pop();
+        watcher.apply(c.clone());
+        assert!(watcher.update("SOLARTRADE_INC-A", |c| c.paused = false));
+        c.ships[0].script = config.ships[0].script.clone();
+        watcher.apply(c);
+        assert!(!watcher.ship_config("SOLARTRADE_INC-A").unwrap().paused);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_bad_source() {
+        let config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        let watcher = ConfigWatcher::new(&config);
+        let mut rx_3 = watcher.subscribe("SOLARTRADE_INC-3").unwrap();
+
+        // a half-finished edit doesn't stop the watcher
+        let path = std::env::temp_dir().join(format!("agentconfig-{}.toml", std::process::id()));
+        std::fs::write(&path, "callsign = ").unwrap();
+        let source = ConfigSource::File(path.to_str().unwrap().into());
+        let task = tokio::spawn(watcher.clone().poll(source, Duration::from_millis(10)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!task.is_finished());
+        assert!(!rx_3.has_changed().unwrap());
+
+        // and the next good one is picked up
+        std::fs::write(&path, CONFIG.replace("X1-HY12-60905F", "X1-HY12-00000A")).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), rx_3.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rx_3.borrow().script,
+            ShipScript::Mining(MiningConfig {
+                asteroid_symbol: "X1-HY12-00000A".into()
+            })
+        );
+        task.abort();
+        std::fs::remove_file(&path).unwrap();
+
+        // nor does a database that can't be reached
+        let source = ConfigSource::Database(DatabaseClient::with_url("postgres://127.0.0.1:1/x"));
+        assert!(matches!(source.load().await, Err(ConfigError::Database(_))));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut config: AgentConfig = toml::from_str(CONFIG).unwrap();
+        apply_overrides(&mut config, |key| match key {
+            "AGENT_FACTION" => Some("COSMIC".into()),
+            "AGENT_EMAIL" => Some("".into()),
+            _ => None,
+        });
+        assert_eq!(config.callsign, "SOLARTRADE_INC");
+        assert_eq!(config.faction, "COSMIC");
+        assert_eq!(config.email, None);
+    }
 }
@@ -0,0 +1,107 @@
+use spacetraders_rs::decision_tree::{evaluate_recursive, evaluate_with, EvaluateOptions};
+use spacetraders_rs::models::*;
+use spacetraders_rs::scripts::mining::MiningController;
+use std::time::{Duration, Instant};
+
+///
+/// Times decision_tree's Evaluator against the recursive evaluation, on a mining graph
+/// like the one MiningController::setup builds for each ship at startup
+///
+/// cargo run --release --bin bench_evaluate [runs]
+///
+fn main() {
+    let runs: u32 = std::env::args()
+        .nth(1)
+        .map(|s| s.parse().expect("runs should be a number"))
+        .unwrap_or(20);
+
+    let market = |symbol: &str, goods: &[(&str, u32)]| Market {
+        symbol: symbol.into(),
+        trade_goods: goods
+            .iter()
+            .map(|&(symbol, sell_price)| MarketTradeGood {
+                symbol: symbol.into(),
+                sell_price,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let markets = vec![
+        market(
+            "X1-HY12-60905F",
+            &[
+                ("ICE_WATER", 10),
+                ("QUARTZ_SAND", 20),
+                ("SILICON_CRYSTALS", 30),
+            ],
+        ),
+        market(
+            "X1-HY12-A1",
+            &[("QUARTZ_SAND", 25), ("IRON_ORE", 40), ("COPPER_ORE", 45)],
+        ),
+    ];
+    let mount = |symbol: &str, strength, deposits: Option<Vec<String>>, power| ShipMount {
+        symbol: symbol.into(),
+        strength: Some(strength),
+        deposits,
+        requirements: ShipMountRequirements {
+            power,
+            crew: 2,
+            slots: None,
+        },
+    };
+    let deposits = [
+        "QUARTZ_SAND",
+        "SILICON_CRYSTALS",
+        "ICE_WATER",
+        "IRON_ORE",
+        "COPPER_ORE",
+        "ALUMINUM_ORE",
+    ];
+    let mounts = vec![
+        mount(
+            "MOUNT_SURVEYOR_II",
+            2,
+            Some(deposits.iter().map(|&d| d.to_string()).collect()),
+            4,
+        ),
+        mount("MOUNT_MINING_LASER_II", 25, None, 2),
+    ];
+
+    let prepared = MiningController::mining_prep(
+        "X1-HY12-60905F",
+        &["MINERAL_DEPOSITS".into(), "COMMON_METAL_DEPOSITS".into()],
+        &markets,
+        &mounts,
+    );
+    let start = prepared.nodes["start"];
+    println!(
+        "Mining graph: {} nodes, {} edges",
+        prepared.nodes.len(),
+        prepared.edges.len()
+    );
+
+    let options = EvaluateOptions::default();
+    let time = |name: &str, f: &dyn Fn() -> f64| {
+        let mut total = Duration::ZERO;
+        let mut x0 = 0.0;
+        for _ in 0..runs {
+            let t = Instant::now();
+            x0 = f();
+            total += t.elapsed();
+        }
+        println!(
+            "{:>10}: {:>8.2}ms per evaluation, {} cps",
+            name,
+            total.as_secs_f64() * 1000.0 / runs as f64,
+            x0
+        );
+    };
+    time("iterative", &|| {
+        evaluate_with(&prepared.graph, start, &options).x0
+    });
+    time("recursive", &|| {
+        evaluate_recursive(&prepared.graph, start, &options).x0
+    });
+}
@@ -1,7 +1,7 @@
 use dotenvy::dotenv;
 use log::*;
 
-use spacetraders_rs::agentconfig::CONFIG;
+use spacetraders_rs::agentconfig;
 use spacetraders_rs::{controller::Controller, util};
 
 #[tokio::main]
@@ -9,7 +9,8 @@ async fn main() {
     dotenv().ok();
     pretty_env_logger::init_timed();
 
-    let mut controller = Controller::new(&CONFIG).load().await;
+    let config = agentconfig::load().await;
+    let mut controller = Controller::new(&config).load().await;
 
     // refetch agent
     controller.fetch_agent().await;
@@ -17,8 +18,8 @@ async fn main() {
     controller.fetch_ships(1, 20).await;
 
     // grab our command frigate, and send it to all the marketplaces in the starting system
-    let ship_symbol = format!("{}-{}", CONFIG.callsign, 1);
-    let mut ship_controller = controller.ship_controller(&ship_symbol).await;
+    let ship_symbol = format!("{}-{}", config.callsign, 1);
+    let mut ship_controller = controller.ship_controller(&ship_symbol).await.unwrap();
     ship_controller.flight_mode("CRUISE").await;
     let ship_system = ship_controller.ship.nav.system_symbol.clone();
     let waypoints = controller
@@ -0,0 +1,25 @@
+use dotenvy::dotenv;
+use log::*;
+
+use spacetraders_rs::agentconfig;
+use spacetraders_rs::database::DatabaseClient;
+
+// Copy the ship configs from a config file into the 'ship_configs' table
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    pretty_env_logger::init();
+
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: import_config <agentconfig.toml>");
+    let mut config = agentconfig::from_file(&path).unwrap_or_else(|e| panic!("{}", e));
+    agentconfig::apply_overrides(&mut config, |key| std::env::var(key).ok());
+    agentconfig::validate(&config).unwrap_or_else(|e| panic!("{}", e));
+
+    let db_client = DatabaseClient::new();
+    for ship_config in config.ships.iter() {
+        db_client.upsert_ship_config(ship_config).await;
+    }
+    info!("Imported {} ship configs", config.ships.len());
+}
@@ -0,0 +1,65 @@
+use dotenvy::dotenv;
+use spacetraders_rs::models::System;
+use spacetraders_rs::pathfinding::{L2Graph, RouteCost, ShipProfile};
+
+///
+/// Plans routes across the charted systems, eg. a dump of GET /v2/systems with each
+/// system's waypoints and their traits
+///
+/// cargo run --release --bin route <charted_systems.json> <from> <to>...
+///
+/// Plans for the ship in ROUTE_PROFILE, as json, or else ShipProfile::default()
+///
+/// With ROUTE_CREDITS_PER_SECOND, plans the fastest, cheapest and balanced routes at that
+/// price of time, with fuel at the default price
+///
+fn main() {
+    dotenv().ok();
+    pretty_env_logger::init_timed();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!("Usage: {} <charted_systems.json> <from> <to>...", args[0]);
+        std::process::exit(1);
+    }
+    let charted_systems_json = std::fs::read_to_string(&args[1]).unwrap();
+    let charted_systems: Vec<System> = serde_json::from_str(&charted_systems_json).unwrap();
+    let graph = L2Graph::from_systems(&charted_systems);
+
+    // eg. ROUTE_PROFILE='{"fuel":400,"fuel_capacity":1200,"speed":30,"warp_drive":true,"jump_drive":false}'
+    let profile: ShipProfile = match std::env::var("ROUTE_PROFILE") {
+        Ok(json) => serde_json::from_str(&json).expect("ROUTE_PROFILE should be a ShipProfile"),
+        Err(_) => ShipProfile::default(),
+    };
+
+    let cost = std::env::var("ROUTE_CREDITS_PER_SECOND").ok().map(|cps| {
+        let cps = cps
+            .parse()
+            .expect("ROUTE_CREDITS_PER_SECOND should be a number");
+        RouteCost::new(cps)
+    });
+
+    let src = &args[2];
+    for dest in args[3..].iter() {
+        match &cost {
+            None => match graph.astar(src, dest, &profile) {
+                Some(route) => println!("{}\n", route),
+                None => println!("No route from {} to {}\n", src, dest),
+            },
+            Some(cost) => match graph.pareto(src, dest, &profile, cost) {
+                Some(routes) => {
+                    for (name, route) in [
+                        ("Fastest", &routes.fastest),
+                        ("Balanced", &routes.balanced),
+                        ("Cheapest", &routes.cheapest),
+                    ] {
+                        let credits = cost.route_fuel_credits(route);
+                        println!("{}, {:.0} credits of fuel:\n{}\n", name, credits, route);
+                    }
+                    println!("{} routes on the front\n", routes.front.len());
+                }
+                None => println!("No route from {} to {}\n", src, dest),
+            },
+        }
+    }
+}
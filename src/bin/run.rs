@@ -1,9 +1,16 @@
 use dotenvy::dotenv;
 use log::*;
-use spacetraders_rs::agentconfig::CONFIG;
-use spacetraders_rs::runtime::Runtime;
-use spacetraders_rs::shipconfig::*;
-use spacetraders_rs::{controller::Controller, scripts::mining::MiningController};
+use spacetraders_rs::agentconfig::{self, ConfigWatcher};
+use spacetraders_rs::control_api::{self, Fleet};
+use spacetraders_rs::controller::Controller;
+use spacetraders_rs::events::FleetEvent;
+use spacetraders_rs::runtime::{Runtime, RuntimeHandle};
+use spacetraders_rs::scripts::ship::{config_key, step_id, ShipExecutor};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
@@ -12,23 +19,118 @@ async fn main() {
     info!("Starting up...");
 
     // load agent (set bearer token)
-    let mut controller = Controller::new(&CONFIG).load().await;
+    let config = agentconfig::load().await;
+    let mut controller = Controller::new(&config).load().await;
 
     // refetch ships: todo load from postgres instead
     controller.fetch_ships(1, 20).await;
 
+    let watcher = ConfigWatcher::new(&config);
     let mut runtime = Runtime::new(5);
-    for ship in &CONFIG.ships {
-        if !controller.ships.contains_key(&ship.symbol) {
-            continue;
+    let mut steps = BTreeMap::new();
+    for ship in &config.ships {
+        let rx = watcher.subscribe(&ship.symbol).unwrap();
+        let id = step_id(&ship.symbol);
+        runtime
+            .add(id.clone(), Box::new(ShipExecutor::new(&controller, rx)), 50)
+            .await;
+        steps.insert(ship.symbol.clone(), id);
+    }
+    let events = controller.events.clone();
+    runtime.on_error(move |id, error| {
+        let _ = events.send(FleetEvent::StepError {
+            step: id.to_string(),
+            error,
+        });
+    });
+    let runtime = Arc::new(runtime);
+    let handle = runtime.handle();
+
+    // wake steps waiting on config changes and credits
+    for ship in &config.ships {
+        let mut rx = watcher.subscribe(&ship.symbol).unwrap();
+        let key = config_key(&ship.symbol);
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                handle.notify(&key).await;
+            }
+        });
+    }
+    let mut events = controller.events.subscribe();
+    let events_handle = handle.clone();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Some(key) = event.wake_key() {
+                        events_handle.notify(key).await;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
         }
-        if let ShipScript::Mining(mining_config) = &ship.script {
-            let mining_controller =
-                MiningController::new(&controller, &ship.symbol, &mining_config.asteroid_symbol);
-            let executor = mining_controller.setup().await;
-            runtime.add(Box::new(executor), 50).await;
+    });
+    tokio::spawn(watcher.clone().run(Duration::from_secs(30)));
+
+    let control_api_addr =
+        std::env::var("CONTROL_API_ADDR").unwrap_or_else(|_| "127.0.0.1:8081".into());
+    let listener = std::net::TcpListener::bind(&control_api_addr)
+        .unwrap_or_else(|e| panic!("Failed to bind control api to {}: {}", control_api_addr, e));
+    let fleet = Fleet {
+        par: controller.clone(),
+        runtime: handle.clone(),
+        watcher: watcher.clone(),
+        steps,
+    };
+    tokio::spawn(control_api::serve(listener, Arc::new(fleet)));
+    tokio::spawn(shutdown_signal(handle));
+
+    let report = runtime.run().await;
+    for id in report.interrupted.iter() {
+        warn!("Interrupted step {}", id);
+    }
+
+    info!("Persisting state...");
+    controller.persist().await;
+    // in-memory changes from the control api: keep them if the database is the config source
+    let overridden = watcher.overridden();
+    if std::env::var("AGENT_CONFIG").is_err() {
+        for ship_config in overridden.iter() {
+            controller.db_client.upsert_ship_config(ship_config).await;
         }
+    } else if !overridden.is_empty() {
+        warn!(
+            "Dropping control api changes to {} ships: the config file is the source",
+            overridden.len()
+        );
+    }
+    info!("Shut down, {} steps interrupted", report.interrupted.len());
+    if let Some(id) = report.escalated {
+        // let systemd / run.sh restart us
+        error!("Exiting after step {} escalated", id);
+        std::process::exit(1);
+    }
+}
+
+// systemd sends SIGTERM, and waits 90s before killing us
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(60);
+
+async fn shutdown_signal(runtime: RuntimeHandle) {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received ctrl-c"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
     }
+    info!(
+        "Shutting down: waiting up to {}s for running steps",
+        SHUTDOWN_GRACE.as_secs()
+    );
+    runtime.shutdown(SHUTDOWN_GRACE);
 
-    runtime.run().await;
+    // a second ctrl-c doesn't wait
+    tokio::signal::ctrl_c().await.unwrap();
+    warn!("Received ctrl-c again: aborting running steps");
+    runtime.shutdown(Duration::from_secs(0));
 }
@@ -1,13 +1,14 @@
 use dotenvy::dotenv;
 use log::*;
-use spacetraders_rs::{agentconfig::CONFIG, controller::Controller, util};
+use spacetraders_rs::{agentconfig, controller::Controller, util};
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     pretty_env_logger::init();
 
-    let mut controller = Controller::new(&CONFIG).load().await;
+    let config = agentconfig::load().await;
+    let mut controller = Controller::new(&config).load().await;
 
     if controller.agent.lock().unwrap().is_none() {
         info!("No agent found. Registering...");
@@ -21,14 +22,16 @@ async fn main() {
     info!("Number of ships: {}", controller.ships.len());
 
     controller.fetch_contracts(1, 20).await;
-    let contracts = controller.contracts.lock().unwrap();
-    info!("Number of contracts: {}", contracts.len());
+    let contract = {
+        let contracts = controller.contracts.lock().unwrap();
+        info!("Number of contracts: {}", contracts.len());
+        contracts[0].clone()
+    };
 
-    if !contracts[0].accepted {
-        controller.accept_contract(&contracts[0].id).await;
+    if !contract.accepted {
+        controller.accept_contract(&contract.id).await;
     }
-    let agent_guard = controller.agent.lock().unwrap();
-    let agent = agent_guard.as_ref().unwrap();
+    let agent = controller.agent.lock().unwrap().clone().unwrap();
     info!("Agent: {} ${}", agent.symbol, agent.credits);
 
     // buy ore hound
@@ -39,7 +42,6 @@ async fn main() {
             .fetch_system_waypoints(&ship_system)
             .await;
         let shipyard = waypoints.iter().find(|w| util::is_shipyard(w)).unwrap();
-        // @@ this is turbo bugged because agent_guard is still locked
         controller
             .buy_ship("SHIP_ORE_HOUND", &shipyard.symbol)
             .await;
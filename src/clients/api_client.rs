@@ -13,6 +13,12 @@ pub struct ApiClient {
     auth_token: Option<String>,
 }
 
+impl Default for ApiClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ApiClientResponse {
     pub status: hyper::StatusCode,
     pub headers: hyper::HeaderMap,
@@ -21,11 +27,11 @@ pub struct ApiClientResponse {
 
 impl ApiClient {
     pub fn new() -> Self {
-        let https = hyper_tls::HttpsConnector::new();
-        let client = hyper::Client::builder().build::<_, hyper::Body>(https);
         let base_url = std::env::var("SPACETRADERS_API_URL").unwrap_or_else(|_| {
             panic!("SPACETRADERS_API_URL must be set");
         });
+        let https = hyper_tls::HttpsConnector::new();
+        let client = hyper::Client::builder().build::<_, hyper::Body>(https);
         Self {
             inner: client,
             base_url,
@@ -33,6 +39,20 @@ impl ApiClient {
         }
     }
 
+    /// eg. a fake api in tests. Idle connections aren't timed out: with tokio's time paused,
+    /// the pool's timer would let a SimClock jump ahead while a request is in flight
+    pub fn with_base_url(base_url: &str) -> Self {
+        let https = hyper_tls::HttpsConnector::new();
+        let client = (hyper::Client::builder())
+            .pool_idle_timeout(None)
+            .build::<_, hyper::Body>(https);
+        Self {
+            inner: client,
+            base_url: base_url.into(),
+            auth_token: None,
+        }
+    }
+
     async fn request(&self, req: hyper::Request<hyper::Body>) -> ApiClientResponse {
         let res = self.inner.request(req).await.unwrap();
         let status = res.status();
@@ -114,12 +134,12 @@ impl ApiClient {
     }
 
     pub async fn buy_ship(&self, ship_type: &str, waypoint_symbol: &str) -> (Agent, Ship) {
-        let uri = format!("/v2/my/ships");
+        let uri = "/v2/my/ships";
         let payload = json!({
             "shipType": ship_type,
             "waypointSymbol": waypoint_symbol,
         });
-        let resp = self.post(&uri, payload).await;
+        let resp = self.post(uri, payload).await;
         assert!(
             resp.status.is_success(),
             "Failed to buy ship: {} {}",
@@ -427,6 +447,75 @@ impl ApiClient {
         (nav, fuel)
     }
 
+    pub async fn warp(&self, ship_symbol: &str, waypoint_symbol: &str) -> (ShipNav, ShipFuel) {
+        let resp = self
+            .post(
+                &format!("/v2/my/ships/{}/warp", ship_symbol),
+                json! ({
+                    "waypointSymbol": waypoint_symbol,
+                }),
+            )
+            .await;
+        assert!(
+            resp.status.is_success(),
+            "Failed to warp: {} {}",
+            resp.status,
+            resp.body
+        );
+        let mut body: Value = serde_json::from_str(&resp.body).unwrap();
+        let nav: ShipNav = serde_json::from_value(body["data"]["nav"].take()).unwrap_or_else(|e| {
+            error!(
+                "Deserialization error: '{}' while parsing nav\n{}",
+                e, resp.body
+            );
+            panic!();
+        });
+        let fuel: ShipFuel =
+            serde_json::from_value(body["data"]["fuel"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing fuel\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        (nav, fuel)
+    }
+
+    // through the jump gate we're orbiting, or with a jump drive
+    pub async fn jump(&self, ship_symbol: &str, system_symbol: &str) -> (ShipNav, ShipCooldown) {
+        let resp = self
+            .post(
+                &format!("/v2/my/ships/{}/jump", ship_symbol),
+                json! ({
+                    "systemSymbol": system_symbol,
+                }),
+            )
+            .await;
+        assert!(
+            resp.status.is_success(),
+            "Failed to jump: {} {}",
+            resp.status,
+            resp.body
+        );
+        let mut body: Value = serde_json::from_str(&resp.body).unwrap();
+        let nav: ShipNav = serde_json::from_value(body["data"]["nav"].take()).unwrap_or_else(|e| {
+            error!(
+                "Deserialization error: '{}' while parsing nav\n{}",
+                e, resp.body
+            );
+            panic!();
+        });
+        let cooldown: ShipCooldown = serde_json::from_value(body["data"]["cooldown"].take())
+            .unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing cooldown\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        (nav, cooldown)
+    }
+
     pub async fn refuel(&self, ship_symbol: &str, units: u32) -> (Agent, ShipFuel) {
         let resp = self
             .post(
@@ -511,6 +600,143 @@ impl ApiClient {
         (agent, cargo, transaction)
     }
 
+    pub async fn purchase(
+        &self,
+        ship_symbol: &str,
+        symbol: &str,
+        units: u32,
+    ) -> (Agent, ShipCargo, MarketTransaction) {
+        let resp = self
+            .post(
+                &format!("/v2/my/ships/{}/purchase", ship_symbol),
+                json!({
+                    "symbol": symbol,
+                    "units": units,
+                }),
+            )
+            .await;
+        assert!(
+            resp.status.is_success(),
+            "Failed to purchase: {} {}",
+            resp.status,
+            resp.body
+        );
+        let mut body: Value = serde_json::from_str(&resp.body).unwrap();
+        let agent: Agent =
+            serde_json::from_value(body["data"]["agent"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing agent\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        let cargo: ShipCargo =
+            serde_json::from_value(body["data"]["cargo"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing cargo\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        let transaction: MarketTransaction =
+            serde_json::from_value(body["data"]["transaction"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing transaction\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        (agent, cargo, transaction)
+    }
+
+    pub async fn install_mount(
+        &self,
+        ship_symbol: &str,
+        symbol: &str,
+    ) -> (
+        Agent,
+        Vec<ShipMount>,
+        ShipCargo,
+        ShipModificationTransaction,
+    ) {
+        self.modify_mounts(ship_symbol, "install", symbol).await
+    }
+
+    pub async fn remove_mount(
+        &self,
+        ship_symbol: &str,
+        symbol: &str,
+    ) -> (
+        Agent,
+        Vec<ShipMount>,
+        ShipCargo,
+        ShipModificationTransaction,
+    ) {
+        self.modify_mounts(ship_symbol, "remove", symbol).await
+    }
+
+    async fn modify_mounts(
+        &self,
+        ship_symbol: &str,
+        action: &str,
+        symbol: &str,
+    ) -> (
+        Agent,
+        Vec<ShipMount>,
+        ShipCargo,
+        ShipModificationTransaction,
+    ) {
+        let resp = self
+            .post(
+                &format!("/v2/my/ships/{}/mounts/{}", ship_symbol, action),
+                json!({
+                    "symbol": symbol,
+                }),
+            )
+            .await;
+        assert!(
+            resp.status.is_success(),
+            "Failed to {} mount: {} {}",
+            action,
+            resp.status,
+            resp.body
+        );
+        let mut body: Value = serde_json::from_str(&resp.body).unwrap();
+        let agent: Agent =
+            serde_json::from_value(body["data"]["agent"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing agent\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        let mounts: Vec<ShipMount> = serde_json::from_value(body["data"]["mounts"].take())
+            .unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing mounts\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        let cargo: ShipCargo =
+            serde_json::from_value(body["data"]["cargo"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing cargo\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        let transaction: ShipModificationTransaction =
+            serde_json::from_value(body["data"]["transaction"].take()).unwrap_or_else(|e| {
+                error!(
+                    "Deserialization error: '{}' while parsing transaction\n{}",
+                    e, resp.body
+                );
+                panic!();
+            });
+        (agent, mounts, cargo, transaction)
+    }
+
     pub async fn fetch_market(&self, system: &str, waypoint: &str) -> Market {
         let uri = format!("/v2/systems/{}/waypoints/{}/market", system, waypoint);
         let resp = self.get(&uri).await;
@@ -1,3 +1,4 @@
+use crate::agentconfig::ConfigError;
 use crate::db_models;
 use crate::diesel::ExpressionMethods;
 use crate::diesel::OptionalExtension as _;
@@ -6,6 +7,7 @@ use crate::models::Market;
 use crate::models::Survey;
 use crate::models::WrappedSurvey;
 use crate::schema::*;
+use crate::shipconfig::ShipConfig;
 use diesel::QueryDsl as _;
 use diesel_async::pooled_connection::deadpool::Pool;
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
@@ -22,13 +24,22 @@ pub struct DatabaseClient {
     pub db: Pool<AsyncPgConnection>,
 }
 
+impl Default for DatabaseClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DatabaseClient {
     pub fn new() -> Self {
-        let db_pool = {
-            let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-            let manager = AsyncDieselConnectionManager::new(database_url);
-            Pool::builder(manager).max_size(2).build().unwrap()
-        };
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        Self::with_url(&database_url)
+    }
+
+    /// Connects on first use, so a test that never touches the database needs none
+    pub fn with_url(database_url: &str) -> Self {
+        let manager = AsyncDieselConnectionManager::new(database_url);
+        let db_pool = Pool::builder(manager).max_size(2).build().unwrap();
         Self { db: db_pool }
     }
 
@@ -71,6 +82,20 @@ impl DatabaseClient {
             .unwrap();
     }
 
+    pub async fn update_agent(&self, callsign: &str, agent: &Agent) {
+        let mut conn = self.db.get().await.unwrap();
+        let agent = serde_json::to_value(agent).unwrap();
+        diesel::update(agents::table)
+            .filter(agents::symbol.eq(callsign))
+            .set((
+                agents::agent.eq(agent),
+                agents::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
     pub async fn upsert_market(&self, market: &Market) {
         let mut conn = self.db.get().await.unwrap();
         let market_val: Value = serde_json::to_value(market).unwrap();
@@ -118,7 +143,47 @@ impl DatabaseClient {
         serde_json::from_value(row.unwrap().market).unwrap()
     }
 
-    pub async fn insert_surveys(&self, surveys: &Vec<Survey>) -> Vec<WrappedSurvey> {
+    /// Errors rather than panics: the table is edited by hand while the agent runs
+    pub async fn load_ship_configs(&self) -> Result<Vec<ShipConfig>, ConfigError> {
+        let db_error = |e: &dyn std::fmt::Display| ConfigError::Database(e.to_string());
+        let mut conn = self.db.get().await.map_err(|e| db_error(&e))?;
+        let rows: Vec<(String, Value)> = ship_configs::table
+            .select((ship_configs::symbol, ship_configs::config))
+            .order(ship_configs::symbol.asc())
+            .load(&mut conn)
+            .await
+            .map_err(|e| db_error(&e))?;
+        rows.into_iter()
+            .map(|(symbol, config)| {
+                serde_json::from_value(config).map_err(|e| {
+                    ConfigError::Parse(format!("ship_configs row {}", symbol), e.to_string())
+                })
+            })
+            .collect()
+    }
+
+    pub async fn upsert_ship_config(&self, ship_config: &ShipConfig) {
+        let mut conn = self.db.get().await.unwrap();
+        let config_val: Value = serde_json::to_value(ship_config).unwrap();
+        diesel::insert_into(ship_configs::table)
+            .values((
+                ship_configs::symbol.eq(&ship_config.symbol),
+                ship_configs::config.eq(&config_val),
+                ship_configs::created_at.eq(diesel::dsl::now),
+                ship_configs::updated_at.eq(diesel::dsl::now),
+            ))
+            .on_conflict(ship_configs::symbol)
+            .do_update()
+            .set((
+                ship_configs::config.eq(&config_val),
+                ship_configs::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    pub async fn insert_surveys(&self, surveys: &[Survey]) -> Vec<WrappedSurvey> {
         let mut conn = self.db.get().await.unwrap();
         let inserts = surveys
             .iter()
@@ -0,0 +1,88 @@
+use super::api_client::ApiClient;
+use super::database::DatabaseClient;
+use crate::clock::Clock;
+use crate::controller::Controller;
+use crate::shipconfig::AgentConfig;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+type Handler = dyn Fn(&Method, &str, &Value) -> (StatusCode, Value) + Send + Sync;
+
+///
+/// A stand-in for the SpaceTraders api in tests: an http server on localhost that answers
+/// each request with `handler(method, path, body)`, and keeps a log of what it was asked
+///
+pub struct FakeApi {
+    pub url: String,
+    // "POST /v2/my/ships/X/navigate", in order
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeApi {
+    /// Call from within a tokio runtime: the server runs until the runtime ends
+    pub fn start(
+        handler: impl Fn(&Method, &str, &Value) -> (StatusCode, Value) + Send + Sync + 'static,
+    ) -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let handler: Arc<Handler> = Arc::new(handler);
+        let log = requests.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let handler = handler.clone();
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let handler = handler.clone();
+                    let log = log.clone();
+                    async move { Ok::<_, Infallible>(handle(&*handler, &log, req).await) }
+                }))
+            }
+        });
+        let server = hyper::Server::from_tcp(listener).unwrap().serve(make_svc);
+        tokio::spawn(server);
+        Self { url, requests }
+    }
+
+    pub fn client(&self) -> ApiClient {
+        ApiClient::with_base_url(&self.url)
+    }
+
+    /// A controller on the fake api, with no database behind it
+    pub fn controller(&self, clock: Arc<dyn Clock>) -> Controller {
+        let config = AgentConfig {
+            callsign: "FAKE".into(),
+            faction: "COSMIC".into(),
+            email: None,
+            ships: vec![],
+        };
+        let db_client = DatabaseClient::with_url("postgres://localhost/unused");
+        Controller::new(&config)
+            .clock(clock)
+            .build(self.client(), db_client)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle(handler: &Handler, log: &Mutex<Vec<String>>, req: Request<Body>) -> Response<Body> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    log.lock().unwrap().push(format!("{} {}", method, path));
+
+    let (status, body) = handler(&method, &path, &body);
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
@@ -1,2 +1,4 @@
 pub mod api_client;
 pub mod database;
+#[cfg(test)]
+pub mod fake_api;
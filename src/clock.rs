@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tokio::time::Instant;
+
+///
+/// Clock: where the Runtime and the controllers get the time from
+///
+/// The Runtime schedules on tokio Instants, while the api hands out DateTime<Utc> arrival and
+/// cooldown times. Both have to come from the same clock, or a simulated run would have ships
+/// arrive at a different time from when the runtime wakes them.
+///
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn utc(&self) -> DateTime<Utc>;
+    async fn sleep_until(&self, instant: Instant);
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+
+    /// How long until `time`, zero if it has passed
+    fn until(&self, time: DateTime<Utc>) -> Duration {
+        (time - self.utc()).to_std().unwrap_or_default()
+    }
+
+    fn to_utc(&self, instant: Instant) -> DateTime<Utc> {
+        let now = self.now();
+        let utc = self.utc();
+        match instant.checked_duration_since(now) {
+            Some(ahead) => utc + chrono::Duration::from_std(ahead).unwrap(),
+            None => utc - chrono::Duration::from_std(now - instant).unwrap(),
+        }
+    }
+}
+
+pub struct RealClock;
+
+#[async_trait]
+impl Clock for RealClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, instant: Instant) {
+        tokio::time::sleep_until(instant).await
+    }
+}
+
+///
+/// SimClock derives wall clock time from tokio's clock, starting at `start`.
+/// With tokio's time paused (`#[tokio::test(start_paused = true)]`), time only moves when
+/// everything is asleep, and then jumps straight to the next wake: hours of scheduled
+/// navigation and cooldowns run in milliseconds, and stay consistent with api timestamps.
+///
+pub struct SimClock {
+    start: DateTime<Utc>,
+    start_instant: Instant,
+}
+
+impl SimClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            start_instant: Instant::now(),
+        }
+    }
+
+    /// Time passed since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+}
+
+#[async_trait]
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        self.start + chrono::Duration::from_std(self.elapsed()).unwrap()
+    }
+
+    async fn sleep_until(&self, instant: Instant) {
+        tokio::time::sleep_until(instant).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_clock() {
+        let start = "2023-08-01T00:00:00Z".parse().unwrap();
+        let clock = SimClock::new(start);
+        assert_eq!(clock.utc(), start);
+
+        // six hours pass instantly
+        let arrival = start + chrono::Duration::hours(6);
+        let real = std::time::Instant::now();
+        clock.sleep(clock.until(arrival)).await;
+        assert!(real.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.utc(), arrival);
+        assert_eq!(clock.until(arrival), Duration::ZERO);
+        assert_eq!(
+            clock.to_utc(clock.now() + Duration::from_secs(60)),
+            arrival + chrono::Duration::seconds(60)
+        );
+    }
+}
@@ -0,0 +1,686 @@
+use crate::agentconfig::{self, ConfigWatcher};
+use crate::clock::Clock;
+use crate::controller::Controller;
+use crate::events::FleetEvent;
+use crate::models::Agent;
+use crate::runtime::{Histogram, ItemState, RuntimeHandle, StepId};
+use crate::shipconfig::ShipScript;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+///
+/// Control Api: a small http server for looking at and steering the running fleet
+///
+///   GET  /ships                        list ships with their state, script and next wake time
+///   GET  /ships/{symbol}
+///   POST /ships/{symbol}/pause
+///   POST /ships/{symbol}/resume
+///   POST /ships/{symbol}/reassign      body: a ShipScript, eg. {"Mining":{"asteroid_symbol":"X1-HY12-60905F"}}
+///   POST /ships/{symbol}/priority      body: {"priority": 50}
+///   POST /ships/{symbol}/stop          unschedule the ship's step once its current execution finishes
+///   GET  /agent                        agent (credits)
+///   POST /markets/refresh
+///   GET  /runtime                      concurrency, and per step state, run counts and timings
+///   POST /runtime/concurrency          body: {"concurrency": 5}
+///   GET  /events                       server-sent events stream of FleetEvents
+///
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShipStatus {
+    pub symbol: String,
+    // runtime state: queued, running, sleeping, waiting, finished, failed
+    pub state: String,
+    pub next_wake: Option<DateTime<Utc>>,
+    // None once the step is finished or stopped
+    pub priority: Option<i64>,
+    // panics since the step last returned normally
+    pub failures: u32,
+    pub last_error: Option<String>,
+    pub script: ShipScript,
+    pub paused: bool,
+    // None while the ship is locked by a running step
+    pub nav_status: Option<String>,
+    pub waypoint_symbol: Option<String>,
+}
+
+/// Runtime metrics: whether steps are waiting on a free slot (queue_wait), and how far behind
+/// their requested wake times they run (lateness)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeStatus {
+    pub concurrency: i64,
+    pub running: usize,
+    pub queued: usize,
+    pub steps: Vec<StepStatus>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepStatus {
+    pub step: StepId,
+    pub added_at: DateTime<Utc>,
+    pub priority: i64,
+    pub state: String,
+    pub next_wake: Option<DateTime<Utc>>,
+    pub runs: u64,
+    pub duration: Timings,
+    pub queue_wait: Timings,
+    pub lateness: Timings,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    pub count: u64,
+    pub mean_ms: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl From<&Histogram> for Timings {
+    fn from(h: &Histogram) -> Self {
+        Timings {
+            count: h.count,
+            mean_ms: h.mean().as_millis() as u64,
+            p50_ms: h.quantile(0.5).as_millis() as u64,
+            p95_ms: h.quantile(0.95).as_millis() as u64,
+            max_ms: h.max.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ControlError {
+    NotFound(String),
+    BadRequest(String),
+}
+
+#[async_trait]
+pub trait FleetControl: Send + Sync {
+    async fn ships(&self) -> Vec<ShipStatus>;
+    async fn agent(&self) -> Option<Agent>;
+    async fn set_paused(&self, ship_symbol: &str, paused: bool) -> Result<(), ControlError>;
+    async fn reassign(&self, ship_symbol: &str, script: ShipScript) -> Result<(), ControlError>;
+    async fn set_priority(&self, ship_symbol: &str, priority: i64) -> Result<(), ControlError>;
+    async fn stop(&self, ship_symbol: &str) -> Result<(), ControlError>;
+    async fn refresh_markets(&self) -> Vec<String>;
+    async fn runtime(&self) -> RuntimeStatus;
+    fn set_concurrency(&self, concurrency: i64) -> Result<(), ControlError>;
+    fn subscribe(&self) -> broadcast::Receiver<FleetEvent>;
+}
+
+/// The FleetControl of the `run` binary
+pub struct Fleet {
+    pub par: Controller,
+    pub runtime: RuntimeHandle,
+    pub watcher: ConfigWatcher,
+    // ship symbol -> the ship's step
+    pub steps: BTreeMap<String, StepId>,
+}
+
+impl Fleet {
+    fn step(&self, ship_symbol: &str) -> Result<&StepId, ControlError> {
+        self.steps
+            .get(ship_symbol)
+            .ok_or_else(|| ControlError::NotFound(ship_symbol.into()))
+    }
+}
+
+// state name and next wake time
+fn describe_state(clock: &dyn Clock, state: &ItemState) -> (String, Option<DateTime<Utc>>) {
+    let (name, next_wake) = match state {
+        ItemState::Queued => ("queued", None),
+        ItemState::Running => ("running", None),
+        ItemState::Sleeping(instant) => ("sleeping", Some(clock.to_utc(*instant))),
+        ItemState::Waiting(_) => ("waiting", None),
+        ItemState::Finished => ("finished", None),
+        ItemState::Failed => ("failed", None),
+    };
+    (name.into(), next_wake)
+}
+
+#[async_trait]
+impl FleetControl for Fleet {
+    async fn ships(&self) -> Vec<ShipStatus> {
+        let mut ret = vec![];
+        for (symbol, id) in self.steps.iter() {
+            let (state, next_wake) =
+                describe_state(self.par.clock.as_ref(), &self.runtime.state(id).await);
+            let failures = self.runtime.failures(id).await.unwrap_or_default();
+            let config = self.watcher.ship_config(symbol).unwrap();
+            let ship_arc = self.par.ships.get(symbol).map(|s| s.clone());
+            let (nav_status, waypoint_symbol) = match ship_arc.as_ref().map(|s| s.try_read()) {
+                Some(Ok(ship)) => (
+                    Some(ship.nav.status.clone()),
+                    Some(ship.nav.waypoint_symbol.clone()),
+                ),
+                _ => (None, None),
+            };
+            ret.push(ShipStatus {
+                symbol: symbol.clone(),
+                state,
+                next_wake,
+                priority: self.runtime.priority(id).await,
+                failures: failures.consecutive,
+                last_error: failures.last_error,
+                script: config.script,
+                paused: config.paused,
+                nav_status,
+                waypoint_symbol,
+            });
+        }
+        ret
+    }
+
+    async fn agent(&self) -> Option<Agent> {
+        self.par.agent.lock().unwrap().clone()
+    }
+
+    async fn set_paused(&self, ship_symbol: &str, paused: bool) -> Result<(), ControlError> {
+        match self.watcher.update(ship_symbol, |c| c.paused = paused) {
+            true => Ok(()),
+            false => Err(ControlError::NotFound(ship_symbol.into())),
+        }
+    }
+
+    async fn reassign(&self, ship_symbol: &str, script: ShipScript) -> Result<(), ControlError> {
+        let mut config = self
+            .watcher
+            .ship_config(ship_symbol)
+            .ok_or_else(|| ControlError::NotFound(ship_symbol.into()))?;
+        config.script = script;
+        agentconfig::validate_ship(&self.par.config.callsign, &config)
+            .map_err(|e| ControlError::BadRequest(e.to_string()))?;
+        self.watcher.update(ship_symbol, |c| *c = config);
+        Ok(())
+    }
+
+    async fn set_priority(&self, ship_symbol: &str, priority: i64) -> Result<(), ControlError> {
+        let id = self.step(ship_symbol)?;
+        match self.runtime.set_priority(id, priority).await {
+            true => Ok(()),
+            false => Err(ControlError::BadRequest(format!(
+                "{} is no longer scheduled",
+                ship_symbol
+            ))),
+        }
+    }
+
+    async fn stop(&self, ship_symbol: &str) -> Result<(), ControlError> {
+        let id = self.step(ship_symbol)?;
+        match self.runtime.remove(id).await {
+            true => Ok(()),
+            false => Err(ControlError::BadRequest(format!(
+                "{} is no longer scheduled",
+                ship_symbol
+            ))),
+        }
+    }
+
+    async fn refresh_markets(&self) -> Vec<String> {
+        self.par.refresh_markets().await
+    }
+
+    async fn runtime(&self) -> RuntimeStatus {
+        let snapshot = self.runtime.snapshot().await;
+        let steps = (snapshot.items.iter())
+            .map(|item| {
+                let (state, next_wake) = describe_state(self.par.clock.as_ref(), &item.state);
+                StepStatus {
+                    step: item.id.clone(),
+                    added_at: item.added_at,
+                    priority: item.priority,
+                    state,
+                    next_wake,
+                    runs: item.metrics.runs,
+                    duration: (&item.metrics.duration).into(),
+                    queue_wait: (&item.metrics.queue_wait).into(),
+                    lateness: (&item.metrics.lateness).into(),
+                }
+            })
+            .collect();
+        RuntimeStatus {
+            concurrency: snapshot.concurrency,
+            running: snapshot.running,
+            queued: snapshot.queued,
+            steps,
+        }
+    }
+
+    fn set_concurrency(&self, concurrency: i64) -> Result<(), ControlError> {
+        if concurrency < 1 {
+            return Err(ControlError::BadRequest(
+                "concurrency must be at least 1".into(),
+            ));
+        }
+        self.runtime.set_concurrency(concurrency);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<FleetEvent> {
+        self.par.events.subscribe()
+    }
+}
+
+pub async fn serve(listener: std::net::TcpListener, control: Arc<dyn FleetControl>) {
+    listener.set_nonblocking(true).unwrap();
+    info!(
+        "Control api listening on {}",
+        listener.local_addr().unwrap()
+    );
+    let make_svc = make_service_fn(move |_conn| {
+        let control = control.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let control = control.clone();
+                async move { Ok::<_, Infallible>(handle(control, req).await) }
+            }))
+        }
+    });
+    let server = hyper::Server::from_tcp(listener).unwrap().serve(make_svc);
+    if let Err(e) = server.await {
+        error!("Control api error: {:?}", e);
+    }
+}
+
+fn respond<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap()))
+        .unwrap()
+}
+
+fn error_response(e: ControlError) -> Response<Body> {
+    match e {
+        ControlError::NotFound(what) => respond(
+            StatusCode::NOT_FOUND,
+            &json!({ "error": format!("not found: {}", what) }),
+        ),
+        ControlError::BadRequest(msg) => respond(StatusCode::BAD_REQUEST, &json!({ "error": msg })),
+    }
+}
+
+fn event_stream(rx: broadcast::Receiver<FleetEvent>) -> Response<Body> {
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        let chunk = match rx.recv().await {
+            Ok(event) => format!(
+                "event: {}\ndata: {}\n\n",
+                event.name(),
+                serde_json::to_string(&event).unwrap()
+            ),
+            // slow consumer: let it know, and carry on from the oldest retained event
+            Err(broadcast::error::RecvError::Lagged(n)) => format!(": lagged {}\n\n", n),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok::<_, Infallible>(chunk), rx))
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(Body::wrap_stream(stream))
+        .unwrap()
+}
+
+async fn parse_body<T: serde::de::DeserializeOwned>(req: Request<Body>) -> Result<T, ControlError> {
+    let bytes = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| ControlError::BadRequest(e.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|e| ControlError::BadRequest(e.to_string()))
+}
+
+pub async fn handle(control: Arc<dyn FleetControl>, req: Request<Body>) -> Response<Body> {
+    debug!("Control api: {} {}", req.method(), req.uri().path());
+    let path = req.uri().path().to_string();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let ok = json!({ "ok": true });
+
+    let ret = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["ships"]) => Ok(respond(StatusCode::OK, &control.ships().await)),
+        (&Method::GET, ["ships", symbol]) => {
+            let ships = control.ships().await;
+            match ships.iter().find(|s| &s.symbol == symbol) {
+                Some(ship) => Ok(respond(StatusCode::OK, ship)),
+                None => Err(ControlError::NotFound(symbol.to_string())),
+            }
+        }
+        (&Method::POST, ["ships", symbol, "pause"]) => control
+            .set_paused(symbol, true)
+            .await
+            .map(|_| respond(StatusCode::OK, &ok)),
+        (&Method::POST, ["ships", symbol, "resume"]) => control
+            .set_paused(symbol, false)
+            .await
+            .map(|_| respond(StatusCode::OK, &ok)),
+        (&Method::POST, ["ships", symbol, "reassign"]) => {
+            let symbol = symbol.to_string();
+            match parse_body::<ShipScript>(req).await {
+                Ok(script) => control
+                    .reassign(&symbol, script)
+                    .await
+                    .map(|_| respond(StatusCode::OK, &ok)),
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["ships", symbol, "priority"]) => {
+            #[derive(Deserialize)]
+            struct Body {
+                priority: i64,
+            }
+            let symbol = symbol.to_string();
+            match parse_body::<Body>(req).await {
+                Ok(body) => control
+                    .set_priority(&symbol, body.priority)
+                    .await
+                    .map(|_| respond(StatusCode::OK, &ok)),
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["ships", symbol, "stop"]) => control
+            .stop(symbol)
+            .await
+            .map(|_| respond(StatusCode::OK, &ok)),
+        (&Method::GET, ["agent"]) => match control.agent().await {
+            Some(agent) => Ok(respond(StatusCode::OK, &agent)),
+            None => Err(ControlError::NotFound("agent".into())),
+        },
+        (&Method::POST, ["markets", "refresh"]) => {
+            let refreshed = control.refresh_markets().await;
+            Ok(respond(StatusCode::OK, &json!({ "refreshed": refreshed })))
+        }
+        (&Method::GET, ["runtime"]) => Ok(respond(StatusCode::OK, &control.runtime().await)),
+        (&Method::POST, ["runtime", "concurrency"]) => {
+            #[derive(Deserialize)]
+            struct Body {
+                concurrency: i64,
+            }
+            match parse_body::<Body>(req).await {
+                Ok(body) => control
+                    .set_concurrency(body.concurrency)
+                    .map(|_| respond(StatusCode::OK, &ok)),
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::GET, ["events"]) => Ok(event_stream(control.subscribe())),
+        _ => Err(ControlError::NotFound(path.clone())),
+    };
+    ret.unwrap_or_else(error_response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shipconfig::MiningConfig;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Mutex;
+
+    struct MockFleet {
+        ships: Mutex<Vec<ShipStatus>>,
+        concurrency: AtomicI64,
+        events: broadcast::Sender<FleetEvent>,
+    }
+
+    impl MockFleet {
+        fn new() -> Self {
+            let ship = |symbol: &str| ShipStatus {
+                symbol: symbol.into(),
+                state: "sleeping".into(),
+                next_wake: Some(Utc::now()),
+                priority: Some(50),
+                failures: 0,
+                last_error: None,
+                script: ShipScript::Mining(MiningConfig {
+                    asteroid_symbol: "X1-HY12-60905F".into(),
+                }),
+                paused: false,
+                nav_status: Some("IN_ORBIT".into()),
+                waypoint_symbol: Some("X1-HY12-60905F".into()),
+            };
+            Self {
+                ships: Mutex::new(vec![ship("SOLARTRADE_INC-3"), ship("SOLARTRADE_INC-4")]),
+                concurrency: AtomicI64::new(5),
+                events: broadcast::channel(16).0,
+            }
+        }
+
+        fn with_ship<T>(
+            &self,
+            symbol: &str,
+            f: impl FnOnce(&mut ShipStatus) -> T,
+        ) -> Result<T, ControlError> {
+            let mut ships = self.ships.lock().unwrap();
+            match ships.iter_mut().find(|s| s.symbol == symbol) {
+                Some(ship) => Ok(f(ship)),
+                None => Err(ControlError::NotFound(symbol.into())),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl FleetControl for MockFleet {
+        async fn ships(&self) -> Vec<ShipStatus> {
+            self.ships.lock().unwrap().clone()
+        }
+        async fn agent(&self) -> Option<Agent> {
+            Some(Agent {
+                symbol: "SOLARTRADE_INC".into(),
+                credits: 150_000,
+                ..Default::default()
+            })
+        }
+        async fn set_paused(&self, ship_symbol: &str, paused: bool) -> Result<(), ControlError> {
+            self.with_ship(ship_symbol, |s| s.paused = paused)
+        }
+        async fn reassign(
+            &self,
+            ship_symbol: &str,
+            script: ShipScript,
+        ) -> Result<(), ControlError> {
+            self.with_ship(ship_symbol, |s| s.script = script)
+        }
+        async fn set_priority(&self, ship_symbol: &str, priority: i64) -> Result<(), ControlError> {
+            self.with_ship(ship_symbol, |s| s.priority = Some(priority))
+        }
+        async fn stop(&self, ship_symbol: &str) -> Result<(), ControlError> {
+            self.with_ship(ship_symbol, |s| {
+                s.state = "finished".into();
+                s.priority = None;
+            })
+        }
+        async fn refresh_markets(&self) -> Vec<String> {
+            vec!["X1-HY12-60905F".into()]
+        }
+        async fn runtime(&self) -> RuntimeStatus {
+            RuntimeStatus {
+                concurrency: self.concurrency.load(Ordering::SeqCst),
+                running: 1,
+                queued: 0,
+                steps: vec![StepStatus {
+                    step: StepId::new("ship", "SOLARTRADE_INC-3"),
+                    added_at: Utc::now(),
+                    priority: 50,
+                    state: "running".into(),
+                    next_wake: None,
+                    runs: 12,
+                    duration: Timings::default(),
+                    queue_wait: Timings::default(),
+                    lateness: Timings::default(),
+                }],
+            }
+        }
+        fn set_concurrency(&self, concurrency: i64) -> Result<(), ControlError> {
+            self.concurrency.store(concurrency, Ordering::SeqCst);
+            Ok(())
+        }
+        fn subscribe(&self) -> broadcast::Receiver<FleetEvent> {
+            self.events.subscribe()
+        }
+    }
+
+    async fn request(
+        control: &Arc<MockFleet>,
+        method: Method,
+        path: &str,
+        body: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let resp = handle(control.clone(), req).await;
+        let status = resp.status();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ships() {
+        let control = Arc::new(MockFleet::new());
+        let (status, body) = request(&control, Method::GET, "/ships", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        let (status, body) = request(&control, Method::GET, "/ships/SOLARTRADE_INC-4", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["state"], "sleeping");
+
+        let (status, _) = request(&control, Method::GET, "/ships/SOLARTRADE_INC-5", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume_reassign() {
+        let control = Arc::new(MockFleet::new());
+        let (status, _) =
+            request(&control, Method::POST, "/ships/SOLARTRADE_INC-3/pause", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(control.ships.lock().unwrap()[0].paused);
+        let (status, _) =
+            request(&control, Method::POST, "/ships/SOLARTRADE_INC-3/resume", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!control.ships.lock().unwrap()[0].paused);
+
+        let (status, _) = request(
+            &control,
+            Method::POST,
+            "/ships/SOLARTRADE_INC-4/reassign",
+            r#""None""#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(control.ships.lock().unwrap()[1].script, ShipScript::None);
+
+        let (status, body) = request(
+            &control,
+            Method::POST,
+            "/ships/SOLARTRADE_INC-4/reassign",
+            r#""Trading""#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_priority_stop() {
+        let control = Arc::new(MockFleet::new());
+        let (status, _) = request(
+            &control,
+            Method::POST,
+            "/ships/SOLARTRADE_INC-3/priority",
+            r#"{"priority": 80}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = request(&control, Method::GET, "/ships/SOLARTRADE_INC-3", "").await;
+        assert_eq!(body["priority"], 80);
+
+        let (status, _) = request(&control, Method::POST, "/ships/SOLARTRADE_INC-4/stop", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = request(&control, Method::GET, "/ships/SOLARTRADE_INC-4", "").await;
+        assert_eq!(body["state"], "finished");
+        assert!(body["priority"].is_null());
+
+        let (status, _) = request(&control, Method::POST, "/ships/SOLARTRADE_INC-5/stop", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_agent_markets_runtime() {
+        let control = Arc::new(MockFleet::new());
+        let (_, body) = request(&control, Method::GET, "/agent", "").await;
+        assert_eq!(body["credits"], 150_000);
+
+        let (_, body) = request(&control, Method::POST, "/markets/refresh", "").await;
+        assert_eq!(body["refreshed"][0], "X1-HY12-60905F");
+
+        let (status, _) = request(
+            &control,
+            Method::POST,
+            "/runtime/concurrency",
+            r#"{"concurrency": 8}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = request(&control, Method::GET, "/runtime", "").await;
+        assert_eq!(body["concurrency"], 8);
+        assert_eq!(body["steps"][0]["runs"], 12);
+        assert_eq!(body["steps"][0]["step"]["name"], "SOLARTRADE_INC-3");
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(MockFleet::new())));
+
+        let client = hyper::Client::new();
+        let uri = format!("http://{}/ships", addr).parse().unwrap();
+        let resp = client.get(uri).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let ships: Vec<ShipStatus> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(ships.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        use hyper::body::HttpBody as _;
+
+        let control = Arc::new(MockFleet::new());
+        let req = Request::builder()
+            .uri("/events")
+            .body(Body::empty())
+            .unwrap();
+        let resp = handle(control.clone(), req).await;
+        assert_eq!(resp.headers()["Content-Type"], "text/event-stream");
+
+        control
+            .events
+            .send(FleetEvent::Extracted {
+                ship_symbol: "SOLARTRADE_INC-3".into(),
+                symbol: "IRON_ORE".into(),
+                units: 25,
+            })
+            .unwrap();
+        let mut body = resp.into_body();
+        let chunk = body.data().await.unwrap().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&chunk).unwrap(),
+            "event: extracted\ndata: {\"type\":\"Extracted\",\"ship_symbol\":\"SOLARTRADE_INC-3\",\"symbol\":\"IRON_ORE\",\"units\":25}\n\n"
+        );
+
+        // the stream ends with the fleet
+        drop(control);
+        assert!(body.data().await.is_none());
+    }
+}
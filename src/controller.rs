@@ -1,21 +1,46 @@
 use std::sync::{Arc, Mutex};
 
+use crate::clock::{Clock, RealClock};
 use crate::database::DatabaseClient;
+use crate::events::FleetEvent;
 use crate::models::*;
 use crate::{api_client::ApiClient, shipconfig::AgentConfig};
-use chrono::Utc;
 use dashmap::DashMap;
 use log::debug;
+use std::fmt;
 use std::time::Duration;
-use tokio::{
-    sync::{OwnedRwLockWriteGuard, RwLock as AsyncRwLock},
-    time::sleep,
-};
+use tokio::sync::{broadcast, OwnedRwLockWriteGuard, RwLock as AsyncRwLock};
+
+const SHIP_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq)]
+pub enum LockError {
+    UnknownShip(String),
+    Timeout(String),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::UnknownShip(symbol) => write!(f, "unknown ship {}", symbol),
+            LockError::Timeout(symbol) => write!(f, "timeout locking ship {}", symbol),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
 
 pub struct ControllerBuilder {
     config: AgentConfig,
+    clock: Arc<dyn Clock>,
 }
 impl ControllerBuilder {
+    /// eg. a SimClock in tests
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub async fn load(self) -> Controller {
         let mut api_client = ApiClient::new();
         let db_client = DatabaseClient::new();
@@ -33,25 +58,33 @@ impl ControllerBuilder {
 
         // load surveys
         let surveys_list = db_client.load_surveys(0).await;
-        let surveys: DashMap<String, Vec<Arc<WrappedSurvey>>> = DashMap::new();
+
+        let agent = agent.map(|(_token, agent)| agent);
+        // todo: load ships
+        let controller = self.build(api_client, db_client);
+        *controller.agent.lock().unwrap() = agent;
         for survey in surveys_list.into_iter() {
-            surveys
+            (controller.surveys)
                 .entry(survey.inner().symbol.clone())
                 .or_insert(vec![])
                 .push(Arc::new(survey));
         }
+        controller
+    }
 
-        let agent = agent.map(|(_token, agent)| agent);
-        // todo: load ships
+    /// A controller with nothing loaded, eg. on a fake api in tests
+    pub fn build(self, api_client: ApiClient, db_client: DatabaseClient) -> Controller {
         Controller {
             api_client,
             db_client,
             config: self.config,
-            agent: Arc::new(Mutex::new(agent)),
+            agent: Arc::new(Mutex::new(None)),
             ships: Arc::new(DashMap::new()),
             contracts: Arc::new(Mutex::new(Vec::new())),
             markets: Arc::new(DashMap::new()),
-            surveys: Arc::new(surveys),
+            surveys: Arc::new(DashMap::new()),
+            events: broadcast::channel(1024).0,
+            clock: self.clock,
         }
     }
 }
@@ -70,12 +103,37 @@ pub struct Controller {
     pub markets: Arc<DashMap<String, Arc<Market>>>,
     pub agent: Arc<Mutex<Option<Agent>>>,
     pub surveys: Arc<DashMap<String, Vec<Arc<WrappedSurvey>>>>,
+
+    // live feed of what the fleet is doing
+    pub events: broadcast::Sender<FleetEvent>,
+    pub clock: Arc<dyn Clock>,
 }
 
 impl Controller {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(config: &AgentConfig) -> ControllerBuilder {
         ControllerBuilder {
             config: config.clone(),
+            clock: Arc::new(RealClock),
+        }
+    }
+
+    pub fn publish(&self, event: FleetEvent) {
+        // an error only means nobody is listening
+        let _ = self.events.send(event);
+    }
+
+    pub fn set_agent(&self, agent: Agent) {
+        let prev = self.agent.lock().unwrap().replace(agent.clone());
+        // the first agent we hear of is no change
+        let Some(prev) = prev else {
+            return;
+        };
+        if agent.credits != prev.credits {
+            self.publish(FleetEvent::CreditsChanged {
+                credits: agent.credits,
+                delta: agent.credits - prev.credits,
+            });
         }
     }
 
@@ -98,12 +156,12 @@ impl Controller {
 
     pub async fn fetch_agent(&self) {
         let agent = self.api_client.fetch_agent().await;
-        self.agent.lock().unwrap().replace(agent);
+        self.set_agent(agent);
     }
 
     pub async fn accept_contract(&self, contract_id: &str) {
         let (agent, contract) = self.api_client.accept_contract(contract_id).await;
-        self.agent.lock().unwrap().replace(agent);
+        self.set_agent(agent);
         let mut contracts = self.contracts.lock().unwrap();
         let index = contracts
             .iter()
@@ -118,21 +176,72 @@ impl Controller {
             ship_symbol, waypoint_symbol
         );
         let (agent, ship) = self.api_client.buy_ship(ship_symbol, waypoint_symbol).await;
-        self.agent.lock().unwrap().replace(agent);
+        self.set_agent(agent);
         self.ships
             .insert(ship.symbol.clone(), Arc::new(AsyncRwLock::new(ship)));
         debug!("Bought ship {}", ship_symbol);
     }
 
-    pub async fn ship_controller(&self, ship_symbol: &str) -> ShipController {
-        let ship_arc = self.ships.get(ship_symbol).unwrap().clone();
-        let guard = tokio::time::timeout(Duration::from_secs(5), ship_arc.write_owned())
+    /// Refetch every known market that one of our ships is currently at
+    /// (prices are only visible with a ship present)
+    pub async fn refresh_markets(&self) -> Vec<String> {
+        let mut waypoints: Vec<(String, String)> = vec![];
+        let ships: Vec<_> = self.ships.iter().map(|s| s.value().clone()).collect();
+        for ship in ships {
+            let ship = ship.read().await;
+            let location = (
+                ship.nav.system_symbol.clone(),
+                ship.nav.waypoint_symbol.clone(),
+            );
+            if ship.nav.status != "IN_TRANSIT"
+                && self.markets.contains_key(&location.1)
+                && !waypoints.contains(&location)
+            {
+                waypoints.push(location);
+            }
+        }
+        let mut refreshed = vec![];
+        for (system_symbol, waypoint_symbol) in waypoints {
+            debug!("Refreshing market {}", waypoint_symbol);
+            let market = self
+                .api_client
+                .fetch_market(&system_symbol, &waypoint_symbol)
+                .await;
+            self.db_client.upsert_market(&market).await;
+            self.markets
+                .insert(market.symbol.clone(), Arc::new(market.clone()));
+            refreshed.push(market.symbol);
+        }
+        refreshed
+    }
+
+    /// Locks the ship for writing. Steps claiming Resource::Ship shouldn't ever wait here,
+    /// but anything else holding the lock (eg. a binary, or the control api) might
+    pub async fn ship_controller(&self, ship_symbol: &str) -> Result<ShipController, LockError> {
+        let ship_arc = self
+            .ships
+            .get(ship_symbol)
+            .map(|s| s.clone())
+            .ok_or_else(|| LockError::UnknownShip(ship_symbol.to_string()))?;
+        let guard = tokio::time::timeout(SHIP_LOCK_TIMEOUT, ship_arc.write_owned())
             .await
-            .expect("Timeout on mut ship lock");
-        ShipController {
+            .map_err(|_| LockError::Timeout(ship_symbol.to_string()))?;
+        Ok(ShipController {
             symbol: ship_symbol.to_string(),
             par: self.clone(),
             ship: guard,
+        })
+    }
+
+    /// Save state that is otherwise only kept in memory, eg. before shutting down.
+    /// That's only the agent: surveys and markets are written to the database as they
+    /// change, and ships aren't stored at all, but fetched from the api on startup
+    pub async fn persist(&self) {
+        let agent = self.agent.lock().unwrap().clone();
+        if let Some(agent) = agent {
+            self.db_client
+                .update_agent(&self.config.callsign, &agent)
+                .await;
         }
     }
 
@@ -146,7 +255,7 @@ impl Controller {
             .register(&callsign, &faction, email.as_deref())
             .await;
         self.db_client.save_agent(&callsign, &token, &agent).await;
-        self.agent.lock().unwrap().replace(agent);
+        self.set_agent(agent);
     }
 }
 
@@ -159,7 +268,7 @@ pub struct ShipController {
 impl ShipController {
     pub fn navigation_cooldown(&mut self) -> Option<Duration> {
         // OutOfRangeError on negative duration
-        if let Ok(duration) = (self.ship.nav.route.arrival - Utc::now()).to_std() {
+        if let Ok(duration) = (self.ship.nav.route.arrival - self.par.clock.utc()).to_std() {
             return Some(duration);
         }
         None
@@ -168,7 +277,7 @@ impl ShipController {
     pub fn reactor_cooldown(&mut self) -> Option<Duration> {
         if let Some(cooldown) = &self.ship.cooldown {
             // OutOfRangeError on negative duration
-            if let Ok(duration) = (cooldown.expiration - Utc::now()).to_std() {
+            if let Ok(duration) = (cooldown.expiration - self.par.clock.utc()).to_std() {
                 return Some(duration);
             }
         }
@@ -181,7 +290,7 @@ impl ShipController {
                 "Sleeping for navigation cooldown {}s",
                 cooldown.as_millis() as f64 / 1000.0
             );
-            sleep(cooldown).await;
+            self.par.clock.sleep(cooldown).await;
         }
     }
 
@@ -191,7 +300,7 @@ impl ShipController {
                 "Sleeping for reactor cooldown {}s",
                 cooldown.as_millis() as f64 / 1000.0
             );
-            sleep(cooldown).await;
+            self.par.clock.sleep(cooldown).await;
         }
     }
 
@@ -225,6 +334,44 @@ impl ShipController {
         let (nav, fuel) = self.par.api_client.navigate(&self.symbol, target).await;
         self.ship.nav = nav;
         self.ship.fuel = fuel;
+        self.par.publish(FleetEvent::Navigated {
+            ship_symbol: self.symbol.clone(),
+            destination: target.into(),
+            arrival: self.ship.nav.route.arrival,
+            fuel: self.ship.fuel.current,
+        });
+    }
+
+    pub async fn warp(&mut self, target: &str) {
+        self.orbit_status("IN_ORBIT").await;
+        if self.ship.nav.waypoint_symbol == target {
+            return;
+        }
+        let (nav, fuel) = self.par.api_client.warp(&self.symbol, target).await;
+        self.ship.nav = nav;
+        self.ship.fuel = fuel;
+        self.par.publish(FleetEvent::Navigated {
+            ship_symbol: self.symbol.clone(),
+            destination: target.into(),
+            arrival: self.ship.nav.route.arrival,
+            fuel: self.ship.fuel.current,
+        });
+    }
+
+    pub async fn jump(&mut self, system_symbol: &str) {
+        self.orbit_status("IN_ORBIT").await;
+        if self.ship.nav.system_symbol == system_symbol {
+            return;
+        }
+        let (nav, cooldown) = self.par.api_client.jump(&self.symbol, system_symbol).await;
+        self.ship.nav = nav;
+        self.ship.cooldown = Some(cooldown);
+        self.par.publish(FleetEvent::Navigated {
+            ship_symbol: self.symbol.clone(),
+            destination: self.ship.nav.waypoint_symbol.clone(),
+            arrival: self.ship.nav.route.arrival,
+            fuel: self.ship.fuel.current,
+        });
     }
 
     pub async fn fetch_market(&self) -> Market {
@@ -250,6 +397,11 @@ impl ShipController {
         self.ship.cooldown = Some(cooldown);
 
         let wrapped: Vec<WrappedSurvey> = self.par.db_client.insert_surveys(&surveys).await;
+        self.par.publish(FleetEvent::Surveyed {
+            ship_symbol: self.symbol.clone(),
+            waypoint_symbol: self.ship.nav.waypoint_symbol.clone(),
+            surveys: wrapped.len(),
+        });
         let mut e = self
             .par
             .surveys
@@ -272,6 +424,11 @@ impl ShipController {
                 );
                 self.ship.cooldown = Some(cooldown);
                 self.ship.cargo = cargo;
+                self.par.publish(FleetEvent::Extracted {
+                    ship_symbol: self.symbol.clone(),
+                    symbol: extraction._yield.symbol,
+                    units: extraction._yield.units,
+                });
             }
             Err(e) => {
                 debug!("Extraction failed: {:?}", e);
@@ -289,7 +446,7 @@ impl ShipController {
                 if e.code == 4000 {
                     // ship action on cooldown
                     debug!("Ship action on cooldown.. sleeping for 15s");
-                    sleep(Duration::from_secs(15)).await;
+                    self.par.clock.sleep(Duration::from_secs(15)).await;
                 }
             }
         }
@@ -297,23 +454,120 @@ impl ShipController {
 
     pub async fn refuel(&mut self) {
         let refuel_units = (self.ship.fuel.capacity - self.ship.fuel.current) / 100 * 100;
+        self.refuel_units(refuel_units).await;
+    }
+
+    /// Fill the tank exactly, as the pathfinder plans for at each refuel
+    pub async fn fill_up(&mut self) {
+        let refuel_units = self.ship.fuel.capacity - self.ship.fuel.current;
+        self.refuel_units(refuel_units).await;
+    }
+
+    async fn refuel_units(&mut self, refuel_units: u32) {
         if refuel_units == 0 {
             return;
         }
         debug!("Refuel: {} units", refuel_units);
         self.orbit_status("DOCKED").await;
-        let (_agent, fuel) = self.par.api_client.refuel(&self.symbol, refuel_units).await;
+        let (agent, fuel) = self.par.api_client.refuel(&self.symbol, refuel_units).await;
 
+        self.par.set_agent(agent);
         self.ship.fuel = fuel;
         debug!("Updated fuel: {:?}", self.ship.fuel.current);
+        self.par.publish(FleetEvent::Refueled {
+            ship_symbol: self.symbol.clone(),
+            units: refuel_units,
+            fuel: self.ship.fuel.current,
+        });
     }
 
     pub async fn sell(&mut self, symbol: &str, units: u32) {
         self.orbit_status("DOCKED").await;
-        let (_agent, cargo, t) = self.par.api_client.sell(&self.symbol, symbol, units).await;
+        let (agent, cargo, t) = self.par.api_client.sell(&self.symbol, symbol, units).await;
         debug!("Sold {}x {}: +${}", t.units, t.trade_symbol, t.total_price);
 
+        self.par.set_agent(agent);
+        self.ship.cargo = cargo;
+        self.par.publish(FleetEvent::Sold {
+            ship_symbol: self.symbol.clone(),
+            waypoint_symbol: t.waypoint_symbol,
+            symbol: t.trade_symbol,
+            units: t.units,
+            total_price: t.total_price,
+        });
+        debug!("Updated cargo: {:?}", self.ship.cargo);
+    }
+
+    pub async fn purchase(&mut self, symbol: &str, units: u32) {
+        self.orbit_status("DOCKED").await;
+        let (agent, cargo, t) = self
+            .par
+            .api_client
+            .purchase(&self.symbol, symbol, units)
+            .await;
+        debug!(
+            "Bought {}x {}: -${}",
+            t.units, t.trade_symbol, t.total_price
+        );
+
+        self.par.set_agent(agent);
         self.ship.cargo = cargo;
         debug!("Updated cargo: {:?}", self.ship.cargo);
     }
+
+    pub async fn install_mount(&mut self, symbol: &str) {
+        self.orbit_status("DOCKED").await;
+        let (agent, mounts, cargo, t) = self
+            .par
+            .api_client
+            .install_mount(&self.symbol, symbol)
+            .await;
+        debug!("Installed {}: -${}", t.trade_symbol, t.total_price);
+
+        self.par.set_agent(agent);
+        self.ship.mounts = mounts;
+        self.ship.cargo = cargo;
+    }
+
+    pub async fn remove_mount(&mut self, symbol: &str) {
+        self.orbit_status("DOCKED").await;
+        let (agent, mounts, cargo, t) =
+            self.par.api_client.remove_mount(&self.symbol, symbol).await;
+        debug!("Removed {}: -${}", t.trade_symbol, t.total_price);
+
+        self.par.set_agent(agent);
+        self.ship.mounts = mounts;
+        self.ship.cargo = cargo;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clients::fake_api::FakeApi;
+    use hyper::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_set_agent() {
+        let api = FakeApi::start(|_, _, _| (StatusCode::NOT_FOUND, json!({})));
+        let par = api.controller(Arc::new(RealClock));
+        let mut events = par.events.subscribe();
+        let agent = |credits| Agent {
+            credits,
+            ..Default::default()
+        };
+
+        // loading the agent isn't a change in credits
+        par.set_agent(agent(150_000));
+        par.set_agent(agent(150_000));
+        par.set_agent(agent(149_000));
+        match events.try_recv().unwrap() {
+            FleetEvent::CreditsChanged { credits, delta } => {
+                assert_eq!((credits, delta), (149_000, -1000));
+            }
+            event => panic!("{:?}", event),
+        }
+        assert!(events.try_recv().is_err());
+    }
 }
@@ -1,5 +1,6 @@
 use graph_builder::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Metric(pub f64, pub f64);
@@ -43,6 +44,163 @@ pub struct State<T> {
     pub successor: Option<T>,
 }
 
+/// What `validate` finds wrong with a graph
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// The nodes around the cycle, starting and ending at the same node
+    Cycle(Vec<usize>),
+    MixedEdgeTypes {
+        node: usize,
+    },
+    /// A probability node whose weights sum to zero or less
+    ZeroWeight {
+        node: usize,
+    },
+    Unreachable {
+        node: usize,
+    },
+}
+
+impl ValidationError {
+    pub fn node(&self) -> usize {
+        match self {
+            ValidationError::Cycle(nodes) => nodes[0],
+            ValidationError::MixedEdgeTypes { node }
+            | ValidationError::ZeroWeight { node }
+            | ValidationError::Unreachable { node } => *node,
+        }
+    }
+
+    /// Describes the error with `name` for node names, eg. the strings a graph was built from
+    pub fn describe(&self, name: impl Fn(usize) -> String) -> String {
+        match self {
+            ValidationError::Cycle(nodes) => {
+                let nodes: Vec<String> = nodes.iter().map(|&n| name(n)).collect();
+                format!("cycle {}", nodes.join(" -> "))
+            }
+            ValidationError::MixedEdgeTypes { node } => {
+                format!("{} has both decision and probability edges", name(*node))
+            }
+            ValidationError::ZeroWeight { node } => {
+                format!("the probability edges of {} have no weight", name(*node))
+            }
+            ValidationError::Unreachable { node } => format!("{} is unreachable", name(*node)),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.describe(|n| format!("node {}", n)))
+    }
+}
+
+///
+/// Checks what `evaluate` assumes about a graph: that it is acyclic, that each node's edges
+/// are all decisions or all probabilities, that probability weights sum to something, and
+/// that every node can be reached from one of the `roots`
+///
+pub fn validate(
+    graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
+    roots: &[usize],
+) -> Result<(), Vec<ValidationError>> {
+    let mut errors = vec![];
+    if let Some(cycle) = find_cycle(graph) {
+        errors.push(ValidationError::Cycle(cycle));
+    }
+
+    for node in 0..graph.node_count() {
+        let mut decisions = false;
+        let mut probabilities = false;
+        let mut weight_sum = 0.0;
+        for t in graph.out_neighbors_with_values(node) {
+            match t.value.edge_type {
+                EdgeType::Decision(_) => decisions = true,
+                EdgeType::Probability(w) => {
+                    probabilities = true;
+                    weight_sum += w;
+                }
+            }
+        }
+        if decisions && probabilities {
+            errors.push(ValidationError::MixedEdgeTypes { node });
+        } else if probabilities && (weight_sum <= 0.0 || weight_sum.is_nan()) {
+            errors.push(ValidationError::ZeroWeight { node });
+        }
+    }
+
+    let mut reached = vec![false; graph.node_count()];
+    let mut queue: VecDeque<usize> = roots.iter().copied().collect();
+    for &root in roots {
+        reached[root] = true;
+    }
+    while let Some(x) = queue.pop_front() {
+        for t in graph.out_neighbors_with_values(x) {
+            if !reached[t.target] {
+                reached[t.target] = true;
+                queue.push_back(t.target);
+            }
+        }
+    }
+    for (node, &reached) in reached.iter().enumerate() {
+        if !reached {
+            errors.push(ValidationError::Unreachable { node });
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+// depth first search, without recursing: the survey graphs are wide
+fn find_cycle(graph: &DirectedCsrGraph<usize, (), Edge<Metric>>) -> Option<Vec<usize>> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum Visit {
+        New,
+        OnStack,
+        Done,
+    }
+    let mut visit = vec![Visit::New; graph.node_count()];
+    for root in 0..graph.node_count() {
+        if visit[root] != Visit::New {
+            continue;
+        }
+        // (node, its successors, the next successor to look at)
+        let successors = |x: usize| -> Vec<usize> {
+            graph
+                .out_neighbors_with_values(x)
+                .map(|t| t.target)
+                .collect()
+        };
+        let mut stack = vec![(root, successors(root), 0)];
+        visit[root] = Visit::OnStack;
+        while let Some((x, next, i)) = stack.last_mut() {
+            let Some(&y) = next.get(*i) else {
+                visit[*x] = Visit::Done;
+                stack.pop();
+                continue;
+            };
+            *i += 1;
+            match visit[y] {
+                Visit::New => {
+                    visit[y] = Visit::OnStack;
+                    stack.push((y, successors(y), 0));
+                }
+                Visit::OnStack => {
+                    let from = stack.iter().position(|(n, _, _)| *n == y).unwrap();
+                    let mut cycle: Vec<usize> = stack[from..].iter().map(|(n, _, _)| *n).collect();
+                    cycle.push(y);
+                    return Some(cycle);
+                }
+                Visit::Done => {}
+            }
+        }
+    }
+    None
+}
+
 fn step(
     graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
     x: usize,
@@ -117,28 +275,884 @@ fn step(
     s
 }
 
-pub fn evaluate(
+/// How `evaluate` got to its answer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Method {
+    Newton,
+    Bisection,
+}
+
+/// Something that went wrong along the way. Newton's method gives up on the first one
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// f or df is NaN at x0, eg. a probability node whose weights sum to zero
+    NaN {
+        x0: f64,
+    },
+    Infinite {
+        x0: f64,
+    },
+    /// df is zero at x0: every optimal path from here takes no time
+    ZeroDerivative {
+        x0: f64,
+    },
+    /// bisection found no x0 where f changes sign, so there is no rate to find
+    NoBracket,
+}
+
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    // the rate: the root of f(x0) = max E[value - x0 * duration]
+    pub x0: f64,
+    pub state: HashMap<usize, State<usize>>,
+    // evaluations of the graph, over both methods
+    pub iterations: u32,
+    // |f| at the last evaluation
+    pub residual: f64,
+    pub converged: bool,
+    pub method: Method,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct EvaluateOptions {
+    pub max_iterations: u32,
+    pub tolerance: f64,
+    /// Fall back to bisection if Newton's method fails
+    pub bisection: bool,
+    /// Where Newton's method starts, eg. the rate before the graph changed
+    pub initial_x0: f64,
+}
+
+impl Default for EvaluateOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: 10,
+            tolerance: 1e-6,
+            bisection: true,
+            initial_x0: 0.0,
+        }
+    }
+}
+
+const MAX_BISECTIONS: u32 = 100;
+// relative: a node recomputed with its edges in another order hasn't moved
+const MOVED_TOLERANCE: f64 = 1e-9;
+// doublings of the search interval before giving up on finding a sign change
+const MAX_BRACKET_EXPANSIONS: u32 = 64;
+
+fn problem(f: f64, df: f64, x0: f64) -> Option<Problem> {
+    if f.is_nan() || df.is_nan() {
+        Some(Problem::NaN { x0 })
+    } else if f.is_infinite() || df.is_infinite() {
+        Some(Problem::Infinite { x0 })
+    } else {
+        None
+    }
+}
+
+///
+/// Evaluator walks the graph without recursing: nodes reachable from the start are put in
+/// topological order once, and every evaluation then fills the same dense buffers, leaves
+/// first. The graph should have passed `validate`: a cycle is cut wherever the walk finds it
+///
+pub struct Evaluator<'a> {
+    graph: &'a DirectedCsrGraph<usize, (), Edge<Metric>>,
+    // successors before predecessors, ending with the start
+    order: Vec<usize>,
+    fx: Vec<(f64, f64)>,
+    successor: Vec<Option<usize>>,
+}
+
+// nodes reachable from start, successors before predecessors, ending with the start
+fn topological_order(
     graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
     start_idx: usize,
-) -> (f64, HashMap<usize, State<usize>>) {
-    let mut x0 = 0.0;
+) -> Vec<usize> {
+    let mut order = vec![];
+    let mut visited = vec![false; graph.node_count()];
+    // (node, the next successor to look at)
+    let mut stack = vec![(start_idx, 0)];
+    visited[start_idx] = true;
+    while let Some((x, i)) = stack.last_mut() {
+        let x = *x;
+        match graph.out_neighbors_with_values(x).nth(*i) {
+            Some(t) => {
+                *i += 1;
+                if !visited[t.target] {
+                    visited[t.target] = true;
+                    stack.push((t.target, 0));
+                }
+            }
+            None => {
+                order.push(x);
+                stack.pop();
+            }
+        }
+    }
+    order
+}
+
+// f and df of a node at x0, given its out edges and the f and df of their targets:
+// the best decision, or the weighted mean over probabilities. A node without edges is a leaf
+fn combine<T>(x0: f64, edges: impl Iterator<Item = (T, Edge<Metric>, (f64, f64))>) -> State<T> {
+    let mut edges = edges.peekable();
+    let edge_type = match edges.peek() {
+        Some(t) => t.1.edge_type,
+        None => {
+            return State {
+                fx: (0.0, 0.0),
+                successor: None,
+            }
+        }
+    };
+    match edge_type {
+        EdgeType::Decision(_) => {
+            let mut max = (f64::MIN, f64::MIN);
+            let mut successor = None;
+            for (y, value, (g, dg)) in edges {
+                let edge = value.metric;
+                let repeats = match value.edge_type {
+                    EdgeType::Decision(repeats) => repeats,
+                    _ => panic!(),
+                } as f64;
+                let f = repeats * g + (edge.0 - x0 * edge.1);
+                let df = repeats * dg - edge.1;
+                if f > max.0 || f == max.0 && df > max.1 {
+                    max.0 = f;
+                    max.1 = df;
+                    successor = Some(y);
+                }
+            }
+            State { fx: max, successor }
+        }
+        EdgeType::Probability(_) => {
+            let mut sum = (0.0, 0.0);
+            let mut weight_sum = 0.0;
+            for (_, value, (g, dg)) in edges {
+                let edge = value.metric;
+                let edge_weight = match value.edge_type {
+                    EdgeType::Probability(w) => w,
+                    _ => panic!(),
+                };
+                let f = g + (edge.0 - x0 * edge.1);
+                let df = dg - edge.1;
+                sum.0 += f * edge_weight;
+                sum.1 += df * edge_weight;
+                weight_sum += edge_weight;
+            }
+            State {
+                fx: (sum.0 / weight_sum, sum.1 / weight_sum),
+                successor: None,
+            }
+        }
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(graph: &'a DirectedCsrGraph<usize, (), Edge<Metric>>, start_idx: usize) -> Self {
+        let n = graph.node_count();
+        Self {
+            graph,
+            order: topological_order(graph, start_idx),
+            fx: vec![(0.0, 0.0); n],
+            successor: vec![None; n],
+        }
+    }
+
+    /// f and df of the start node at x0
+    pub fn fx(&mut self, x0: f64) -> (f64, f64) {
+        for &x in self.order.iter() {
+            let fx = &self.fx;
+            let state = combine(
+                x0,
+                self.graph
+                    .out_neighbors_with_values(x)
+                    .map(|t| (t.target, t.value, fx[t.target])),
+            );
+            self.fx[x] = state.fx;
+            self.successor[x] = state.successor;
+        }
+        self.order.last().map(|&start| self.fx[start]).unwrap()
+    }
+
+    /// The state of every reachable node, as of the last `fx`
+    pub fn state(&self) -> HashMap<usize, State<usize>> {
+        self.order
+            .iter()
+            .map(|&x| {
+                let state = State {
+                    fx: self.fx[x],
+                    successor: self.successor[x],
+                };
+                (x, state)
+            })
+            .collect()
+    }
+}
+
+/// What a decision node maximizes, over the net reward R - x0 * T of the path it leads to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Objective {
+    /// E[R - x0 * T]: what `evaluate` does
+    Mean,
+    /// E[R - x0 * T] - lambda * stddev[R - x0 * T]: trades rate for predictability
+    MeanStddev { lambda: f64 },
+}
+
+/// First and second moments of the reward R and duration T of a path, under the chosen policy
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Moments {
+    pub reward: f64,
+    pub duration: f64,
+    // E[R^2], E[R * T], E[T^2]
+    pub reward2: f64,
+    pub reward_duration: f64,
+    pub duration2: f64,
+}
+
+impl Moments {
+    // the edge, then the path
+    fn after(&self, edge: Metric) -> Self {
+        let (r, t) = (edge.0, edge.1);
+        Self {
+            reward: r + self.reward,
+            duration: t + self.duration,
+            reward2: r * r + 2.0 * r * self.reward + self.reward2,
+            reward_duration: r * t + r * self.duration + t * self.reward + self.reward_duration,
+            duration2: t * t + 2.0 * t * self.duration + self.duration2,
+        }
+    }
+
+    // the sum of n independent runs of the path
+    fn repeat(&self, n: f64) -> Self {
+        let cov = |e2: f64, a: f64, b: f64| n * (e2 - a * b) + n * n * a * b;
+        Self {
+            reward: n * self.reward,
+            duration: n * self.duration,
+            reward2: cov(self.reward2, self.reward, self.reward),
+            reward_duration: cov(self.reward_duration, self.reward, self.duration),
+            duration2: cov(self.duration2, self.duration, self.duration),
+        }
+    }
+
+    fn scale(&self, w: f64) -> Self {
+        Self {
+            reward: w * self.reward,
+            duration: w * self.duration,
+            reward2: w * self.reward2,
+            reward_duration: w * self.reward_duration,
+            duration2: w * self.duration2,
+        }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self {
+            reward: self.reward + other.reward,
+            duration: self.duration + other.duration,
+            reward2: self.reward2 + other.reward2,
+            reward_duration: self.reward_duration + other.reward_duration,
+            duration2: self.duration2 + other.duration2,
+        }
+    }
+
+    /// E[R - x0 * T]
+    pub fn mean(&self, x0: f64) -> f64 {
+        self.reward - x0 * self.duration
+    }
+
+    /// Var[R - x0 * T]
+    pub fn variance(&self, x0: f64) -> f64 {
+        let var_r = self.reward2 - self.reward * self.reward;
+        let cov = self.reward_duration - self.reward * self.duration;
+        let var_t = self.duration2 - self.duration * self.duration;
+        // rounding can take a zero variance slightly negative
+        (var_r - 2.0 * x0 * cov + x0 * x0 * var_t).max(0.0)
+    }
+
+    pub fn stddev(&self, x0: f64) -> f64 {
+        self.variance(x0).sqrt()
+    }
+
+    /// The objective at x0, and its derivative in x0
+    pub fn score(&self, x0: f64, objective: Objective) -> (f64, f64) {
+        let (f, df) = (self.mean(x0), -self.duration);
+        match objective {
+            Objective::Mean => (f, df),
+            Objective::MeanStddev { lambda } => {
+                let sd = self.stddev(x0);
+                if sd == 0.0 {
+                    return (f, df);
+                }
+                let cov = self.reward_duration - self.reward * self.duration;
+                let var_t = self.duration2 - self.duration * self.duration;
+                let dsd = (x0 * var_t - cov) / sd;
+                (f - lambda * sd, df - lambda * dsd)
+            }
+        }
+    }
+}
+
+///
+/// RiskEvaluator is Evaluator for an Objective: alongside f, it carries the variance of the
+/// reward and the duration through probability nodes, and each decision node picks the edge
+/// with the best score. With Objective::Mean it chooses what Evaluator does
+///
+pub struct RiskEvaluator<'a> {
+    graph: &'a DirectedCsrGraph<usize, (), Edge<Metric>>,
+    objective: Objective,
+    order: Vec<usize>,
+    moments: Vec<Moments>,
+    fx: Vec<(f64, f64)>,
+    successor: Vec<Option<usize>>,
+}
+
+impl<'a> RiskEvaluator<'a> {
+    pub fn new(
+        graph: &'a DirectedCsrGraph<usize, (), Edge<Metric>>,
+        start_idx: usize,
+        objective: Objective,
+    ) -> Self {
+        let n = graph.node_count();
+        Self {
+            graph,
+            objective,
+            order: topological_order(graph, start_idx),
+            moments: vec![Moments::default(); n],
+            fx: vec![(0.0, 0.0); n],
+            successor: vec![None; n],
+        }
+    }
+
+    /// The objective of the start node at x0, and its derivative
+    pub fn fx(&mut self, x0: f64) -> (f64, f64) {
+        for &x in self.order.iter() {
+            let mut moments = Moments::default();
+            let mut successor = None;
+            let mut max = (f64::MIN, f64::MIN);
+            let mut weight_sum = None;
+            for t in self.graph.out_neighbors_with_values(x) {
+                let y = t.target;
+                match t.value.edge_type {
+                    EdgeType::Decision(repeats) => {
+                        let m = self.moments[y].repeat(repeats as f64).after(t.value.metric);
+                        let (f, df) = m.score(x0, self.objective);
+                        if f > max.0 || f == max.0 && df > max.1 {
+                            max = (f, df);
+                            moments = m;
+                            successor = Some(y);
+                        }
+                    }
+                    EdgeType::Probability(w) => {
+                        let m = self.moments[y].after(t.value.metric);
+                        moments = moments.add(&m.scale(w));
+                        *weight_sum.get_or_insert(0.0) += w;
+                    }
+                }
+            }
+            if let Some(weight_sum) = weight_sum {
+                moments = moments.scale(1.0 / weight_sum);
+            }
+            self.moments[x] = moments;
+            self.fx[x] = moments.score(x0, self.objective);
+            self.successor[x] = successor;
+        }
+        self.order.last().map(|&start| self.fx[start]).unwrap()
+    }
+
+    /// The moments of the path from `node`, as of the last `fx`
+    pub fn moments(&self, node: usize) -> Moments {
+        self.moments[node]
+    }
+
+    pub fn state(&self) -> HashMap<usize, State<usize>> {
+        self.order
+            .iter()
+            .map(|&x| {
+                let state = State {
+                    fx: self.fx[x],
+                    successor: self.successor[x],
+                };
+                (x, state)
+            })
+            .collect()
+    }
+}
+
+/// The rate x0 at which the start node's objective is zero. The state's fx is the objective
+pub fn evaluate_risk(
+    graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
+    start_idx: usize,
+    objective: Objective,
+    options: &EvaluateOptions,
+) -> Evaluation {
+    solve(
+        &mut RiskEvaluator::new(graph, start_idx, objective),
+        options,
+    )
+}
+
+// the recursive evaluation, kept to check and benchmark Evaluator against
+struct RecursiveEvaluator<'a> {
+    graph: &'a DirectedCsrGraph<usize, (), Edge<Metric>>,
+    start_idx: usize,
+    state: HashMap<usize, State<usize>>,
+}
+
+trait Fx {
+    fn fx(&mut self, x0: f64) -> (f64, f64);
+    fn state(&self) -> HashMap<usize, State<usize>>;
+}
+
+impl Fx for Evaluator<'_> {
+    fn fx(&mut self, x0: f64) -> (f64, f64) {
+        Evaluator::fx(self, x0)
+    }
+    fn state(&self) -> HashMap<usize, State<usize>> {
+        Evaluator::state(self)
+    }
+}
+
+impl Fx for RiskEvaluator<'_> {
+    fn fx(&mut self, x0: f64) -> (f64, f64) {
+        RiskEvaluator::fx(self, x0)
+    }
+    fn state(&self) -> HashMap<usize, State<usize>> {
+        RiskEvaluator::state(self)
+    }
+}
+
+impl Fx for RecursiveEvaluator<'_> {
+    fn fx(&mut self, x0: f64) -> (f64, f64) {
+        self.state = HashMap::new();
+        step(self.graph, self.start_idx, x0, &mut self.state).fx
+    }
+    fn state(&self) -> HashMap<usize, State<usize>> {
+        self.state.clone()
+    }
+}
+
+pub fn evaluate(graph: &DirectedCsrGraph<usize, (), Edge<Metric>>, start_idx: usize) -> Evaluation {
+    evaluate_with(graph, start_idx, &EvaluateOptions::default())
+}
+
+pub fn evaluate_with(
+    graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
+    start_idx: usize,
+    options: &EvaluateOptions,
+) -> Evaluation {
+    solve(&mut Evaluator::new(graph, start_idx), options)
+}
+
+/// `evaluate` by recursing from the start, with a fresh state map on every iteration.
+/// Slower: kept as a reference for Evaluator
+pub fn evaluate_recursive(
+    graph: &DirectedCsrGraph<usize, (), Edge<Metric>>,
+    start_idx: usize,
+    options: &EvaluateOptions,
+) -> Evaluation {
+    let mut evaluator = RecursiveEvaluator {
+        graph,
+        start_idx,
+        state: HashMap::new(),
+    };
+    solve(&mut evaluator, options)
+}
+
+// Newton's method, then bisection if that fails
+fn solve(evaluator: &mut impl Fx, options: &EvaluateOptions) -> Evaluation {
+    let mut x0 = options.initial_x0;
     let mut iterations = 0;
-    loop {
-        let mut state: HashMap<usize, State<usize>> = HashMap::new();
-        let (f, df) = step(graph, start_idx, x0, &mut state).fx;
+    let mut problems = vec![];
+    let mut residual = 0.0;
+    // where the state was last evaluated
+    let mut state_x0 = None;
+    while iterations < options.max_iterations {
+        let (f, df) = evaluator.fx(x0);
         iterations += 1;
+        state_x0 = Some(x0);
+        residual = f.abs();
 
         // println!("f({}) = {}, df = {}", x0, f, df);
+        if let Some(p) = problem(f, df, x0) {
+            problems.push(p);
+            break;
+        }
+        if f.abs() < options.tolerance {
+            if df != 0.0 {
+                x0 -= f / df;
+            }
+            return Evaluation {
+                x0,
+                state: evaluator.state(),
+                iterations,
+                residual,
+                converged: true,
+                method: Method::Newton,
+                problems,
+            };
+        }
+        if df == 0.0 {
+            problems.push(Problem::ZeroDerivative { x0 });
+            break;
+        }
         x0 -= f / df;
+    }
 
-        if f.abs() < 1e-6 || iterations >= 10 {
-            // println!("x0 = {}", x0);
-            return (x0, state);
+    let newton = Evaluation {
+        x0,
+        state: HashMap::new(),
+        iterations,
+        residual,
+        converged: false,
+        method: Method::Newton,
+        problems,
+    };
+    let mut evaluation = match options.bisection {
+        true => bisect(evaluator, options, newton),
+        false => newton,
+    };
+    if evaluation.method == Method::Newton {
+        // bisection didn't get anywhere: the state is Newton's
+        if let Some(x0) = state_x0 {
+            evaluator.fx(x0);
+            evaluation.state = evaluator.state();
         }
     }
+    evaluation
+}
+
+fn bisect(evaluator: &mut impl Fx, options: &EvaluateOptions, newton: Evaluation) -> Evaluation {
+    let mut iterations = newton.iterations;
+    let mut problems = newton.problems.clone();
+
+    // f is decreasing in x0: find lo with f(lo) >= 0 and hi with f(hi) <= 0
+    let (mut lo, mut hi) = (-1.0, 1.0);
+    let mut expansions = 0;
+    loop {
+        let (f_lo, df_lo) = evaluator.fx(lo);
+        let (f_hi, df_hi) = evaluator.fx(hi);
+        iterations += 2;
+        if let Some(p) = problem(f_lo, df_lo, lo).or(problem(f_hi, df_hi, hi)) {
+            problems.push(p);
+            return Evaluation {
+                iterations,
+                problems,
+                ..newton
+            };
+        }
+        if f_lo >= 0.0 && f_hi <= 0.0 {
+            break;
+        }
+        expansions += 1;
+        if expansions > MAX_BRACKET_EXPANSIONS {
+            problems.push(Problem::NoBracket);
+            return Evaluation {
+                iterations,
+                problems,
+                ..newton
+            };
+        }
+        if f_lo < 0.0 {
+            lo *= 2.0;
+        }
+        if f_hi > 0.0 {
+            hi *= 2.0;
+        }
+    }
+
+    let mut bisections = 0;
+    loop {
+        let x0 = (lo + hi) / 2.0;
+        let (f, _df) = evaluator.fx(x0);
+        iterations += 1;
+        bisections += 1;
+        let converged = f.abs() < options.tolerance || hi - lo < options.tolerance;
+        if converged || bisections >= MAX_BISECTIONS {
+            return Evaluation {
+                x0,
+                state: evaluator.state(),
+                iterations,
+                residual: f.abs(),
+                converged,
+                method: Method::Bisection,
+                problems,
+            };
+        }
+        if f > 0.0 {
+            lo = x0;
+        } else {
+            hi = x0;
+        }
+    }
+}
+
+///
+/// DecisionGraphBuilder lets scripts describe their decisions by name, eg. "cargo_IRON_ORE" to
+/// "sell_X1-A1", and indexes the nodes in the order they're first mentioned. The same builder
+/// describes a hypothetical subtree for `evaluate_subtree`
+///
+#[derive(Clone, Default)]
+pub struct DecisionGraphBuilder {
+    nodes: HashMap<String, usize>,
+    names: Vec<String>,
+    edges: Vec<(usize, usize, Edge<Metric>)>,
+}
+
+impl DecisionGraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&idx) = self.nodes.get(name) {
+            return idx;
+        }
+        let idx = self.names.len();
+        self.nodes.insert(name.into(), idx);
+        self.names.push(name.into());
+        idx
+    }
+
+    pub fn edge(&mut self, from: &str, to: &str, edge: Edge<Metric>) -> &mut Self {
+        let from = self.intern(from);
+        let to = self.intern(to);
+        self.edges.push((from, to, edge));
+        self
+    }
+
+    pub fn decision(&mut self, from: &str, to: &str, metric: Metric) -> &mut Self {
+        self.edge(from, to, Edge::new_decision(metric))
+    }
+
+    /// Taking `to`, and then everything after it, `repeats` times
+    pub fn repeated_decision(
+        &mut self,
+        from: &str,
+        to: &str,
+        metric: Metric,
+        repeats: u32,
+    ) -> &mut Self {
+        self.edge(from, to, Edge::new_repeatable_decision(metric, repeats))
+    }
+
+    pub fn probability(&mut self, from: &str, to: &str, metric: Metric, weight: f64) -> &mut Self {
+        self.edge(from, to, Edge::new_probability(metric, weight))
+    }
+
+    pub fn build(self) -> DecisionGraph {
+        let graph = GraphBuilder::new()
+            .edges_with_values(self.edges.clone())
+            .build();
+        DecisionGraph {
+            nodes: self.nodes,
+            names: self.names,
+            edges: self.edges,
+            graph,
+        }
+    }
+}
+
+/// What `DecisionGraph::reevaluate` did
+#[derive(Clone, Debug)]
+pub struct Reevaluation {
+    pub evaluation: Evaluation,
+    // node evaluations, over every pass
+    pub recomputed: usize,
+    // whether the rate held, and only the changed nodes and their ancestors were recomputed
+    pub partial: bool,
+}
+
+/// A built DecisionGraphBuilder: the graph, and the names of its nodes
+pub struct DecisionGraph {
+    pub nodes: HashMap<String, usize>,
+    pub names: Vec<String>,
+    // in the order they were added
+    pub edges: Vec<(usize, usize, Edge<Metric>)>,
+    pub graph: DirectedCsrGraph<usize, (), Edge<Metric>>,
+}
+
+impl DecisionGraph {
+    /// `validate`, with the errors described by node name
+    pub fn validate(&self, roots: &[&str]) -> Result<(), Vec<String>> {
+        let roots: Vec<usize> = roots.iter().map(|&r| self.nodes[r]).collect();
+        validate(&self.graph, &roots).map_err(|errors| {
+            errors
+                .iter()
+                .map(|e| e.describe(|n| self.names[n].clone()))
+                .collect()
+        })
+    }
+
+    pub fn evaluate(&self, start: &str, options: &EvaluateOptions) -> Evaluation {
+        evaluate_with(&self.graph, self.nodes[start], options)
+    }
+
+    /// An evaluation's state, by node name
+    pub fn named_state(&self, evaluation: &Evaluation) -> HashMap<String, State<String>> {
+        evaluation
+            .state
+            .iter()
+            .map(|(&x, state)| {
+                let state = State {
+                    fx: state.fx,
+                    successor: state.successor.map(|y| self.names[y].clone()),
+                };
+                (self.names[x].clone(), state)
+            })
+            .collect()
+    }
+
+    ///
+    /// Re-evaluates after the edges out of `changed` nodes were replaced, from the state and
+    /// rate of the graph before. At the previous rate, a node only needs recomputing if it
+    /// changed or one of its successors moved, so a change that doesn't move a node stops
+    /// there; if start's f is still within tolerance, the rate holds and that's the answer.
+    /// Otherwise the rate moved, and with it every node, so this falls back to a full
+    /// evaluation, starting from the previous rate
+    ///
+    pub fn reevaluate(
+        &self,
+        start: &str,
+        changed: &[&str],
+        previous_x0: f64,
+        previous: &HashMap<String, State<String>>,
+        options: &EvaluateOptions,
+    ) -> Reevaluation {
+        let n = self.graph.node_count();
+        let mut dirty: Vec<bool> = (0..n)
+            .map(|x| !previous.contains_key(&self.names[x]))
+            .collect();
+        for name in changed {
+            if let Some(&x) = self.nodes.get(*name) {
+                dirty[x] = true;
+            }
+        }
+
+        let start_idx = self.nodes[start];
+        let order = topological_order(&self.graph, start_idx);
+        let mut fx = vec![(0.0, 0.0); n];
+        let mut successor = vec![None; n];
+        let mut moved = vec![false; n];
+        let mut recomputed = 0;
+        for &x in order.iter() {
+            let before = previous.get(&self.names[x]);
+            let mut targets = self.graph.out_neighbors_with_values(x);
+            if !dirty[x] && !targets.any(|t| moved[t.target]) {
+                let state = before.unwrap();
+                fx[x] = state.fx;
+                successor[x] = state.successor.as_ref().map(|s| self.nodes[s]);
+                continue;
+            }
+            let state = combine(
+                previous_x0,
+                self.graph
+                    .out_neighbors_with_values(x)
+                    .map(|t| (t.target, t.value, fx[t.target])),
+            );
+            fx[x] = state.fx;
+            successor[x] = state.successor;
+            moved[x] = before.map(|b| has_moved(b.fx, state.fx)).unwrap_or(true);
+            recomputed += 1;
+        }
+
+        let (f, df) = fx[start_idx];
+        if f.abs() < options.tolerance && problem(f, df, previous_x0).is_none() {
+            let state = order
+                .iter()
+                .map(|&x| {
+                    let state = State {
+                        fx: fx[x],
+                        successor: successor[x],
+                    };
+                    (x, state)
+                })
+                .collect();
+            let x0 = if df != 0.0 {
+                previous_x0 - f / df
+            } else {
+                previous_x0
+            };
+            let evaluation = Evaluation {
+                x0,
+                state,
+                iterations: 1,
+                residual: f.abs(),
+                converged: true,
+                method: Method::Newton,
+                problems: vec![],
+            };
+            return Reevaluation {
+                evaluation,
+                recomputed,
+                partial: true,
+            };
+        }
+
+        let options = EvaluateOptions {
+            initial_x0: previous_x0,
+            ..*options
+        };
+        let evaluation = self.evaluate(start, &options);
+        Reevaluation {
+            recomputed: recomputed + order.len() * evaluation.iterations as usize,
+            evaluation,
+            partial: false,
+        }
+    }
+
+    pub fn named_edges(&self) -> Vec<(String, String, Edge<Metric>)> {
+        self.edges
+            .iter()
+            .map(|&(from, to, edge)| (self.names[from].clone(), self.names[to].clone(), edge))
+            .collect()
+    }
+}
+
+/// Evaluates a subtree that isn't part of the graph at x0, eg. a survey that wasn't sampled,
+/// from `root`. Nodes the subtree gives no edges to take their fx from `state`, so the
+/// subtree can lead back into the evaluated graph; nodes missing from both are leaves.
+/// Returns the state of every node the subtree expands
+pub fn evaluate_subtree(
+    subtree: &DecisionGraphBuilder,
+    root: &str,
+    x0: f64,
+    state: &HashMap<String, State<String>>,
+) -> HashMap<String, State<String>> {
+    let subtree = subtree.clone().build();
+    let order = topological_order(&subtree.graph, subtree.nodes[root]);
+    let mut fx = vec![(0.0, 0.0); subtree.graph.node_count()];
+    let mut result = HashMap::new();
+    for x in order {
+        let name = &subtree.names[x];
+        if subtree.graph.out_degree(x) == 0 {
+            fx[x] = state.get(name).map(|s| s.fx).unwrap_or((0.0, 0.0));
+            continue;
+        }
+        let s = combine(
+            x0,
+            subtree
+                .graph
+                .out_neighbors_with_values(x)
+                .map(|t| (t.target, t.value, fx[t.target])),
+        );
+        fx[x] = s.fx;
+        let s = State {
+            fx: s.fx,
+            successor: s.successor.map(|y| subtree.names[y].clone()),
+        };
+        result.insert(name.clone(), s);
+    }
+    result
+}
+
+fn has_moved(before: (f64, f64), after: (f64, f64)) -> bool {
+    let apart = |a: f64, b: f64| (a - b).abs() > MOVED_TOLERANCE * a.abs().max(b.abs()).max(1.);
+    apart(before.0, after.0) || apart(before.1, after.1)
 }
 
 #[cfg(test)]
+#[allow(clippy::vec_init_then_push)]
 mod test {
     use super::*;
 
@@ -153,7 +1167,9 @@ mod test {
         let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
             GraphBuilder::new().edges_with_values(edges).build();
 
-        let (rate, state) = evaluate(&graph, 0);
+        let Evaluation {
+            x0: rate, state, ..
+        } = evaluate(&graph, 0);
         assert_eq!(rate, 1.5);
         assert_eq!(state[&0].successor, Some(2));
     }
@@ -167,7 +1183,7 @@ mod test {
         let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
             GraphBuilder::new().edges_with_values(edges).build();
 
-        let (rate, _state) = evaluate(&graph, 0);
+        let rate = evaluate(&graph, 0).x0;
         assert_eq!(rate, 3.5);
     }
 
@@ -185,7 +1201,9 @@ mod test {
         let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
             GraphBuilder::new().edges_with_values(edges).build();
 
-        let (rate, state) = evaluate(&graph, 0);
+        let Evaluation {
+            x0: rate, state, ..
+        } = evaluate(&graph, 0);
         assert_eq!(rate, 0.8153846153846154);
 
         // we pick 100 per 100s, over 11 per 10s, even though the latter has a higher rate
@@ -208,7 +1226,9 @@ mod test {
         let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
             GraphBuilder::new().edges_with_values(edges).build();
 
-        let (rate, state) = evaluate(&graph, 0);
+        let Evaluation {
+            x0: rate, state, ..
+        } = evaluate(&graph, 0);
         assert_eq!(rate, 1.5);
         assert_eq!(state[&0].successor, Some(2));
     }
@@ -224,7 +1244,453 @@ mod test {
         let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
             GraphBuilder::new().edges_with_values(edges).build();
 
-        let (rate, _state) = evaluate(&graph, 0);
+        let rate = evaluate(&graph, 0).x0;
         assert_eq!(rate, 1.1111111111111112);
     }
+
+    #[test]
+    fn graph3_risk_mean() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_probability(Metric(0.0, 0.0), 1.0)));
+        edges.push((0, 2, Edge::new_probability(Metric(0.0, 0.0), 3.0)));
+        edges.push((1, 3, Edge::new_decision(Metric(11.0, 10.0))));
+        edges.push((1, 4, Edge::new_decision(Metric(100.0, 100.0))));
+        edges.push((2, 5, Edge::new_decision(Metric(2.0, 10.0))));
+        edges.push((2, 6, Edge::new_decision(Metric(21.0, 100.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        // the expectation alone: the same choices as evaluate
+        let options = EvaluateOptions::default();
+        let evaluation = evaluate_risk(&graph, 0, Objective::Mean, &options);
+        assert!((evaluation.x0 - 0.8153846153846154).abs() < 1e-12);
+        assert_eq!(evaluation.state[&1].successor, Some(4));
+        assert_eq!(evaluation.state[&2].successor, Some(5));
+    }
+
+    /// 3 credits per 2 seconds for sure, or a coin flip between 8 and 0 per 2 seconds
+    fn gamble() -> DirectedCsrGraph<usize, (), Edge<Metric>> {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(3.0, 2.0))));
+        edges.push((0, 2, Edge::new_decision(Metric(0.0, 0.0))));
+        edges.push((2, 3, Edge::new_probability(Metric(8.0, 2.0), 1.0)));
+        edges.push((2, 4, Edge::new_probability(Metric(0.0, 2.0), 1.0)));
+        GraphBuilder::new().edges_with_values(edges).build()
+    }
+
+    #[test]
+    fn graph_gamble_risk() {
+        let graph = gamble();
+        let options = EvaluateOptions::default();
+
+        // on average, the gamble pays 2 per second
+        let evaluation = evaluate_risk(&graph, 0, Objective::Mean, &options);
+        assert_eq!(evaluation.x0, 2.0);
+        assert_eq!(evaluation.state[&0].successor, Some(2));
+
+        // but with a stddev of 4 credits a go, it isn't worth it
+        let objective = Objective::MeanStddev { lambda: 1.0 };
+        let evaluation = evaluate_risk(&graph, 0, objective, &options);
+        assert!(evaluation.converged);
+        assert_eq!(evaluation.x0, 1.5);
+        assert_eq!(evaluation.state[&0].successor, Some(1));
+
+        let mut evaluator = RiskEvaluator::new(&graph, 0, objective);
+        evaluator.fx(1.5);
+        let moments = evaluator.moments(2);
+        assert_eq!(moments.mean(1.5), 1.0);
+        assert_eq!(moments.stddev(1.5), 4.0);
+        assert_eq!(evaluator.moments(0).stddev(1.5), 0.0);
+    }
+
+    #[test]
+    fn graph_prob_only_risk() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_probability(Metric(4.0, 2.0), 1.0)));
+        edges.push((0, 2, Edge::new_probability(Metric(5.0, 1.0), 2.0)));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let options = EvaluateOptions::default();
+        let mean = evaluate_risk(&graph, 0, Objective::Mean, &options);
+        assert!((mean.x0 - 3.5).abs() < 1e-12);
+
+        // no choices to make, but the risk adjusted rate is lower
+        let objective = Objective::MeanStddev { lambda: 0.5 };
+        let risky = evaluate_risk(&graph, 0, objective, &options);
+        assert!(risky.converged);
+        assert!(risky.x0 < mean.x0);
+        let mut evaluator = RiskEvaluator::new(&graph, 0, objective);
+        let (f, _df) = evaluator.fx(risky.x0);
+        assert!(f.abs() < 1e-6);
+        // repeated draws: 4 - 2x w.p. 1/3, 5 - x w.p. 2/3
+        let x = risky.x0;
+        let m = (4.0 - 2.0 * x) / 3.0 + 2.0 * (5.0 - x) / 3.0;
+        let m2 = (4.0 - 2.0 * x).powi(2) / 3.0 + 2.0 * (5.0 - x).powi(2) / 3.0;
+        assert!((evaluator.moments(0).variance(x) - (m2 - m * m)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn repeated_risk() {
+        // ten independent coin flips: the stddev grows with the square root
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_repeatable_decision(Metric(0.0, 0.0), 10)));
+        edges.push((1, 2, Edge::new_probability(Metric(8.0, 2.0), 1.0)));
+        edges.push((1, 3, Edge::new_probability(Metric(0.0, 2.0), 1.0)));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let mut evaluator = RiskEvaluator::new(&graph, 0, Objective::Mean);
+        evaluator.fx(0.0);
+        let moments = evaluator.moments(0);
+        assert_eq!(moments.mean(0.0), 40.0);
+        assert!((moments.stddev(0.0) - 4.0 * 10f64.sqrt()).abs() < 1e-9);
+        assert_eq!(moments.stddev(2.0), moments.stddev(0.0));
+    }
+
+    #[test]
+    fn graph0_bisection() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((0, 2, Edge::new_decision(Metric(3.0, 2.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        // one Newton step isn't enough
+        let options = EvaluateOptions {
+            max_iterations: 1,
+            ..Default::default()
+        };
+        let evaluation = evaluate_with(&graph, 0, &options);
+        assert!(evaluation.converged);
+        assert_eq!(evaluation.method, Method::Bisection);
+        assert!((evaluation.x0 - 1.5).abs() < 1e-6);
+        assert!(evaluation.residual < 1e-6);
+        assert_eq!(evaluation.state[&0].successor, Some(2));
+
+        let options = EvaluateOptions {
+            bisection: false,
+            ..options
+        };
+        let evaluation = evaluate_with(&graph, 0, &options);
+        assert!(!evaluation.converged);
+        assert_eq!(evaluation.method, Method::Newton);
+        assert_eq!(evaluation.iterations, 1);
+    }
+
+    #[test]
+    fn graph0_converged() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((0, 2, Edge::new_decision(Metric(3.0, 2.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let evaluation = evaluate(&graph, 0);
+        assert!(evaluation.converged);
+        assert_eq!(evaluation.method, Method::Newton);
+        assert_eq!(evaluation.iterations, 2);
+        assert_eq!(evaluation.residual, 0.0);
+        assert!(evaluation.problems.is_empty());
+    }
+
+    /// Credits for no time: the rate is unbounded
+    #[test]
+    fn degenerate_zero_duration() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(1.0, 0.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let evaluation = evaluate(&graph, 0);
+        assert!(!evaluation.converged);
+        assert_eq!(
+            evaluation.problems,
+            vec![Problem::ZeroDerivative { x0: 0.0 }, Problem::NoBracket]
+        );
+        assert!(evaluation.x0.is_finite());
+    }
+
+    /// Nothing to do, and nothing earned: a rate of zero
+    #[test]
+    fn degenerate_zero_everything() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(0.0, 0.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let evaluation = evaluate(&graph, 0);
+        assert!(evaluation.converged);
+        assert_eq!(evaluation.x0, 0.0);
+    }
+
+    #[test]
+    fn degenerate_zero_weights() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_probability(Metric(1.0, 1.0), 0.0)));
+        edges.push((0, 2, Edge::new_probability(Metric(3.0, 2.0), 0.0)));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let evaluation = evaluate(&graph, 0);
+        assert!(!evaluation.converged);
+        assert_eq!(evaluation.problems[0], Problem::NaN { x0: 0.0 });
+        assert!(matches!(evaluation.problems[1], Problem::NaN { .. }));
+    }
+
+    #[test]
+    fn degenerate_infinite() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(f64::INFINITY, 1.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let evaluation = evaluate(&graph, 0);
+        assert!(!evaluation.converged);
+        assert_eq!(evaluation.problems[0], Problem::Infinite { x0: 0.0 });
+    }
+
+    #[test]
+    fn validate_graph3() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_probability(Metric(0.0, 0.0), 1.0)));
+        edges.push((0, 2, Edge::new_probability(Metric(0.0, 0.0), 3.0)));
+        edges.push((1, 3, Edge::new_decision(Metric(11.0, 10.0))));
+        edges.push((1, 4, Edge::new_decision(Metric(100.0, 100.0))));
+        edges.push((2, 5, Edge::new_decision(Metric(2.0, 10.0))));
+        edges.push((2, 6, Edge::new_decision(Metric(21.0, 100.0))));
+        // shared leaf: a DAG, not a cycle
+        edges.push((3, 6, Edge::new_decision(Metric(0.0, 0.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        assert_eq!(validate(&graph, &[0]), Ok(()));
+        // only reachable from 0
+        assert_eq!(
+            validate(&graph, &[1]),
+            Err(vec![
+                ValidationError::Unreachable { node: 0 },
+                ValidationError::Unreachable { node: 2 },
+                ValidationError::Unreachable { node: 5 },
+            ])
+        );
+    }
+
+    #[test]
+    fn validate_cycle() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((1, 2, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((2, 3, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((2, 1, Edge::new_decision(Metric(1.0, 1.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let errors = validate(&graph, &[0]).unwrap_err();
+        assert_eq!(errors, vec![ValidationError::Cycle(vec![1, 2, 1])]);
+        assert_eq!(errors[0].to_string(), "cycle node 1 -> node 2 -> node 1");
+    }
+
+    #[test]
+    fn validate_edge_types() {
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        edges.push((0, 1, Edge::new_decision(Metric(1.0, 1.0))));
+        edges.push((0, 2, Edge::new_probability(Metric(3.0, 2.0), 1.0)));
+        edges.push((1, 3, Edge::new_probability(Metric(3.0, 2.0), 0.0)));
+        edges.push((1, 4, Edge::new_probability(Metric(3.0, 2.0), 0.0)));
+        edges.push((2, 3, Edge::new_decision(Metric(1.0, 1.0))));
+
+        let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
+            GraphBuilder::new().edges_with_values(edges).build();
+
+        let errors = validate(&graph, &[0]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::MixedEdgeTypes { node: 0 },
+                ValidationError::ZeroWeight { node: 1 },
+            ]
+        );
+        let names = ["start", "extract", "survey", "cargo", "finish"];
+        assert_eq!(
+            errors[1].describe(|n| names[n].to_string()),
+            "the probability edges of extract have no weight"
+        );
+    }
+
+    // a random layered DAG, with decision and probability layers
+    fn random_graph(layers: usize, width: usize) -> DirectedCsrGraph<usize, (), Edge<Metric>> {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut edges: Vec<(usize, usize, Edge<Metric>)> = vec![];
+        for y in 1..=width {
+            edges.push((0, y, Edge::new_decision(Metric(0.0, 0.0))));
+        }
+        for layer in 0..layers - 1 {
+            for i in 0..width {
+                let x = 1 + layer * width + i;
+                for _ in 0..3 {
+                    let y = 1 + (layer + 1) * width + rng.gen_range(0..width);
+                    let metric = Metric(rng.gen_range(0.0..100.0), rng.gen_range(1.0..60.0));
+                    edges.push((
+                        x,
+                        y,
+                        match layer % 2 {
+                            0 => Edge::new_decision(metric),
+                            _ => Edge::new_probability(metric, rng.gen_range(0.1..1.0)),
+                        },
+                    ));
+                }
+            }
+        }
+        GraphBuilder::new().edges_with_values(edges).build()
+    }
+
+    #[test]
+    fn evaluator_matches_recursive() {
+        let graph = random_graph(6, 50);
+        let options = EvaluateOptions::default();
+        let evaluation = evaluate_with(&graph, 0, &options);
+        let reference = evaluate_recursive(&graph, 0, &options);
+        assert!(evaluation.converged);
+        assert_eq!(evaluation.x0, reference.x0);
+        assert_eq!(evaluation.iterations, reference.iterations);
+        assert_eq!(evaluation.state, reference.state);
+
+        // and when falling back to bisection
+        let options = EvaluateOptions {
+            max_iterations: 1,
+            ..options
+        };
+        let evaluation = evaluate_with(&graph, 0, &options);
+        let reference = evaluate_recursive(&graph, 0, &options);
+        assert_eq!(evaluation.method, Method::Bisection);
+        assert_eq!(evaluation.x0, reference.x0);
+        assert_eq!(evaluation.state, reference.state);
+    }
+
+    #[test]
+    fn evaluator_reuse() {
+        let graph = random_graph(4, 10);
+        let mut evaluator = Evaluator::new(&graph, 0);
+        let first = evaluator.fx(0.5);
+        evaluator.fx(2.0);
+        assert_eq!(evaluator.fx(0.5), first);
+        assert_eq!(evaluator.state().len(), evaluator.order.len());
+    }
+
+    // 2 cps: 5 seconds of work for cargo that sells for 20 after another 5
+    fn named_graph() -> DecisionGraph {
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .probability("start", "work", Metric(0.0, 5.0), 1.0)
+            .decision("work", "cargo", Metric(0.0, 0.0))
+            .decision("cargo", "sell", Metric(20.0, 5.0))
+            .decision("cargo", "finish", Metric(0.0, 0.0))
+            .decision("sell", "finish", Metric(0.0, 0.0));
+        builder.build()
+    }
+
+    #[test]
+    fn builder() {
+        let g = named_graph();
+        assert_eq!(g.names, ["start", "work", "cargo", "sell", "finish"]);
+        assert_eq!(g.nodes["cargo"], 2);
+        assert_eq!(g.named_edges()[2].0, "cargo");
+        assert_eq!(g.named_edges()[2].1, "sell");
+        assert_eq!(g.validate(&["start"]), Ok(()));
+        assert_eq!(
+            g.validate(&["cargo"]),
+            Err(vec![
+                "start is unreachable".to_string(),
+                "work is unreachable".to_string()
+            ])
+        );
+
+        let evaluation = g.evaluate("start", &EvaluateOptions::default());
+        assert_eq!(evaluation.x0, 2.0);
+        let state = g.named_state(&evaluation);
+        assert_eq!(state["cargo"].successor.as_deref(), Some("sell"));
+        assert_eq!(state["cargo"].fx, (10.0, -5.0));
+    }
+
+    #[test]
+    fn subtree() {
+        let g = named_graph();
+        let evaluation = g.evaluate("start", &EvaluateOptions::default());
+        let state = g.named_state(&evaluation);
+
+        // an offer of two more cargo, each taking `duration` to get
+        let offer = |duration: f64| {
+            let mut subtree = DecisionGraphBuilder::new();
+            subtree
+                .repeated_decision("offer", "take", Metric(0.0, 0.0), 2)
+                .decision("offer", "finish", Metric(0.0, 0.0))
+                .probability("take", "cargo", Metric(0.0, duration), 1.0);
+            evaluate_subtree(&subtree, "offer", evaluation.x0, &state)
+        };
+        let quick = offer(2.0);
+        assert_eq!(quick["take"].fx, (6.0, -7.0));
+        assert_eq!(quick["offer"].fx, (12.0, -14.0));
+        assert_eq!(quick["offer"].successor.as_deref(), Some("take"));
+        // cargo and finish come from the graph, not the subtree
+        assert!(!quick.contains_key("cargo"));
+        assert_eq!(offer(8.0)["offer"].successor.as_deref(), Some("finish"));
+
+        // a node the graph doesn't have is a leaf
+        let mut subtree = DecisionGraphBuilder::new();
+        subtree.probability("offer", "unknown", Metric(4.0, 1.0), 1.0);
+        let unknown = evaluate_subtree(&subtree, "offer", evaluation.x0, &state);
+        assert_eq!(unknown["offer"].fx, (2.0, -1.0));
+    }
+
+    #[test]
+    fn reevaluate() {
+        let g = named_graph();
+        let options = EvaluateOptions::default();
+        let evaluation = g.evaluate("start", &options);
+        let state = g.named_state(&evaluation);
+
+        // a worse way to get rid of cargo: the rate holds
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .probability("start", "work", Metric(0.0, 5.0), 1.0)
+            .decision("work", "cargo", Metric(0.0, 0.0))
+            .decision("cargo", "sell", Metric(20.0, 5.0))
+            .decision("cargo", "finish", Metric(0.0, 0.0))
+            .decision("cargo", "dump", Metric(1.0, 50.0))
+            .decision("sell", "finish", Metric(0.0, 0.0));
+        let changed = builder.build();
+        let reevaluation = changed.reevaluate("start", &["cargo"], 2.0, &state, &options);
+        assert!(reevaluation.partial);
+        // the new dump and cargo, which doesn't move
+        assert_eq!(reevaluation.recomputed, 2);
+        assert_eq!(reevaluation.evaluation.x0, 2.0);
+        let new_state = changed.named_state(&reevaluation.evaluation);
+        assert_eq!(new_state["cargo"].successor.as_deref(), Some("sell"));
+
+        // a better price moves the rate
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .probability("start", "work", Metric(0.0, 5.0), 1.0)
+            .decision("work", "cargo", Metric(0.0, 0.0))
+            .decision("cargo", "sell", Metric(40.0, 5.0))
+            .decision("cargo", "finish", Metric(0.0, 0.0))
+            .decision("sell", "finish", Metric(0.0, 0.0));
+        let changed = builder.build();
+        let reevaluation = changed.reevaluate("start", &["cargo"], 2.0, &state, &options);
+        assert!(!reevaluation.partial);
+        assert_eq!(reevaluation.evaluation.x0, 4.0);
+    }
 }
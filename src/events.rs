@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Anything happening in the fleet that a dashboard or alert might care about.
+// Published on Controller::events, and streamed by the control api at GET /events
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FleetEvent {
+    Navigated {
+        ship_symbol: String,
+        destination: String,
+        arrival: DateTime<Utc>,
+        fuel: u32,
+    },
+    Extracted {
+        ship_symbol: String,
+        symbol: String,
+        units: u32,
+    },
+    Sold {
+        ship_symbol: String,
+        waypoint_symbol: String,
+        symbol: String,
+        units: u32,
+        total_price: u32,
+    },
+    Surveyed {
+        ship_symbol: String,
+        waypoint_symbol: String,
+        surveys: usize,
+    },
+    Refueled {
+        ship_symbol: String,
+        units: u32,
+        fuel: u32,
+    },
+    CreditsChanged {
+        credits: i64,
+        delta: i64,
+    },
+    StepError {
+        step: String,
+        error: String,
+    },
+}
+
+// notified on the runtime when the agent's credits change
+pub const CREDITS_KEY: &str = "credits";
+
+impl FleetEvent {
+    /// The runtime key a step can wait on to hear of the event, for those any step waits on
+    pub fn wake_key(&self) -> Option<&'static str> {
+        match self {
+            FleetEvent::CreditsChanged { .. } => Some(CREDITS_KEY),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FleetEvent::Navigated { .. } => "navigated",
+            FleetEvent::Extracted { .. } => "extracted",
+            FleetEvent::Sold { .. } => "sold",
+            FleetEvent::Surveyed { .. } => "surveyed",
+            FleetEvent::Refueled { .. } => "refueled",
+            FleetEvent::CreditsChanged { .. } => "credits_changed",
+            FleetEvent::StepError { .. } => "step_error",
+        }
+    }
+}
@@ -13,10 +13,14 @@ pub use models::schema;
 pub use models::shipconfig;
 
 pub mod agentconfig;
+pub mod clock;
+pub mod control_api;
 pub mod controller;
+pub mod events;
 pub mod runtime;
 pub mod scripts;
 
 // tools
 pub mod decision_tree;
+pub mod pathfinding;
 pub mod util;
@@ -1,4 +1,5 @@
 pub mod db_models;
+#[allow(clippy::module_inception)]
 pub mod models;
 pub mod schema;
 pub mod shipconfig;
@@ -32,7 +32,11 @@ pub struct Ship {
     pub nav: ShipNav,
     // pub crew
     // pub frame
-    // pub reactor, engine, modules
+    // pub reactor
+    #[serde(default)]
+    pub engine: ShipEngine,
+    #[serde(default)]
+    pub modules: Vec<ShipModule>,
     pub mounts: Vec<ShipMount>,
     pub cargo: ShipCargo,
     pub fuel: ShipFuel,
@@ -41,6 +45,21 @@ pub struct Ship {
     pub cooldown: Option<ShipCooldown>,
 }
 
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ShipEngine {
+    pub symbol: String,
+    // name, descr, condition, requirements
+    pub speed: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct ShipModule {
+    pub symbol: String,
+    // name, descr, requirements
+    pub capacity: Option<u32>,
+    pub range: Option<u32>,
+}
+
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct ShipMount {
     pub symbol: String,
@@ -111,15 +130,37 @@ pub struct Waypoint {
     pub symbol: String,
     #[serde(rename = "type")]
     pub _type: String,
-    #[serde(rename = "systemSymbol")]
+    // not on a system's own waypoints
+    #[serde(rename = "systemSymbol", default)]
     pub system_symbol: String,
     pub x: i32,
     pub y: i32,
     // orbitals, faction,
+    // null for uncharted waypoints
+    #[serde(default, deserialize_with = "null_as_default")]
     pub traits: Vec<Symbol>,
     // chart
 }
 
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct System {
+    pub symbol: String,
+    #[serde(rename = "type")]
+    pub _type: String,
+    pub x: i32,
+    pub y: i32,
+    pub waypoints: Vec<Waypoint>,
+    // factions
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 pub struct Market {
     pub symbol: String,
@@ -211,6 +252,16 @@ pub struct MarketTransaction {
     pub timestamp: DateTime<Utc>,
 }
 
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShipModificationTransaction {
+    pub waypoint_symbol: String,
+    pub ship_symbol: String,
+    pub trade_symbol: String,
+    pub total_price: u32,
+    pub timestamp: DateTime<Utc>,
+}
+
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Contract {
@@ -276,8 +327,17 @@ mod test {
     #[test]
     fn test_contract_deserialize() {
         let data = r#"{"data":[{"id":"clkpdxc0c3i6gs60cofa7jor6","factionSymbol":"UNITED","type":"PROCUREMENT","terms":{"deadline":"2023-08-06T11:55:37.335Z","payment":{"onAccepted":35670,"onFulfilled":214020},"deliver":[{"tradeSymbol":"COPPER_ORE","destinationSymbol":"X1-HY12-93292Z","unitsRequired":1230,"unitsFulfilled":0}]},"accepted":false,"fulfilled":false,"expiration":"2023-07-31T11:55:37.335Z","deadlineToAccept":"2023-07-31T11:55:37.335Z"}],"meta":{"total":1,"page":1,"limit":20}}"#;
-        let contracts: List<Contract> = serde_json::from_str(&data).unwrap();
+        let contracts: List<Contract> = serde_json::from_str(data).unwrap();
         assert_eq!(contracts.data.len(), 1);
         assert_eq!(contracts.data[0].id, "clkpdxc0c3i6gs60cofa7jor6");
     }
+
+    #[test]
+    fn test_system_deserialize() {
+        let data = r#"{"symbol":"X1-FT95","sectorSymbol":"X1","type":"RED_STAR","x":-4521,"y":1760,"waypoints":[{"symbol":"X1-FT95-48712C","type":"JUMP_GATE","x":-22,"y":40,"traits":null},{"symbol":"X1-FT95-56014B","type":"PLANET","x":13,"y":-9,"traits":[{"symbol":"MARKETPLACE","name":"Marketplace","description":""}]}],"factions":[]}"#;
+        let system: System = serde_json::from_str(data).unwrap();
+        assert_eq!(system.waypoints.len(), 2);
+        assert!(system.waypoints[0].traits.is_empty());
+        assert_eq!(system.waypoints[1].traits[0].symbol, "MARKETPLACE");
+    }
 }
@@ -17,6 +17,15 @@ table! {
     }
 }
 
+table! {
+    ship_configs (symbol) {
+        symbol -> Varchar,
+        config -> Json,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 table! {
     surveys (id) {
         id -> Int8,
@@ -29,4 +38,4 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(agents, markets, surveys,);
+allow_tables_to_appear_in_same_query!(agents, markets, ship_configs, surveys,);
@@ -2,9 +2,13 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AgentConfig {
+    // callsign and faction are usually provided by the environment
+    #[serde(default)]
     pub callsign: String,
+    #[serde(default)]
     pub faction: String,
     pub email: Option<String>,
+    #[serde(default)]
     pub ships: Vec<ShipConfig>,
 }
 
@@ -14,6 +18,9 @@ pub struct ShipConfig {
     pub shipyard: String,
     pub module_config: Option<ModulesConfig>,
     pub script: ShipScript,
+    // paused ships keep their script, but don't act on it
+    #[serde(default)]
+    pub paused: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
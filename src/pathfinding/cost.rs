@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use super::route::Route;
+use crate::models::Market;
+
+// what a unit of FUEL costs where no market has been seen
+const DEFAULT_FUEL_PRICE: u32 = 100;
+// a market unit of FUEL fills 100 of a ship's tank
+const FUEL_PER_UNIT: f64 = 100.;
+
+///
+/// What a route costs in credits: its duration at `credits_per_second`, plus the fuel it burns.
+/// Fuel is priced where it's bought: each refuel tops the tank up at that market's price, for
+/// what was burned since the last one, or what the tank was short at the start. Whatever is
+/// burned after the last refuel is priced at `default_fuel_price`, to be topped up later
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteCost {
+    pub credits_per_second: f64,
+    // per market unit of FUEL, by waypoint
+    pub fuel_prices: HashMap<String, u32>,
+    pub default_fuel_price: u32,
+}
+
+impl RouteCost {
+    pub fn new(credits_per_second: f64) -> Self {
+        Self {
+            credits_per_second,
+            fuel_prices: HashMap::new(),
+            default_fuel_price: DEFAULT_FUEL_PRICE,
+        }
+    }
+
+    /// Fuel prices from the markets that sell it, and their mean everywhere else
+    pub fn with_markets<'a>(self, markets: impl IntoIterator<Item = &'a Market>) -> Self {
+        let fuel_prices: HashMap<String, u32> = markets
+            .into_iter()
+            .filter_map(|market| {
+                let fuel = market.trade_goods.iter().find(|g| g.symbol == "FUEL")?;
+                Some((market.symbol.clone(), fuel.purchase_price))
+            })
+            .collect();
+        let default_fuel_price = match fuel_prices.len() {
+            0 => self.default_fuel_price,
+            n => fuel_prices.values().sum::<u32>() / n as u32,
+        };
+        Self {
+            fuel_prices,
+            default_fuel_price,
+            ..self
+        }
+    }
+
+    pub fn with_credits_per_second(&self, credits_per_second: f64) -> Self {
+        Self {
+            credits_per_second,
+            ..self.clone()
+        }
+    }
+
+    // per unit of the ship's tank
+    pub(super) fn fuel_price(&self, waypoint: &str) -> f64 {
+        let price = self.fuel_prices.get(waypoint);
+        *price.unwrap_or(&self.default_fuel_price) as f64 / FUEL_PER_UNIT
+    }
+
+    // anywhere
+    pub(super) fn min_fuel_price(&self) -> f64 {
+        let min = self.fuel_prices.values().min();
+        *min.unwrap_or(&self.default_fuel_price)
+            .min(&self.default_fuel_price) as f64
+            / FUEL_PER_UNIT
+    }
+
+    /// `fuel` bought at `to`
+    pub fn fuel_credits(&self, to: &str, fuel: i32) -> f64 {
+        fuel as f64 * self.fuel_price(to)
+    }
+
+    /// A leg's duration, and the fuel bought where it lands
+    pub fn leg_credits(&self, to: &str, duration: i32, fuel: i32) -> f64 {
+        duration as f64 * self.credits_per_second + self.fuel_credits(to, fuel)
+    }
+
+    /// What the route spends on fuel: the top-up at each refuel, and at the end
+    pub fn route_fuel_credits(&self, route: &Route) -> f64 {
+        let last = route.legs.len().saturating_sub(1);
+        (route.legs.iter().enumerate())
+            .filter(|&(i, l)| l.refuel || i == last)
+            .map(|(_, l)| self.fuel_credits(&l.to, route.fuel_capacity - l.fuel_after))
+            .sum()
+    }
+
+    /// The route's duration and fuel, in credits
+    pub fn route_credits(&self, route: &Route) -> f64 {
+        let duration = route.legs.iter().map(|l| l.duration).sum::<i32>();
+        duration as f64 * self.credits_per_second + self.route_fuel_credits(route)
+    }
+}
+
+///
+/// The routes L2Graph::pareto found between two waypoints: the fastest, the cheapest in fuel,
+/// the best at the RouteCost it was asked for, and every route no other is both faster and
+/// cheaper than, fastest first
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParetoRoutes {
+    pub fastest: Route,
+    pub cheapest: Route,
+    pub balanced: Route,
+    pub front: Vec<Route>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::MarketTradeGood;
+    use crate::pathfinding::{Edge, FlightMode, Leg};
+
+    fn market(symbol: &str, goods: &[(&str, u32)]) -> Market {
+        Market {
+            symbol: symbol.into(),
+            trade_goods: goods
+                .iter()
+                .map(|&(symbol, purchase_price)| MarketTradeGood {
+                    symbol: symbol.into(),
+                    purchase_price,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_with_markets() {
+        let markets = vec![
+            market("X1-A-M", &[("FUEL", 60), ("IRON_ORE", 40)]),
+            market("X1-B-M", &[("FUEL", 80)]),
+            // doesn't sell fuel
+            market("X1-C-M", &[("IRON_ORE", 45)]),
+        ];
+        let cost = RouteCost::new(0.5).with_markets(&markets);
+        assert_eq!(cost.fuel_prices.len(), 2);
+        assert_eq!(cost.default_fuel_price, 70);
+        assert_eq!(cost.fuel_credits("X1-A-M", 200), 120.);
+        assert_eq!(cost.fuel_credits("X1-C-M", 200), 140.);
+        assert_eq!(cost.leg_credits("X1-B-M", 100, 50), 50. + 40.);
+
+        let cost = RouteCost::new(0.5).with_markets(&[]);
+        assert_eq!(cost.default_fuel_price, DEFAULT_FUEL_PRICE);
+    }
+
+    fn leg(from: &str, to: &str, fuel: i32, fuel_after: i32, refuel: bool) -> Leg {
+        Leg {
+            from: from.into(),
+            to: to.into(),
+            edge: Edge::Nav(FlightMode::Cruise),
+            duration: 100,
+            fuel,
+            fuel_after,
+            refuel,
+        }
+    }
+
+    #[test]
+    fn test_route_fuel_credits() {
+        let markets = vec![
+            market("X1-A-M", &[("FUEL", 50)]),
+            market("X1-B-M", &[("FUEL", 150)]),
+        ];
+        let cost = RouteCost::new(0.5).with_markets(&markets);
+        let legs = vec![
+            leg("X1-A-M", "X1-A-G", 100, 400, false),
+            leg("X1-A-G", "X1-B-M", 100, 300, true),
+            leg("X1-B-M", "X1-B-1", 50, 450, false),
+        ];
+        let route = Route::new("X1-A-M", "X1-B-1", 500, 500, legs);
+        // the 200 burned before X1-B-M are bought there, and the 50 after at the mean price
+        assert_eq!(cost.route_fuel_credits(&route), 200. * 1.5 + 50. * 1.);
+        assert_eq!(cost.route_credits(&route), 300. * 0.5 + 350.);
+
+        // cheaper where the last leg refuels
+        let mut cheaper = route.clone();
+        cheaper.legs[2].to = "X1-A-M".into();
+        cheaper.legs[2].refuel = true;
+        assert_eq!(cost.route_fuel_credits(&cheaper), 200. * 1.5 + 50. * 0.5);
+
+        // a tank that starts short is filled at the first refuel
+        let mut short = route.clone();
+        short.start_fuel = 450;
+        short.legs[0].fuel_after = 350;
+        short.legs[1].fuel_after = 250;
+        assert_eq!(cost.route_fuel_credits(&short), 250. * 1.5 + 50. * 1.);
+        assert_eq!(cost.min_fuel_price(), 0.5);
+    }
+}
@@ -1,17 +1,25 @@
+use log::debug;
 use pathfinding::prelude::*;
 use std::cmp::max;
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
 use std::vec::Vec;
 
-use crate::systems::System;
+use super::cost::{ParetoRoutes, RouteCost};
+use super::profile::ShipProfile;
+use super::route::{Edge, FlightMode::*, Leg, Route};
+use crate::models::{Market, System};
+use crate::util;
 
-const START_FUEL: i32 = 1500;
-const MAX_FUEL: i32 = 1500;
-const SHIP_SPEED: i32 = 30;
+// the range of a jumpgate, and the longest warp the graph links
 const MAX_JUMPGATE: i32 = 2000;
 const MAX_WARP: i32 = 10000;
-const FUEL_SEGMENT: i32 = 150;
+const JUMP_DRIVE_RANGE: i32 = 500;
 const DRIFT_FUEL_COST: i32 = 1;
+// the credits per second L2Graph::pareto weighs routes at, on top of the one it's asked for
+const PARETO_CREDITS_PER_SECOND: [f64; 5] = [0.01, 0.1, 1., 10., 100.];
+// search costs are whole millicredits
+const MILLICREDITS: f64 = 1000.;
 
 #[derive(PartialEq)]
 struct SystemPoint(i32, i32);
@@ -24,20 +32,16 @@ enum Node {
     System(SystemPoint),
 }
 use Node::*;
-#[derive(Clone, Debug)]
-enum FlightMode {
-    CRUISE,
-    BURN,
-    DRIFT,
-}
-use FlightMode::*;
-#[derive(Clone, Debug)]
-enum Edge {
-    Jumpgate,
-    Warp(FlightMode),
-    Nav(FlightMode),
-}
 
+impl Node {
+    fn system_point(&self) -> (i32, i32) {
+        match self {
+            JumpgateWaypoint(SystemPoint(x, y), _) => (*x, *y),
+            MarketWaypoint(SystemPoint(x, y), _) => (*x, *y),
+            System(SystemPoint(x, y)) => (*x, *y),
+        }
+    }
+}
 
 fn dist(a: (i32, i32), b: (i32, i32)) -> i32 {
     ((((a.0 - b.0) as i64).pow(2) + ((a.1 - b.1) as i64).pow(2)) as f64)
@@ -45,173 +49,399 @@ fn dist(a: (i32, i32), b: (i32, i32)) -> i32 {
         .round() as i32
 }
 
+// how two nodes are connected, whatever the ship
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Link {
+    Jumpgate,
+    // between systems
+    Warp,
+    // within a system
+    Nav,
+}
+
+// (target, link, distance)
+type Adjacency = (usize, Link, i32);
+
+// the ways `profile` can fly a link, as (edge, duration, fuel)
+fn flights(
+    profile: &ShipProfile,
+    link: Link,
+    distance: i32,
+) -> impl Iterator<Item = (Edge, i32, i32)> {
+    let speed = profile.speed as f64;
+    let fuel = |fuel: i32| match profile.burns_fuel() {
+        true => fuel,
+        false => 0,
+    };
+    let flights = match link {
+        Link::Jumpgate => {
+            let duration = max(60, ((distance as f64) / 10f64).round() as i32);
+            [Some((Edge::Jumpgate, duration, 0)), None, None]
+        }
+        Link::Nav => {
+            let duration = |multiplier: f64| {
+                let effective_speed = speed * multiplier / 15.;
+                15 + (distance as f64 / effective_speed).round() as i32
+            };
+            [
+                Some((Edge::Nav(Cruise), duration(1.), fuel(distance))),
+                Some((Edge::Nav(Burn), duration(2.), fuel(distance * 2))),
+                Some((Edge::Nav(Drift), duration(0.1), fuel(DRIFT_FUEL_COST))),
+            ]
+        }
+        Link::Warp if profile.warp_drive => {
+            let duration = |multiplier: f64| {
+                let effective_speed = speed * multiplier / 20.;
+                15 + (distance as f64 / effective_speed).round() as i32
+            };
+            [
+                Some((Edge::Warp(Burn), duration(2.), fuel(distance * 2))),
+                Some((Edge::Warp(Cruise), duration(1.), fuel(distance))),
+                Some((Edge::Warp(Drift), duration(0.1), fuel(DRIFT_FUEL_COST))),
+            ]
+        }
+        Link::Warp if profile.jump_drive && distance <= JUMP_DRIVE_RANGE => {
+            let duration = max(60, ((distance as f64) / 10f64).round() as i32);
+            [Some((Edge::JumpDrive, duration, 0)), None, None]
+        }
+        Link::Warp => [None, None, None],
+    };
+    flights.into_iter().flatten()
+}
+
+///
+/// L2Graph is the universe at the granularity of routes between systems: every jumpgate and
+/// market waypoint, and a single node for each system with neither, which can still be warped
+/// through. Markets are where a ship refuels, but for those `with_markets` saw without FUEL.
+/// A route can also start at a waypoint that isn't a node, eg. an asteroid field, with a nav
+/// to the nearest node in its system. The graph only holds distances, so one graph serves
+/// every ship: what each ship can fly comes from its ShipProfile, at query time
+///
 pub struct L2Graph {
     l2_nodes: Vec<Node>,
     l2_nodes_name: Vec<String>,
-    l2_adj: Vec<Vec<(usize, Edge, i32, i32, bool)>>,
+    l2_adj: Vec<Vec<Adjacency>>,
+    // by node
+    l2_refuel: Vec<bool>,
+    // waypoints that aren't nodes
+    off_graph: HashMap<String, (SystemPoint, WaypointPoint)>,
 }
 
 impl L2Graph {
-    pub fn from_systems(charted_systems: &Vec<System>) -> Self {
+    pub fn from_systems(charted_systems: &[System]) -> Self {
         let (nodes, nodes_name, adj) = build_l2(charted_systems);
+        let refuel = (nodes.iter())
+            .map(|n| matches!(n, MarketWaypoint(_, _)))
+            .collect();
+        let names: HashSet<&String> = nodes_name.iter().collect();
+        let off_graph = (charted_systems.iter())
+            .flat_map(|s| s.waypoints.iter().map(move |w| (s, w)))
+            .filter(|(_, w)| !names.contains(&w.symbol))
+            .map(|(s, w)| {
+                let points = (SystemPoint(s.x, s.y), WaypointPoint(w.x, w.y));
+                (w.symbol.clone(), points)
+            })
+            .collect();
         L2Graph {
             l2_nodes: nodes,
             l2_nodes_name: nodes_name,
             l2_adj: adj,
+            l2_refuel: refuel,
+            off_graph,
         }
     }
-    
-    pub fn dijkstra(&self, src: &str) {
-        let src = self.l2_nodes_name.iter().position(|x| x == src).unwrap();
-        let start = Instant::now();
-        let result = dijkstra_all::<(usize, i32), i32, _, _>(
-            &(src, MAX_FUEL),
-            |&n| {
-                let node_idx = n.0;
-                let node_fuel = n.1;
-                self.l2_adj[node_idx]
-                    .iter()
-                    .filter_map(move |&(e, _, w, f, dest_is_refuel)| {
-                        if n.1 - f >= 0 {
-                            if dest_is_refuel {
-                                let f1 = MAX_FUEL;
-                                Some(((e, f1), w))
-                            } else {
-                                let f1 = (node_fuel - f) / FUEL_SEGMENT * FUEL_SEGMENT;
-                                Some(((e, f1), w))
-                            }
+
+    /// Only refuel at the markets that sell FUEL, of those we've seen
+    pub fn with_markets<'a>(mut self, markets: impl IntoIterator<Item = &'a Market>) -> Self {
+        for market in markets {
+            let sells_fuel = market.trade_goods.iter().any(|g| g.symbol == "FUEL");
+            if let Some(node) = self.position(&market.symbol) {
+                self.l2_refuel[node] &= sells_fuel;
+            }
+        }
+        self
+    }
+
+    fn position(&self, symbol: &str) -> Option<usize> {
+        self.l2_nodes_name.iter().position(|x| x == symbol)
+    }
+
+    // every leg out of n, as (next state, edge, duration, fuel), where a state is (node, fuel)
+    fn transitions<'a>(
+        &'a self,
+        profile: &'a ShipProfile,
+        n: (usize, i32),
+    ) -> impl Iterator<Item = ((usize, i32), Edge, i32, i32)> + 'a {
+        let (node_idx, node_fuel) = n;
+        let segment = profile.fuel_segment();
+        self.l2_adj[node_idx]
+            .iter()
+            .flat_map(move |&(e, link, distance)| {
+                flights(profile, link, distance).filter_map(move |(edge, w, f)| {
+                    if node_fuel - f >= 0 {
+                        if self.l2_refuel[e] {
+                            let f1 = profile.fuel_capacity;
+                            Some(((e, f1), edge, w, f))
                         } else {
-                            None
+                            let f1 = (node_fuel - f) / segment * segment;
+                            Some(((e, f1), edge, w, f))
                         }
-                    })
-            },
+                    } else {
+                        None
+                    }
+                })
+            })
+    }
+
+    /// The shortest duration to every node reachable from src
+    pub fn dijkstra(&self, src: &str, profile: &ShipProfile) -> HashMap<String, i32> {
+        let Some(src) = self.position(src) else {
+            return HashMap::new();
+        };
+        let start = Instant::now();
+        let result = dijkstra_all::<(usize, i32), i32, _, _>(&(src, profile.fuel), |&n| {
+            self.transitions(profile, n).map(|(n1, _, w, _)| (n1, w))
+        });
+        debug!(
+            "Dijkstra from {}: {:?}",
+            self.l2_nodes_name[src],
+            start.elapsed()
         );
-        let duration = start.elapsed();
-        println!("Time elapsed: {:?}", duration);
+
+        let mut durations = HashMap::new();
+        durations.insert(self.l2_nodes_name[src].clone(), 0);
+        for (&(node_idx, _), &(_, duration)) in result.iter() {
+            let best = durations
+                .entry(self.l2_nodes_name[node_idx].clone())
+                .or_insert(duration);
+            *best = (*best).min(duration);
+        }
+        durations
     }
 
-    pub fn astar(&self, src: &str, dest: &str) -> Option<i32> {    
-        let src = self.l2_nodes_name.iter().position(|x| x == src).unwrap();
-        let dest = self.l2_nodes_name.iter().position(|x| x == dest).unwrap();
-    
-        let dist_to_dest = self.l2_nodes
-            .iter()
-            .map(|x| {
-                let sys_i = match x {
-                    JumpgateWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                    MarketWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                    System(SystemPoint(x, y)) => SystemPoint(*x, *y),
-                };
-                let sys_j = match &self.l2_nodes[dest] {
-                    JumpgateWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                    MarketWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                    System(SystemPoint(x, y)) => SystemPoint(*x, *y),
-                };
-                dist((sys_i.0, sys_i.1), (sys_j.0, sys_j.1))
+    /// The fastest route from src to dest, under the fuel constraint
+    pub fn astar(&self, src: &str, dest: &str, profile: &ShipProfile) -> Option<Route> {
+        self.search(src, dest, profile, None)
+    }
+
+    /// The route from src to dest that costs the fewest credits, its time and fuel together
+    pub fn astar_cost(
+        &self,
+        src: &str,
+        dest: &str,
+        profile: &ShipProfile,
+        cost: &RouteCost,
+    ) -> Option<Route> {
+        self.search(src, dest, profile, Some(cost))
+    }
+
+    /// The fastest, the cheapest, and the best route at `cost`, and the trade-offs between them
+    pub fn pareto(
+        &self,
+        src: &str,
+        dest: &str,
+        profile: &ShipProfile,
+        cost: &RouteCost,
+    ) -> Option<ParetoRoutes> {
+        let fastest = self.astar(src, dest, profile)?;
+        let cheapest = self.astar_cost(src, dest, profile, &cost.with_credits_per_second(0.))?;
+        let balanced = self.astar_cost(src, dest, profile, cost)?;
+
+        let mut routes = vec![fastest.clone(), cheapest.clone(), balanced.clone()];
+        for credits_per_second in PARETO_CREDITS_PER_SECOND {
+            let cost = cost.with_credits_per_second(credits_per_second);
+            routes.extend(self.astar_cost(src, dest, profile, &cost));
+        }
+        let points = (routes.iter())
+            .map(|r| (r.duration, cost.route_fuel_credits(r)))
+            .collect::<Vec<_>>();
+        let dominated = |&(duration, credits): &(i32, f64)| {
+            points
+                .iter()
+                .any(|&(d, c)| d <= duration && c <= credits && (d < duration || c < credits))
+        };
+        let mut front = (routes.into_iter().zip(points.iter()))
+            .filter(|(_, point)| !dominated(point))
+            .collect::<Vec<_>>();
+        front.sort_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)));
+        front.dedup_by(|(_, a), (_, b)| a == b);
+
+        Some(ParetoRoutes {
+            fastest,
+            cheapest,
+            balanced,
+            front: front.into_iter().map(|(route, _)| route).collect(),
+        })
+    }
+
+    fn refuels(&self, node: usize, profile: &ShipProfile) -> bool {
+        self.l2_refuel[node] && profile.burns_fuel()
+    }
+
+    // from a waypoint that isn't a node, the nearest node in its system and the distance
+    // to nav there: none to a system node, which stands for the whole system
+    fn onto_graph(&self, waypoint: &str) -> Option<(usize, Option<i32>)> {
+        let (SystemPoint(x, y), WaypointPoint(x0, y0)) = self.off_graph.get(waypoint)?;
+        let (system, point) = ((*x, *y), (*x0, *y0));
+        (self.l2_nodes.iter().enumerate())
+            .filter(|(_, node)| node.system_point() == system)
+            .map(|(i, node)| match node {
+                JumpgateWaypoint(_, WaypointPoint(x, y))
+                | MarketWaypoint(_, WaypointPoint(x, y)) => (i, Some(dist(point, (*x, *y)))),
+                System(_) => (i, None),
             })
+            .min_by_key(|(_, distance)| distance.unwrap_or(0))
+    }
+
+    // by duration, or by credits at `cost`
+    fn search(
+        &self,
+        src: &str,
+        dest: &str,
+        profile: &ShipProfile,
+        cost: Option<&RouteCost>,
+    ) -> Option<Route> {
+        let from = src;
+        let (src, nav) = match self.position(src) {
+            Some(src) => (src, None),
+            None => self.onto_graph(src)?,
+        };
+        let dest = self.position(dest)?;
+
+        let dest_point = self.l2_nodes[dest].system_point();
+        let dist_to_dest = self
+            .l2_nodes
+            .iter()
+            .map(|x| dist(x.system_point(), dest_point))
             .collect::<Vec<_>>();
-    
+
+        // from a state with `fuel`, burning `burned`. Fuel is paid for where it's bought, by
+        // topping the tank up at a refuel, or at the end of the route: it's charged at the
+        // lowest price as it's burned, and the rest at the top-up, so no leg costs less than
+        // nothing. Only the rest sees the state's fuel, which is rounded down
+        let min_price = cost.map_or(0., |cost| cost.min_fuel_price());
+        let weight = |fuel: i32, to: usize, duration: i32, burned: i32| -> i64 {
+            match cost {
+                None => duration as i64,
+                Some(cost) => {
+                    let bought = match self.refuels(to, profile) || to == dest {
+                        true => profile.fuel_capacity - (fuel - burned),
+                        false => 0,
+                    };
+                    let price = cost.fuel_price(&self.l2_nodes_name[to]);
+                    let credits = duration as f64 * cost.credits_per_second
+                        + burned as f64 * min_price
+                        + bought as f64 * (price - min_price);
+                    (credits * MILLICREDITS).round() as i64
+                }
+            }
+        };
+        // jumping the whole way; fuel is never less than free
+        let heuristic = |n: usize| -> i64 {
+            let duration = dist_to_dest[n] / 10;
+            match cost {
+                None => duration as i64,
+                Some(cost) => (duration as f64 * cost.credits_per_second * MILLICREDITS) as i64,
+            }
+        };
+
+        // the nav onto the graph, and the fuel it leaves
+        let mut legs = vec![];
+        let mut fuel_acc = profile.fuel;
+        let mut start_fuel = profile.fuel;
+        if let Some(distance) = nav {
+            let (edge, duration, fuel) = flights(profile, Link::Nav, distance)
+                .filter(|&(_, _, f)| f <= profile.fuel)
+                .min_by_key(|&(_, w, f)| weight(profile.fuel, src, w, f))?;
+            let refuel = self.refuels(src, profile);
+            fuel_acc -= fuel;
+            start_fuel = match self.l2_refuel[src] {
+                true => profile.fuel_capacity,
+                false => fuel_acc / profile.fuel_segment() * profile.fuel_segment(),
+            };
+            legs.push(Leg {
+                from: from.into(),
+                to: self.l2_nodes_name[src].clone(),
+                edge,
+                duration,
+                fuel,
+                fuel_after: fuel_acc,
+                refuel,
+            });
+            if refuel {
+                fuel_acc = profile.fuel_capacity;
+            }
+        }
+
         let start = Instant::now();
-        let result = astar::<(usize, i32), i32, _, _, _, _>(
-            &(src, START_FUEL),
+        let result = astar::<(usize, i32), i64, _, _, _, _>(
+            &(src, start_fuel),
             |&n| {
-                let node_idx = n.0;
-                let node_fuel = n.1;
-                self.l2_adj[node_idx]
-                    .iter()
-                    .filter_map(move |&(e, _, w, f, dest_is_refuel)| {
-                        if n.1 - f >= 0 {
-                            if dest_is_refuel {
-                                let f1 = MAX_FUEL;
-                                Some(((e, f1), w))
-                            } else {
-                                let f1 = (node_fuel - f) / FUEL_SEGMENT * FUEL_SEGMENT;
-                                Some(((e, f1), w))
-                            }
-                        } else {
-                            None
-                        }
-                    })
+                self.transitions(profile, n)
+                    .map(move |(n1, _, w, f)| (n1, weight(n.1, n1.0, w, f)))
             },
-            |&n| dist_to_dest[n.0] / 10,
+            |&n| heuristic(n.0),
             |&n| n.0 == dest,
         );
-        let duration = start.elapsed();
-        println!("Time elapsed: {:?}", duration);
-    
-        let (path, duration) = result.unwrap();
-        let names: Vec<&str> = path.iter().map(|&i| self.l2_nodes_name[i.0].as_str()).collect();
-    
-        println!(
-            "Planned route from {} to {} under fuel constraint: {MAX_FUEL}",
-            self.l2_nodes_name[src], self.l2_nodes_name[dest]
+        debug!(
+            "A* from {} to {}: {:?}",
+            self.l2_nodes_name[src],
+            self.l2_nodes_name[dest],
+            start.elapsed()
         );
-        let mut duration_acc = 0;
-        let mut fuel_acc = START_FUEL;
+
+        let (path, _cost) = result?;
         for i in 0..path.len() - 1 {
-            let edge = self.l2_adj[path[i].0]
-                .iter()
-                .filter(|&&(j, _, _w, f, dest_is_refuel)| {
-                    if j != path[i + 1].0 {
-                        return false;
-                    }
-                    if path[i].1 - f >= 0 {
-                        if dest_is_refuel {
-                            let f1 = MAX_FUEL;
-                            f1 == path[i + 1].1
-                        } else {
-                            let f1 = (path[i].1 - f) / FUEL_SEGMENT * FUEL_SEGMENT;
-                            f1 == path[i + 1].1
-                        }
-                    } else {
-                        false
-                    }
-                })
-                .min_by(|&&(_, _, w1, _, _), &&(_, _, w2, _, _)| w1.cmp(&w2))
+            // the cheapest leg that makes this transition
+            let (_, edge, duration, fuel) = self
+                .transitions(profile, path[i])
+                .filter(|&(n1, ..)| n1 == path[i + 1])
+                .min_by_key(|&(n1, _, w, f)| weight(path[i].1, n1.0, w, f))
                 .unwrap();
-            duration_acc += edge.2;
-            fuel_acc = (fuel_acc - edge.3) / FUEL_SEGMENT * FUEL_SEGMENT;
-            println!(
-                "{:?}\t{:14}  ->  {:14}\t{}s\t{}s\t{}\t{}",
-                edge.1,
-                names[i],
-                names[i + 1],
-                edge.2,
-                duration_acc,
-                edge.3,
-                fuel_acc,
-            );
-            if edge.4 {
-                fuel_acc = MAX_FUEL;
-                println!("Refuel\t\t{:14}\t{:14}\t\t\t\t\t{}", names[i+1], "", fuel_acc);
+            let refuel = self.refuels(path[i + 1].0, profile);
+            fuel_acc -= fuel;
+            legs.push(Leg {
+                // a system node is flown from wherever in the system the ship is
+                from: match (i, nav) {
+                    (0, None) => from.into(),
+                    _ => self.l2_nodes_name[path[i].0].clone(),
+                },
+                to: self.l2_nodes_name[path[i + 1].0].clone(),
+                edge,
+                duration,
+                fuel,
+                fuel_after: fuel_acc,
+                refuel,
+            });
+            if refuel {
+                fuel_acc = profile.fuel_capacity;
             }
         }
-    
-        println!("Total duration: {}s", duration);
-        Some(duration)
+
+        Some(Route::new(
+            from,
+            &self.l2_nodes_name[dest],
+            profile.fuel,
+            profile.fuel_capacity,
+            legs,
+        ))
     }
 }
 
-fn build_l2(charted_systems: &Vec<System>) -> (Vec<Node>, Vec<String>, Vec<Vec<(usize, Edge, i32, i32, bool)>>) {
+fn build_l2(charted_systems: &[System]) -> (Vec<Node>, Vec<String>, Vec<Vec<Adjacency>>) {
     let mut l2_nodes: Vec<Node> = vec![];
     let mut l2_nodes_name: Vec<String> = vec![];
     for system in charted_systems {
         let mut added = 0;
         for waypoint in &system.waypoints {
-            let is_market = match &waypoint.traits {
-                Some(traits) => traits.iter().any(|t| t.symbol == "MARKETPLACE"),
-                None => false,
-            };
-            if waypoint.waypoint_type == "JUMP_GATE" {
+            if waypoint._type == "JUMP_GATE" {
                 l2_nodes.push(Node::JumpgateWaypoint(
                     SystemPoint(system.x, system.y),
                     WaypointPoint(waypoint.x, waypoint.y),
                 ));
                 l2_nodes_name.push(waypoint.symbol.clone());
                 added += 1;
-            } else if is_market
-                // || waypoint.symbol == "X1-FT59-41745B" || waypoint.symbol == "X1-QP42-01002A"
-            {
+            } else if util::is_market(waypoint) {
                 l2_nodes.push(Node::MarketWaypoint(
                     SystemPoint(system.x, system.y),
                     WaypointPoint(waypoint.x, waypoint.y),
@@ -220,13 +450,13 @@ fn build_l2(charted_systems: &Vec<System>) -> (Vec<Node>, Vec<String>, Vec<Vec<(
                 added += 1;
             }
         }
-        if added == 0 && system.waypoints.len() != 0 {
+        // named after a waypoint, so a ship has somewhere to warp to
+        if let (0, Some(waypoint)) = (added, system.waypoints.first()) {
             l2_nodes.push(Node::System(SystemPoint(system.x, system.y)));
-            l2_nodes_name.push(system.symbol.clone())
+            l2_nodes_name.push(waypoint.symbol.clone())
         }
     }
 
-    println!("{:#?}", charted_systems.len());
     // count each enum type
     let mut jumpgate_waypoints = 0;
     let mut market_waypoints = 0;
@@ -238,8 +468,9 @@ fn build_l2(charted_systems: &Vec<System>) -> (Vec<Node>, Vec<String>, Vec<Vec<(
             System(_) => systems_nodes += 1,
         }
     }
-    println!(
-        "jump: {:#?} market: {:#?} system: {:#?} total: {:#?}",
+    debug!(
+        "{} systems: jump: {} market: {} system: {} total: {}",
+        charted_systems.len(),
         jumpgate_waypoints,
         market_waypoints,
         systems_nodes,
@@ -252,31 +483,14 @@ fn build_l2(charted_systems: &Vec<System>) -> (Vec<Node>, Vec<String>, Vec<Vec<(
             if i == j {
                 continue;
             }
-            let dest_is_refuel: bool = match &node_j {
-                JumpgateWaypoint(_, _) => false,
-                MarketWaypoint(_, _) => true,
-                System(_) => false,
-            };
-
             if let (JumpgateWaypoint(j1, _), JumpgateWaypoint(j2, _)) = &(node_i, node_j) {
                 let distance = dist((j1.0, j1.1), (j2.0, j2.1));
                 if distance <= MAX_JUMPGATE {
-                    let duration = max(60, ((distance as f64) / 10f64).round() as i32);
-                    l2_adj[i].push((j, Edge::Jumpgate, duration, 0, dest_is_refuel));
+                    l2_adj[i].push((j, Link::Jumpgate, distance));
                 }
             }
 
-            let sys_i = match node_i {
-                JumpgateWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                MarketWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                System(SystemPoint(x, y)) => SystemPoint(*x, *y),
-            };
-            let sys_j = match node_j {
-                JumpgateWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                MarketWaypoint(SystemPoint(x, y), _) => SystemPoint(*x, *y),
-                System(SystemPoint(x, y)) => SystemPoint(*x, *y),
-            };
-            let sys_dist = dist((sys_i.0, sys_i.1), (sys_j.0, sys_j.1));
+            let sys_dist = dist(node_i.system_point(), node_j.system_point());
             if sys_dist == 0 {
                 // nav
                 let nav_i = match node_i {
@@ -290,67 +504,272 @@ fn build_l2(charted_systems: &Vec<System>) -> (Vec<Node>, Vec<String>, Vec<Vec<(
                     System(_) => panic!("shouldn't happen"),
                 };
                 let nav_dist = dist((nav_i.0, nav_i.1), (nav_j.0, nav_j.1));
-                // nav: CRUISE
-                {
-                    let fuel = nav_dist;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 1. / 15.;
-                    let duration = 15 + (nav_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL {
-                        l2_adj[i].push((j, Edge::Nav(CRUISE), duration, fuel, dest_is_refuel));
-                    }
-                }
-                // nav BURN
-                {
-                    let fuel = nav_dist * 2;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 2. / 15.;
-                    let duration = 15 + (nav_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL {
-                        l2_adj[i].push((j, Edge::Nav(BURN), duration, fuel, dest_is_refuel));
-                    }
-                }
-                // nav DRIFT
-                {
-                    let fuel = DRIFT_FUEL_COST;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 0.1 / 15.;
-                    let duration = 15 + (nav_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL {
-                        l2_adj[i].push((j, Edge::Nav(DRIFT), duration, fuel, dest_is_refuel));
-                    }
-                }
-            } else {
-                // warp: BURN
-                {
-                    let fuel = sys_dist * 2;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 2. / 20.;
-                    let duration = 15 + (sys_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL {
-                        l2_adj[i].push((j, Edge::Warp(BURN), duration, fuel, dest_is_refuel));
-                    }
-                }
-                // warp: CRUISE
-                {
-                    let fuel = sys_dist;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 1. / 20.;
-                    let duration = 15 + (sys_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL {
-                        l2_adj[i].push((j, Edge::Warp(CRUISE), duration, fuel, dest_is_refuel));
-                    }
-                }
-                // warp: DRIFT
-                {
-                    let fuel = DRIFT_FUEL_COST;
-                    let effective_speed: f64 = SHIP_SPEED as f64 * 0.1 / 20.;
-                    let duration = 15 + (sys_dist as f64 / effective_speed).round() as i32;
-                    if fuel <= MAX_FUEL && sys_dist <= MAX_WARP {
-                        l2_adj[i].push((j, Edge::Warp(DRIFT), duration, fuel, dest_is_refuel));
-                    }
-                }
+                l2_adj[i].push((j, Link::Nav, nav_dist));
+            } else if sys_dist <= MAX_WARP {
+                l2_adj[i].push((j, Link::Warp, sys_dist));
             }
         }
     }
 
     let num_edges = l2_adj.iter().map(|x| x.len()).sum::<usize>();
-    println!("num edges: {}", num_edges);
+    debug!("num edges: {}", num_edges);
 
     (l2_nodes, l2_nodes_name, l2_adj)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{MarketTradeGood, Symbol, Waypoint};
+    use crate::pathfinding::route::FlightMode;
+
+    fn waypoint(symbol: &str, _type: &str, x: i32, y: i32, market: bool) -> Waypoint {
+        let traits = match market {
+            true => vec![Symbol {
+                symbol: "MARKETPLACE".into(),
+            }],
+            false => vec![],
+        };
+        Waypoint {
+            symbol: symbol.into(),
+            _type: _type.into(),
+            x,
+            y,
+            traits,
+            ..Default::default()
+        }
+    }
+
+    fn systems() -> Vec<System> {
+        vec![
+            System {
+                symbol: "X1-A".into(),
+                x: 0,
+                y: 0,
+                waypoints: vec![
+                    waypoint("X1-A-M", "PLANET", 0, 0, true),
+                    waypoint("X1-A-G", "JUMP_GATE", 30, 40, false),
+                    waypoint("X1-A-AST", "ASTEROID_FIELD", -30, 0, false),
+                ],
+                ..Default::default()
+            },
+            System {
+                symbol: "X1-B".into(),
+                x: 600,
+                y: 800,
+                waypoints: vec![
+                    waypoint("X1-B-G", "JUMP_GATE", 0, 0, false),
+                    waypoint("X1-B-M", "MOON", 0, 60, true),
+                ],
+                ..Default::default()
+            },
+            // nothing to stop at
+            System {
+                symbol: "X1-C".into(),
+                x: 3000,
+                y: 0,
+                waypoints: vec![waypoint("X1-C-1", "PLANET", 0, 0, false)],
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_astar() {
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        let route = graph.astar("X1-A-M", "X1-B-M", &profile).unwrap();
+        let edges: Vec<Edge> = route.legs.iter().map(|l| l.edge).collect();
+        assert_eq!(
+            edges,
+            [
+                Edge::Nav(FlightMode::Burn),
+                Edge::Jumpgate,
+                Edge::Nav(FlightMode::Burn)
+            ]
+        );
+        assert_eq!(route.legs[1].from, "X1-A-G");
+        // 1000 units apart: 100 seconds, and no fuel
+        assert_eq!(route.legs[1].duration, 100);
+        assert_eq!(route.legs[1].fuel, 0);
+        assert_eq!(
+            route.duration,
+            route.legs.iter().map(|l| l.duration).sum::<i32>()
+        );
+        assert_eq!(route.fuel, 50 * 2 + 60 * 2);
+        assert_eq!(route.refuels, ["X1-B-M"]);
+        assert_eq!(route.legs[2].fuel_after, profile.fuel - route.fuel);
+
+        assert!(graph.astar("X1-A-M", "X1-NOWHERE", &profile).is_none());
+    }
+
+    #[test]
+    fn test_dijkstra() {
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        let durations = graph.dijkstra("X1-A-M", &profile);
+        assert_eq!(durations["X1-A-M"], 0);
+        assert_eq!(
+            durations["X1-B-M"],
+            graph.astar("X1-A-M", "X1-B-M", &profile).unwrap().duration
+        );
+        // warped to
+        assert!(durations.contains_key("X1-C-1"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        // one graph for every ship
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        let fast = graph.astar("X1-A-M", "X1-B-M", &profile).unwrap();
+
+        let slow = ShipProfile {
+            speed: 10,
+            ..profile
+        };
+        let slow = graph.astar("X1-A-M", "X1-B-M", &slow).unwrap();
+        assert!(slow.duration > fast.duration);
+        assert_eq!(slow.fuel, fast.fuel);
+
+        let no_warp = ShipProfile {
+            warp_drive: false,
+            ..profile
+        };
+        assert!(graph.astar("X1-A-M", "X1-C-1", &no_warp).is_none());
+        let jump_drive = ShipProfile {
+            jump_drive: true,
+            ..no_warp
+        };
+        // 3000 away: out of a jump drive's range too
+        assert!(graph.astar("X1-A-M", "X1-C-1", &jump_drive).is_none());
+
+        // fuel is rounded down to a tenth of the tank, 150: only drifting to the gate
+        // leaves enough to burn the last leg
+        let low_fuel = ShipProfile {
+            fuel: 160,
+            ..profile
+        };
+        let route = graph.astar("X1-A-M", "X1-B-M", &low_fuel).unwrap();
+        assert_eq!(route.legs[0].edge, Edge::Nav(FlightMode::Drift));
+
+        let probe = ShipProfile {
+            fuel: 0,
+            fuel_capacity: 0,
+            ..profile
+        };
+        let route = graph.astar("X1-A-M", "X1-B-M", &probe).unwrap();
+        assert_eq!(route.duration, fast.duration);
+        assert_eq!(route.fuel, 0);
+        assert!(route.refuels.is_empty());
+    }
+
+    #[test]
+    fn test_astar_cost() {
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        let cost = RouteCost {
+            credits_per_second: 1.,
+            fuel_prices: [("X1-A-M".to_string(), 70), ("X1-B-M".to_string(), 70)].into(),
+            default_fuel_price: 70,
+        };
+        let fastest = graph.astar("X1-A-M", "X1-B-M", &profile).unwrap();
+        // burning costs 220 fuel to save 27s
+        let route = graph
+            .astar_cost("X1-A-M", "X1-B-M", &profile, &cost)
+            .unwrap();
+        let edges: Vec<Edge> = route.legs.iter().map(|l| l.edge).collect();
+        assert_eq!(
+            edges,
+            [
+                Edge::Nav(FlightMode::Cruise),
+                Edge::Jumpgate,
+                Edge::Nav(FlightMode::Cruise)
+            ]
+        );
+        assert_eq!(route.duration, fastest.duration + 27);
+        assert_eq!(cost.route_credits(&route), 185. + 77.);
+        assert!(cost.route_credits(&route) < cost.route_credits(&fastest));
+
+        // the fuel burned before the jump is bought at X1-B-M too
+        let dearer = RouteCost {
+            fuel_prices: [("X1-A-M".to_string(), 10), ("X1-B-M".to_string(), 100)].into(),
+            ..cost.clone()
+        };
+        let route = graph
+            .astar_cost("X1-A-M", "X1-B-M", &profile, &dearer)
+            .unwrap();
+        assert_eq!(route.refuels, ["X1-B-M"]);
+        assert_eq!(dearer.route_fuel_credits(&route), route.fuel as f64);
+
+        // time is money
+        let rushed = cost.with_credits_per_second(100.);
+        let route = graph
+            .astar_cost("X1-A-M", "X1-B-M", &profile, &rushed)
+            .unwrap();
+        assert_eq!(route.duration, fastest.duration);
+    }
+
+    #[test]
+    fn test_pareto() {
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        let cost = RouteCost::new(1.);
+        let routes = graph.pareto("X1-A-M", "X1-B-M", &profile, &cost).unwrap();
+        assert_eq!(routes.fastest.legs[0].edge, Edge::Nav(FlightMode::Burn));
+        // a single drifting warp, for a unit of fuel
+        assert_eq!(routes.cheapest.legs.len(), 1);
+        assert_eq!(routes.cheapest.legs[0].edge, Edge::Warp(FlightMode::Drift));
+        assert_eq!(routes.cheapest.fuel, DRIFT_FUEL_COST);
+
+        // faster is dearer
+        assert_eq!(routes.front.first(), Some(&routes.fastest));
+        assert_eq!(routes.front.last(), Some(&routes.cheapest));
+        assert!(routes.front.contains(&routes.balanced));
+        for pair in routes.front.windows(2) {
+            assert!(pair[0].duration < pair[1].duration);
+            assert!(cost.route_fuel_credits(&pair[0]) > cost.route_fuel_credits(&pair[1]));
+        }
+
+        assert!(graph
+            .pareto("X1-A-M", "X1-NOWHERE", &profile, &cost)
+            .is_none());
+    }
+
+    #[test]
+    fn test_off_graph() {
+        let graph = L2Graph::from_systems(&systems());
+        let profile = ShipProfile::default();
+        // an asteroid field isn't a node: nav to the nearest first, and refuel there
+        let route = graph.astar("X1-A-AST", "X1-B-M", &profile).unwrap();
+        assert_eq!(route.from, "X1-A-AST");
+        assert_eq!(route.legs[0].from, "X1-A-AST");
+        assert_eq!(route.legs[0].to, "X1-A-M");
+        assert_eq!(route.legs[0].fuel_after, profile.fuel - route.legs[0].fuel);
+        assert_eq!(route.refuels, ["X1-A-M", "X1-B-M"]);
+        assert_eq!(route.legs[1].from, "X1-A-M");
+        assert_eq!(route.legs.last().unwrap().to, "X1-B-M");
+
+        let route = graph.astar("X1-A-AST", "X1-A-M", &profile).unwrap();
+        assert_eq!(route.legs.len(), 1);
+        assert!(graph.astar("X1-A-NOWHERE", "X1-A-M", &profile).is_none());
+    }
+
+    #[test]
+    fn test_with_markets() {
+        // X1-B-M doesn't sell fuel, and X1-A-M hasn't been seen
+        let markets = [Market {
+            symbol: "X1-B-M".into(),
+            trade_goods: vec![MarketTradeGood {
+                symbol: "IRON_ORE".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let graph = L2Graph::from_systems(&systems()).with_markets(&markets);
+        let profile = ShipProfile::default();
+        let route = graph.astar("X1-A-AST", "X1-B-M", &profile).unwrap();
+        assert_eq!(route.refuels, ["X1-A-M"]);
+        assert!(!route.legs.last().unwrap().refuel);
+    }
+}
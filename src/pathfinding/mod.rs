@@ -0,0 +1,9 @@
+pub mod cost;
+pub mod graph;
+pub mod profile;
+pub mod route;
+
+pub use cost::{ParetoRoutes, RouteCost};
+pub use graph::L2Graph;
+pub use profile::ShipProfile;
+pub use route::{Edge, FlightMode, Leg, Route};
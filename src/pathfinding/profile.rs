@@ -0,0 +1,90 @@
+use crate::models::Ship;
+use serde::{Deserialize, Serialize};
+
+///
+/// What L2Graph needs to know about a ship to plan its route: the graph itself is the same
+/// for every ship, and the flight modes it can take on each link come from here
+///
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShipProfile {
+    pub fuel: i32,
+    // 0 for ships that don't burn fuel, eg. probes
+    pub fuel_capacity: i32,
+    pub speed: i32,
+    pub warp_drive: bool,
+    pub jump_drive: bool,
+}
+
+impl ShipProfile {
+    pub fn from_ship(ship: &Ship) -> Self {
+        let has_module = |prefix: &str| ship.modules.iter().any(|m| m.symbol.starts_with(prefix));
+        Self {
+            fuel: ship.fuel.current as i32,
+            fuel_capacity: ship.fuel.capacity as i32,
+            speed: ship.engine.speed as i32,
+            warp_drive: has_module("MODULE_WARP_DRIVE_"),
+            jump_drive: has_module("MODULE_JUMP_DRIVE_"),
+        }
+    }
+
+    pub fn burns_fuel(&self) -> bool {
+        self.fuel_capacity > 0
+    }
+
+    // what the search rounds fuel down to, so states with nearly the same fuel are merged
+    pub(crate) fn fuel_segment(&self) -> i32 {
+        (self.fuel_capacity / 10).max(1)
+    }
+}
+
+impl From<&Ship> for ShipProfile {
+    fn from(ship: &Ship) -> Self {
+        Self::from_ship(ship)
+    }
+}
+
+// what the route planner used to assume of every ship
+impl Default for ShipProfile {
+    fn default() -> Self {
+        Self {
+            fuel: 1500,
+            fuel_capacity: 1500,
+            speed: 30,
+            warp_drive: true,
+            jump_drive: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{ShipEngine, ShipFuel, ShipModule};
+
+    #[test]
+    fn test_from_ship() {
+        let ship = Ship {
+            fuel: ShipFuel {
+                current: 400,
+                capacity: 1200,
+            },
+            engine: ShipEngine {
+                symbol: "ENGINE_ION_DRIVE_II".into(),
+                speed: 30,
+            },
+            modules: vec![ShipModule {
+                symbol: "MODULE_WARP_DRIVE_I".into(),
+                range: Some(2000),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let profile = ShipProfile::from_ship(&ship);
+        assert_eq!(profile.fuel, 400);
+        assert_eq!(profile.fuel_capacity, 1200);
+        assert_eq!(profile.speed, 30);
+        assert!(profile.warp_drive);
+        assert!(!profile.jump_drive);
+        assert_eq!(profile.fuel_segment(), 120);
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FlightMode {
+    Cruise,
+    Burn,
+    Drift,
+}
+
+impl FlightMode {
+    // as the api spells it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlightMode::Cruise => "CRUISE",
+            FlightMode::Burn => "BURN",
+            FlightMode::Drift => "DRIFT",
+        }
+    }
+}
+
+impl fmt::Display for FlightMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Edge {
+    Jumpgate,
+    // jumping to a nearby system without a gate
+    JumpDrive,
+    Warp(FlightMode),
+    Nav(FlightMode),
+}
+
+impl Edge {
+    pub fn flight_mode(&self) -> Option<FlightMode> {
+        match self {
+            Edge::Jumpgate | Edge::JumpDrive => None,
+            Edge::Warp(mode) | Edge::Nav(mode) => Some(*mode),
+        }
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edge::Jumpgate => write!(f, "JUMP"),
+            Edge::JumpDrive => write!(f, "JUMP (DRIVE)"),
+            Edge::Warp(mode) => write!(f, "WARP ({})", mode),
+            Edge::Nav(mode) => write!(f, "NAV ({})", mode),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Leg {
+    pub from: String,
+    pub to: String,
+    pub edge: Edge,
+    // seconds
+    pub duration: i32,
+    pub fuel: i32,
+    // on arrival, before refuelling
+    pub fuel_after: i32,
+    // to full, at `to`
+    pub refuel: bool,
+}
+
+///
+/// A route planned by L2Graph::astar: the legs to fly in order, and what they add up to
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub from: String,
+    pub to: String,
+    pub start_fuel: i32,
+    // what a refuel fills up to
+    pub fuel_capacity: i32,
+    pub legs: Vec<Leg>,
+    pub duration: i32,
+    // burned over every leg
+    pub fuel: i32,
+    pub refuels: Vec<String>,
+}
+
+impl Route {
+    pub fn new(from: &str, to: &str, start_fuel: i32, fuel_capacity: i32, legs: Vec<Leg>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+            start_fuel,
+            fuel_capacity,
+            duration: legs.iter().map(|l| l.duration).sum(),
+            fuel: legs.iter().map(|l| l.fuel).sum(),
+            refuels: (legs.iter())
+                .filter(|l| l.refuel)
+                .map(|l| l.to.clone())
+                .collect(),
+            legs,
+        }
+    }
+}
+
+// the table that benchmarks.txt has
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Planned route {} to {}", self.from, self.to)?;
+        writeln!(
+            f,
+            "{:12}  {:14}  {:14}  {:>11}  {:>9}",
+            "mode", "from", "to", "fuel", "duration"
+        )?;
+        let mut fuel = self.start_fuel;
+        for leg in self.legs.iter() {
+            let fuel_range = format!("{}->{}", fuel, leg.fuel_after);
+            writeln!(
+                f,
+                "{:12}  {:14}  {:14}  {:>11}  {:>8}s",
+                leg.edge.to_string(),
+                leg.from,
+                leg.to,
+                fuel_range,
+                leg.duration
+            )?;
+            fuel = leg.fuel_after;
+            if leg.refuel {
+                let fuel_range = format!("{}->{}", fuel, self.fuel_capacity);
+                writeln!(
+                    f,
+                    "{:12}  {:14}  {:14}  {:>11}",
+                    "REFUEL", leg.to, leg.to, fuel_range
+                )?;
+                fuel = self.fuel_capacity;
+            }
+        }
+        write!(
+            f,
+            "Total: {}s, {} fuel, {} refuels",
+            self.duration,
+            self.fuel,
+            self.refuels.len()
+        )
+    }
+}
@@ -1,43 +1,276 @@
+use crate::clock::{Clock, RealClock};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 use futures::stream::FuturesUnordered;
 
 use futures::StreamExt as _;
-use log::{debug, error};
+use log::{debug, error, warn};
 ///
 /// Runtime is a simple runtime for executing steps repeatedly in parallel
 /// We get control over the concurrency and the priority of each step
 ///
 use priority_queue::PriorityQueue;
+use serde::{Deserialize, Serialize};
 
 use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicBool, AtomicI64, Ordering},
         Arc,
     },
     time::Duration,
 };
 use tokio::sync::Mutex;
+use tokio::sync::Notify;
 use tokio::sync::RwLock;
 use tokio::{
     sync::mpsc::{UnboundedReceiver, UnboundedSender},
     time::Instant,
 };
 
+/// Stable identity of a step, eg. ship/SOLARTRADE_INC-3: what logs, metrics and the control
+/// api call it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StepId {
+    // what sort of step, eg. ship or autobuy
+    pub kind: String,
+    pub name: String,
+}
+
+impl StepId {
+    pub fn new(kind: &str, name: &str) -> Self {
+        Self {
+            kind: kind.into(),
+            name: name.into(),
+        }
+    }
+}
+
+impl fmt::Display for StepId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.name)
+    }
+}
+
+/// Something a step needs to itself while it runs. The Runtime never runs two steps claiming
+/// the same resource at once
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Resource {
+    Ship(String),
+    // an asteroid field's surveys: extracting wears them out
+    Surveys(String),
+    // a market's trade volume: two ships selling at once both get the price for one
+    Market(String),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Ship(symbol) => write!(f, "ship/{}", symbol),
+            Resource::Surveys(symbol) => write!(f, "surveys/{}", symbol),
+            Resource::Market(symbol) => write!(f, "market/{}", symbol),
+        }
+    }
+}
+
 pub struct Runtime {
-    items: Vec<Arc<Mutex<RuntimeItem>>>,
-    concurrency: i64,
-    prequeue: RwLock<PriorityQueue<(usize, i64), Instant>>,
+    // internal slots: emptied, never reused, once a step is done
+    items: RwLock<Vec<Option<Arc<RuntimeItem>>>>,
+    ids: std::sync::Mutex<HashMap<StepId, usize>>,
+    concurrency: AtomicI64,
+    prequeue: RwLock<PriorityQueue<usize, Reverse<Instant>>>,
     queue: RwLock<PriorityQueue<usize, i64>>,
 
     num_running: AtomicI64,
+    running: RwLock<HashSet<usize>>,
+    // resource -> the running step that claimed it
+    claims: std::sync::Mutex<HashMap<Resource, usize>>,
+    waiting: std::sync::Mutex<Waiting>,
+    wakeup: Notify,
+    shutdown: std::sync::Mutex<Option<Instant>>,
+    escalated: std::sync::Mutex<Option<StepId>>,
+    restart_policy: RestartPolicy,
+    clock: Arc<dyn Clock>,
+    on_error: Option<ErrorHandler>,
     sender: UnboundedSender<usize>,
     recv: Mutex<UnboundedReceiver<usize>>,
 }
 
+type ErrorHandler = Box<dyn Fn(&StepId, String) + Send + Sync>;
+
+#[derive(Default)]
+struct Waiting {
+    // key -> steps that returned WhenNotified(key)
+    steps: HashMap<String, Vec<usize>>,
+    // step -> keys notified while it was running: if it returns WhenNotified on one, it's woken
+    // straight away
+    permits: HashMap<usize, HashSet<String>>,
+}
+
 struct RuntimeItem {
+    id: StepId,
+    added_at: DateTime<Utc>,
     step: Box<dyn Step + Send + Sync>,
-    priority: i64,
+    priority: AtomicI64,
+    // set by remove while the step is running: drop it instead of rescheduling
+    removed: AtomicBool,
+    policy: std::sync::Mutex<RestartPolicy>,
+    failures: std::sync::Mutex<Failures>,
+    metrics: std::sync::Mutex<StepMetrics>,
+    timing: std::sync::Mutex<Timing>,
+}
+
+// when the item last went into the queue, and the wake time it last asked for
+struct Timing {
+    queued_at: Option<Instant>,
+    wake_at: Option<Instant>,
+}
+
+impl RuntimeItem {
+    fn started(&self, now: Instant) {
+        let mut timing = self.timing.lock().unwrap();
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.runs += 1;
+        if let Some(queued_at) = timing.queued_at.take() {
+            metrics
+                .queue_wait
+                .record(now.saturating_duration_since(queued_at));
+        }
+        if let Some(wake_at) = timing.wake_at.take() {
+            metrics
+                .lateness
+                .record(now.saturating_duration_since(wake_at));
+        }
+    }
+}
+
+/// Upper bounds of the histogram buckets, in milliseconds. The last bucket is everything above
+const BUCKETS_MS: [u64; 12] = [
+    1, 5, 10, 50, 100, 500, 1_000, 5_000, 10_000, 30_000, 60_000, 300_000,
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+    pub buckets: [u64; BUCKETS_MS.len() + 1],
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+        let ms = value.as_millis() as u64;
+        let bucket = BUCKETS_MS.iter().position(|&b| ms <= b);
+        self.buckets[bucket.unwrap_or(BUCKETS_MS.len())] += 1;
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => self.sum / n as u32,
+        }
+    }
+
+    /// Upper bound of the bucket holding the q-th quantile, capped at the max seen
+    pub fn quantile(&self, q: f64) -> Duration {
+        let target = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                let bound = BUCKETS_MS.get(i).map(|&ms| Duration::from_millis(ms));
+                return bound.unwrap_or(self.max).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StepMetrics {
+    pub runs: u64,
+    pub duration: Histogram,
+    // from going into the queue to running: time spent waiting for a free slot
+    pub queue_wait: Histogram,
+    // from the wake time a step asked for (After, At, restart backoff) to running
+    pub lateness: Histogram,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemSnapshot {
+    pub id: StepId,
+    pub added_at: DateTime<Utc>,
+    pub priority: i64,
+    pub state: ItemState,
+    pub metrics: StepMetrics,
+    pub failures: Failures,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeSnapshot {
+    pub concurrency: i64,
+    pub running: usize,
+    pub queued: usize,
+    // every item that isn't finished
+    pub items: Vec<ItemSnapshot>,
+}
+
+/// What to do when a step panics or fails
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    /// Restart after `initial`, doubling for every consecutive failure up to `max`.
+    /// Give up after `max_failures` consecutive failures, if set
+    Backoff {
+        initial: Duration,
+        max: Duration,
+        max_failures: Option<u32>,
+    },
+    GiveUp,
+    /// Shut the runtime down: `run` returns the step in `RunReport::escalated`
+    Escalate,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Backoff {
+            initial: Duration::from_secs(5),
+            max: Duration::from_secs(600),
+            max_failures: Some(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Failures {
+    // reset when the step next returns normally
+    pub consecutive: u32,
+    pub total: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RunReport {
+    // still running at the shutdown deadline, and aborted
+    pub interrupted: Vec<StepId>,
+    // the step whose failure shut the runtime down
+    pub escalated: Option<StepId>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemState {
+    Queued,
+    Running,
+    Sleeping(Instant),
+    Waiting(String),
+    Finished,
+    // gave up after a panic
+    Failed,
 }
 
 impl Runtime {
@@ -45,86 +278,565 @@ impl Runtime {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
         Self {
-            items: vec![],
-            concurrency,
+            items: RwLock::new(vec![]),
+            ids: std::sync::Mutex::new(HashMap::new()),
+            concurrency: AtomicI64::new(concurrency),
             prequeue: RwLock::new(PriorityQueue::new()),
             queue: RwLock::new(PriorityQueue::new()),
             num_running: AtomicI64::new(0),
+            running: RwLock::new(HashSet::new()),
+            claims: std::sync::Mutex::new(HashMap::new()),
+            waiting: std::sync::Mutex::new(Waiting::default()),
+            wakeup: Notify::new(),
+            shutdown: std::sync::Mutex::new(None),
+            escalated: std::sync::Mutex::new(None),
+            restart_policy: RestartPolicy::default(),
+            clock: Arc::new(RealClock),
+            on_error: None,
             sender: tx,
             recv: Mutex::new(rx),
         }
     }
 
-    pub async fn add(&mut self, step: Box<dyn Step + Send + Sync>, priority: i64) {
-        let idx = self.items.len();
-        self.items
-            .push(Arc::new(Mutex::new(RuntimeItem { step, priority })));
+    /// A cloneable handle for changing the steps while `run` is active
+    pub fn handle(self: &Arc<Self>) -> RuntimeHandle {
+        RuntimeHandle(self.clone())
+    }
+
+    /// Returns false if a step with the same id is already scheduled, or failed and not
+    /// yet removed
+    pub async fn add(&self, id: StepId, step: Box<dyn Step + Send + Sync>, priority: i64) -> bool {
+        let mut items = self.items.write().await;
+        let idx = items.len();
+        {
+            let mut ids = self.ids.lock().unwrap();
+            if ids.contains_key(&id) {
+                warn!("Step {} already exists", id);
+                return false;
+            }
+            ids.insert(id.clone(), idx);
+        }
+        items.push(Some(Arc::new(RuntimeItem {
+            id,
+            added_at: self.clock.utc(),
+            step,
+            priority: AtomicI64::new(priority),
+            removed: AtomicBool::new(false),
+            policy: std::sync::Mutex::new(self.restart_policy),
+            failures: std::sync::Mutex::new(Failures::default()),
+            metrics: std::sync::Mutex::new(StepMetrics::default()),
+            timing: std::sync::Mutex::new(Timing {
+                queued_at: Some(self.clock.now()),
+                wake_at: None,
+            }),
+        })));
+        drop(items);
         self.queue.write().await.push(idx, priority);
+        self.wakeup.notify_one();
+        true
+    }
+
+    fn idx(&self, id: &StepId) -> Option<usize> {
+        self.ids.lock().unwrap().get(id).copied()
+    }
+
+    async fn item_by_id(&self, id: &StepId) -> Option<(usize, Arc<RuntimeItem>)> {
+        let idx = self.idx(id)?;
+        Some((idx, self.item(idx).await?))
+    }
+
+    // the step is done: forget it
+    async fn clear(&self, idx: usize) {
+        let item = self.items.write().await[idx].take();
+        if let Some(item) = item {
+            let mut ids = self.ids.lock().unwrap();
+            if ids.get(&item.id) == Some(&idx) {
+                ids.remove(&item.id);
+            }
+        }
+    }
+
+    /// Unschedules a step. A running step is left to finish its current execution
+    /// Returns false if the step is unknown or already finished
+    pub async fn remove(&self, id: &StepId) -> bool {
+        let (idx, item) = match self.item_by_id(id).await {
+            Some(item) => item,
+            None => return false,
+        };
+        item.removed.store(true, Ordering::SeqCst);
+        self.queue.write().await.remove(&idx);
+        self.prequeue.write().await.remove(&idx);
+        for steps in self.waiting.lock().unwrap().steps.values_mut() {
+            steps.retain(|i| *i != idx);
+        }
+        if !self.running.read().await.contains(&idx) {
+            self.clear(idx).await;
+        }
+        self.wakeup.notify_one();
+        true
+    }
+
+    /// Returns false if the step is unknown or already finished
+    pub async fn set_priority(&self, id: &StepId, priority: i64) -> bool {
+        let (idx, item) = match self.item_by_id(id).await {
+            Some(item) => item,
+            None => return false,
+        };
+        item.priority.store(priority, Ordering::SeqCst);
+        self.queue.write().await.change_priority(&idx, priority);
+        true
+    }
+
+    pub async fn priority(&self, id: &StepId) -> Option<i64> {
+        let (_, item) = self.item_by_id(id).await?;
+        Some(item.priority.load(Ordering::SeqCst))
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// The policy for steps added from now on
+    pub fn set_default_restart_policy(&mut self, policy: RestartPolicy) {
+        self.restart_policy = policy;
     }
 
-    pub async fn run(&self) {
+    /// Returns false if the step is unknown or already finished
+    pub async fn set_restart_policy(&self, id: &StepId, policy: RestartPolicy) -> bool {
+        match self.item_by_id(id).await {
+            Some((_, item)) => {
+                *item.policy.lock().unwrap() = policy;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn metrics(&self, id: &StepId) -> Option<StepMetrics> {
+        let (_, item) = self.item_by_id(id).await?;
+        let metrics = item.metrics.lock().unwrap().clone();
+        Some(metrics)
+    }
+
+    pub async fn snapshot(&self) -> RuntimeSnapshot {
+        let items: Vec<(usize, Arc<RuntimeItem>)> = (self.items.read().await.iter())
+            .enumerate()
+            .filter_map(|(idx, item)| Some((idx, item.clone()?)))
+            .collect();
+        let mut snapshots = vec![];
+        for (idx, item) in items {
+            snapshots.push(ItemSnapshot {
+                id: item.id.clone(),
+                added_at: item.added_at,
+                priority: item.priority.load(Ordering::SeqCst),
+                state: self.state_of(idx).await,
+                metrics: item.metrics.lock().unwrap().clone(),
+                failures: item.failures.lock().unwrap().clone(),
+            });
+        }
+        RuntimeSnapshot {
+            concurrency: self.concurrency(),
+            running: self.running.read().await.len(),
+            queued: self.queue.read().await.len(),
+            items: snapshots,
+        }
+    }
+
+    pub async fn failures(&self, id: &StepId) -> Option<Failures> {
+        let (_, item) = self.item_by_id(id).await?;
+        let failures = item.failures.lock().unwrap().clone();
+        Some(failures)
+    }
+
+    async fn item(&self, idx: usize) -> Option<Arc<RuntimeItem>> {
+        self.items.read().await.get(idx).cloned().flatten()
+    }
+
+    /// Wakes every step waiting on `key`, and any step running now that goes on to wait on it,
+    /// so a notification sent while a step is still running isn't lost
+    /// Steps that only wait on `key` later aren't woken
+    pub async fn notify(&self, key: &str) {
+        let steps = {
+            let running = self.running.read().await;
+            let mut waiting = self.waiting.lock().unwrap();
+            for &idx in running.iter() {
+                waiting
+                    .permits
+                    .entry(idx)
+                    .or_default()
+                    .insert(key.to_string());
+            }
+            match waiting.steps.remove(key) {
+                Some(steps) if !steps.is_empty() => steps,
+                _ => return,
+            }
+        };
+        debug!("Notified {}: waking {} steps", key, steps.len());
+        for idx in steps {
+            self.enqueue(idx).await;
+        }
+        self.wakeup.notify_one();
+    }
+
+    // `notified`: keys notified while the step was running
+    async fn wait(&self, idx: usize, key: String, notified: &HashSet<String>) {
+        if notified.contains(&key) {
+            debug!("Notified {} while running: waking straight away", key);
+            self.enqueue(idx).await;
+        } else {
+            let mut waiting = self.waiting.lock().unwrap();
+            waiting.steps.entry(key).or_default().push(idx);
+        }
+    }
+
+    async fn enqueue(&self, idx: usize) {
+        if let Some(item) = self.item(idx).await {
+            let priority = item.priority.load(Ordering::SeqCst);
+            item.timing.lock().unwrap().queued_at = Some(self.clock.now());
+            self.queue.write().await.push(idx, priority);
+        }
+    }
+
+    // sleep a step until `instant`, which is what its lateness is measured against
+    async fn wake_at(&self, idx: usize, item: &RuntimeItem, instant: Instant) {
+        item.timing.lock().unwrap().wake_at = Some(instant);
+        self.prequeue.write().await.push(idx, Reverse(instant));
+    }
+
+    fn waiting_on(&self, idx: usize) -> Option<String> {
+        let waiting = self.waiting.lock().unwrap();
+        (waiting.steps.iter())
+            .find(|(_, steps)| steps.contains(&idx))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Called with the item and the error when a step panics or fails
+    pub fn on_error(&mut self, f: impl Fn(&StepId, String) + Send + Sync + 'static) {
+        self.on_error = Some(Box::new(f));
+    }
+
+    pub fn concurrency(&self) -> i64 {
+        self.concurrency.load(Ordering::SeqCst)
+    }
+
+    /// Takes effect immediately when raised. When lowered, running steps are left to finish
+    pub fn set_concurrency(&self, concurrency: i64) {
+        self.concurrency.store(concurrency, Ordering::SeqCst);
+        self.wakeup.notify_one();
+    }
+
+    /// Unknown ids are Finished
+    pub async fn state(&self, id: &StepId) -> ItemState {
+        match self.idx(id) {
+            Some(idx) => self.state_of(idx).await,
+            None => ItemState::Finished,
+        }
+    }
+
+    async fn state_of(&self, idx: usize) -> ItemState {
+        if self.running.read().await.contains(&idx) {
+            return ItemState::Running;
+        }
+        if self.queue.read().await.get(&idx).is_some() {
+            return ItemState::Queued;
+        }
+        if let Some((_, Reverse(instant))) = self.prequeue.read().await.get(&idx) {
+            return ItemState::Sleeping(*instant);
+        }
+        if let Some(key) = self.waiting_on(idx) {
+            return ItemState::Waiting(key);
+        }
+        // failed steps are kept around so their errors can be looked at
+        match self.item(idx).await {
+            Some(item) if item.failures.lock().unwrap().consecutive > 0 => ItemState::Failed,
+            _ => ItemState::Finished,
+        }
+    }
+
+    /// Stop scheduling new steps, and give the running ones until `grace` to finish.
+    /// A second call can only bring the deadline forward
+    pub fn shutdown(&self, grace: Duration) {
+        let deadline = self.clock.now() + grace;
+        let mut shutdown = self.shutdown.lock().unwrap();
+        *shutdown = Some(shutdown.map_or(deadline, |prev| prev.min(deadline)));
+        drop(shutdown);
+        self.wakeup.notify_one();
+    }
+
+    fn shutdown_deadline(&self) -> Option<Instant> {
+        *self.shutdown.lock().unwrap()
+    }
+
+    /// Runs until no steps are left, or until shutdown. Steps added through a RuntimeHandle
+    /// after that are not run.
+    pub async fn run(&self) -> RunReport {
         let mut rx = self.recv.lock().await;
         let mut futures = FuturesUnordered::new();
+        let mut aborts = HashMap::new();
+        let mut started = HashMap::new();
 
         self.try_dequeue().await;
 
         loop {
-            let next_prequeue_instant = (self.prequeue.read().await.peek())
-                .map(|(_, instant)| *instant)
-                .unwrap_or(Instant::now() + Duration::from_secs(3600));
+            let shutdown_deadline = self.shutdown_deadline();
+            if shutdown_deadline.is_some() && self.num_running.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            // every step is done: nothing left to schedule
+            if self.num_running.load(Ordering::SeqCst) == 0
+                && self.prequeue.read().await.is_empty()
+                && self.queue.read().await.is_empty()
+                && self
+                    .waiting
+                    .lock()
+                    .unwrap()
+                    .steps
+                    .values()
+                    .all(|s| s.is_empty())
+            {
+                break;
+            }
+            let next_prequeue_instant = match shutdown_deadline {
+                Some(deadline) => Some(deadline),
+                None => (self.prequeue.read().await.peek()).map(|(_, Reverse(instant))| *instant),
+            };
+            // nothing to wake for: an idle timer would let a paused SimClock jump to it
+            let next_prequeue = async {
+                match next_prequeue_instant {
+                    Some(instant) => self.clock.sleep_until(instant).await,
+                    None => std::future::pending().await,
+                }
+            };
             tokio::select! {
                 Some(idx) = rx.recv() => {
-                    let item = self.items[idx].clone();
+                    if self.shutdown_deadline().is_some() {
+                        self.unschedule(idx).await;
+                        continue;
+                    }
+                    let item = self.item(idx).await;
+                    if let Some(item) = &item {
+                        item.started(self.clock.now());
+                    }
+                    started.insert(idx, self.clock.now());
                     let run_step = async move {
-                        let start = Instant::now();
-                        let ret = item.lock().await.step.step().await;
-                        let end = Instant::now();
-                        debug!("Step {} took {}ms", idx, (end - start).as_millis());
+                        // removed between being dequeued and getting here
+                        let item = match item {
+                            Some(item) if !item.removed.load(Ordering::SeqCst) => item,
+                            _ => return (idx, StepOutcome::Done),
+                        };
+                        let ret = item.step.step().await;
                         (idx, ret)
                     };
+                    let join_handle = tokio::spawn(run_step);
+                    aborts.insert(idx, join_handle.abort_handle());
                     let fut = async move {
-                        let join_result = tokio::spawn(run_step).await;
+                        let join_result = join_handle.await;
                         (idx, join_result)
                     };
                     futures.push(fut);
                 },
-                Some((_idx, join_result)) = futures.next() => {
+                Some((idx, join_result)) = futures.next() => {
                     // log::debug!("{:?}", join_result);
+                    aborts.remove(&idx);
+                    let notified = self.stop_running(idx).await;
                     self.num_running.fetch_add(-1, Ordering::SeqCst);
+                    self.release(idx);
+                    let item = self.item(idx).await;
+                    let elapsed = self.clock.now() - started.remove(&idx).unwrap();
+                    if let Some(item) = &item {
+                        debug!("Step {} took {}ms", item.id, elapsed.as_millis());
+                        item.metrics.lock().unwrap().duration.record(elapsed);
+                    }
+                    let removed = item.as_ref().is_none_or(|item| item.removed.load(Ordering::SeqCst));
                     match join_result {
-                        Ok(result) => {
-                            let (idx, ret) = result;
-                            if let Some(duration) = ret {
-                                let priority = self.items[idx].lock().await.priority;
-                                let instant = tokio::time::Instant::now() + duration;
-                                self.prequeue.write().await.push((idx, priority), instant);
-                            }
-                        },
+                        Err(e) if e.is_cancelled() => {},
+                        Ok(_) if removed => self.clear(idx).await,
+                        Ok((idx, outcome)) => {
+                            self.schedule(idx, &item.unwrap(), outcome, &notified).await
+                        }
                         Err(e) => {
-                            error!("Join error in step: {:?}", e);
+                            let message = panic_message(e);
+                            if let Some(item) = item {
+                                error!("Step {} panicked: {}", item.id, message);
+                                if removed {
+                                    self.clear(idx).await;
+                                } else {
+                                    self.supervise(idx, &item, &message).await;
+                                }
+                                if let Some(f) = &self.on_error {
+                                    f(&item.id, message);
+                                }
+                            }
                         }
                     }
                     self.try_dequeue().await;
                 },
-                _ = tokio::time::sleep_until(next_prequeue_instant) => {
+                _ = next_prequeue => {
+                    if shutdown_deadline.is_some() {
+                        // out of time: abort whatever is still running
+                        break;
+                    }
+                    self.try_dequeue().await;
+                },
+                _ = self.wakeup.notified() => {
                     self.try_dequeue().await;
                 },
                 else => break,
             }
         }
+
+        // dequeued, but never started
+        while let Ok(idx) = rx.try_recv() {
+            self.unschedule(idx).await;
+        }
+        let mut interrupted = vec![];
+        for (idx, abort) in aborts {
+            abort.abort();
+            self.unschedule(idx).await;
+            if let Some(item) = self.item(idx).await {
+                interrupted.push(item.id.clone());
+            }
+        }
+        interrupted.sort();
+        RunReport {
+            interrupted,
+            escalated: self.escalated.lock().unwrap().clone(),
+        }
+    }
+
+    // no longer running: returns the keys notified while it was
+    async fn stop_running(&self, idx: usize) -> HashSet<String> {
+        let mut running = self.running.write().await;
+        running.remove(&idx);
+        // under the running lock, so a notify either sees it running or not at all
+        let mut waiting = self.waiting.lock().unwrap();
+        waiting.permits.remove(&idx).unwrap_or_default()
+    }
+
+    async fn schedule(
+        &self,
+        idx: usize,
+        item: &RuntimeItem,
+        outcome: StepOutcome,
+        notified: &HashSet<String>,
+    ) {
+        if !matches!(outcome, StepOutcome::Failed(_)) {
+            item.failures.lock().unwrap().consecutive = 0;
+        }
+        match outcome {
+            StepOutcome::After(duration) => {
+                self.wake_at(idx, item, self.clock.now() + duration).await;
+            }
+            StepOutcome::At(time) => {
+                // in the past: run again straight away
+                let duration = self.clock.until(time);
+                self.wake_at(idx, item, self.clock.now() + duration).await;
+            }
+            StepOutcome::WhenNotified(key) => self.wait(idx, key, notified).await,
+            StepOutcome::Done => self.clear(idx).await,
+            StepOutcome::Failed(error) => {
+                error!("Step {} failed: {}", item.id, error);
+                self.supervise(idx, item, &error).await;
+                if let Some(f) = &self.on_error {
+                    f(&item.id, error);
+                }
+            }
+        }
+    }
+
+    // a step panicked or failed: restart, give up or escalate according to its policy
+    async fn supervise(&self, idx: usize, item: &RuntimeItem, message: &str) {
+        let consecutive = {
+            let mut failures = item.failures.lock().unwrap();
+            failures.consecutive += 1;
+            failures.total += 1;
+            failures.last_error = Some(message.to_string());
+            failures.consecutive
+        };
+        let policy = *item.policy.lock().unwrap();
+        match policy {
+            RestartPolicy::Backoff {
+                initial,
+                max,
+                max_failures,
+            } if max_failures.is_none_or(|n| consecutive < n) => {
+                let backoff = initial
+                    .saturating_mul(2u32.saturating_pow(consecutive - 1))
+                    .min(max);
+                warn!(
+                    "Step {} failed {} times in a row, restarting in {}s",
+                    item.id,
+                    consecutive,
+                    backoff.as_secs()
+                );
+                self.wake_at(idx, item, self.clock.now() + backoff).await;
+            }
+            RestartPolicy::Backoff { .. } | RestartPolicy::GiveUp => {
+                error!(
+                    "Step {} failed {} times in a row, giving up",
+                    item.id, consecutive
+                );
+            }
+            RestartPolicy::Escalate => {
+                error!("Step {} failed, escalating: shutting down", item.id);
+                (self.escalated.lock().unwrap()).get_or_insert_with(|| item.id.clone());
+                self.shutdown(Duration::from_secs(0));
+            }
+        }
+    }
+
+    // puts a dequeued step back in the queue without running it
+    async fn unschedule(&self, idx: usize) {
+        self.stop_running(idx).await;
+        self.num_running.fetch_add(-1, Ordering::SeqCst);
+        self.release(idx);
+        self.enqueue(idx).await;
+    }
+
+    // claims the step's resources, or returns the first one another running step holds
+    fn claim(&self, idx: usize, item: &RuntimeItem) -> Result<(), Resource> {
+        let resources = item.step.resources();
+        let mut claims = self.claims.lock().unwrap();
+        if let Some(held) = resources
+            .iter()
+            .find(|r| claims.get(*r).is_some_and(|&owner| owner != idx))
+        {
+            return Err(held.clone());
+        }
+        for resource in resources {
+            claims.insert(resource, idx);
+        }
+        Ok(())
+    }
+
+    fn release(&self, idx: usize) {
+        self.claims.lock().unwrap().retain(|_, owner| *owner != idx);
     }
 
     async fn try_dequeue(&self) {
+        if self.shutdown_deadline().is_some() {
+            return;
+        }
+        let items = self.items.read().await;
         let mut prequeue = self.prequeue.write().await;
         let mut queue = self.queue.write().await;
 
         loop {
             let mut added = false;
-            let front = prequeue.peek().map(|(idx, instant)| (*idx, *instant));
-            if let Some(((idx, priority), instant)) = front {
-                if instant <= tokio::time::Instant::now() {
+            let front = prequeue
+                .peek()
+                .map(|(idx, Reverse(instant))| (*idx, *instant));
+            if let Some((idx, instant)) = front {
+                if instant <= self.clock.now() {
                     prequeue.pop();
-                    queue.push(idx, priority);
+                    if let Some(item) = &items[idx] {
+                        item.timing.lock().unwrap().queued_at = Some(self.clock.now());
+                        queue.push(idx, item.priority.load(Ordering::SeqCst));
+                    }
                     added = true;
                 }
             }
@@ -134,37 +846,143 @@ impl Runtime {
         }
         drop(prequeue);
         drop(queue);
+        drop(items);
 
-        while self.num_running.load(Ordering::SeqCst) < self.concurrency {
+        // steps whose resources are held by a running step: they keep their place in the
+        // queue, while lower priority steps that don't conflict go ahead
+        let mut blocked = vec![];
+        let concurrency = self.concurrency();
+        while self.num_running.load(Ordering::SeqCst) < concurrency {
             let front = {
                 let mut queue = self.queue.write().await;
 
                 queue.pop()
             };
-            if let Some((queue_idx, _priority)) = front {
-                let running_prev = self.num_running.fetch_add(1, Ordering::SeqCst);
-                self.sender.send(queue_idx).unwrap();
-                if running_prev + 1 >= self.concurrency {
-                    return;
+            let Some((queue_idx, priority)) = front else {
+                break;
+            };
+            if let Some(item) = self.item(queue_idx).await {
+                if let Err(resource) = self.claim(queue_idx, &item) {
+                    debug!("Step {} is waiting for {}", item.id, resource);
+                    blocked.push((queue_idx, priority));
+                    continue;
                 }
-            } else {
-                return;
             }
+            self.num_running.fetch_add(1, Ordering::SeqCst);
+            self.running.write().await.insert(queue_idx);
+            self.sender.send(queue_idx).unwrap();
+        }
+        if !blocked.is_empty() {
+            self.queue.write().await.extend(blocked);
         }
     }
 }
 
-type StepResult = Option<Duration>;
+///
+/// RuntimeHandle can be cloned into steps and the control api to change what a running
+/// Runtime is doing: add and remove steps, reprioritise them and look at their state
+///
+#[derive(Clone)]
+pub struct RuntimeHandle(Arc<Runtime>);
+
+impl RuntimeHandle {
+    pub async fn add(&self, id: StepId, step: Box<dyn Step + Send + Sync>, priority: i64) -> bool {
+        self.0.add(id, step, priority).await
+    }
+
+    pub async fn remove(&self, id: &StepId) -> bool {
+        self.0.remove(id).await
+    }
+
+    pub async fn set_priority(&self, id: &StepId, priority: i64) -> bool {
+        self.0.set_priority(id, priority).await
+    }
+
+    pub async fn priority(&self, id: &StepId) -> Option<i64> {
+        self.0.priority(id).await
+    }
+
+    pub async fn state(&self, id: &StepId) -> ItemState {
+        self.0.state(id).await
+    }
+
+    pub fn concurrency(&self) -> i64 {
+        self.0.concurrency()
+    }
+
+    pub fn set_concurrency(&self, concurrency: i64) {
+        self.0.set_concurrency(concurrency)
+    }
+
+    pub fn shutdown(&self, grace: Duration) {
+        self.0.shutdown(grace)
+    }
+
+    pub async fn notify(&self, key: &str) {
+        self.0.notify(key).await
+    }
+
+    pub async fn set_restart_policy(&self, id: &StepId, policy: RestartPolicy) -> bool {
+        self.0.set_restart_policy(id, policy).await
+    }
+
+    pub async fn failures(&self, id: &StepId) -> Option<Failures> {
+        self.0.failures(id).await
+    }
+
+    pub async fn metrics(&self, id: &StepId) -> Option<StepMetrics> {
+        self.0.metrics(id).await
+    }
+
+    pub async fn snapshot(&self) -> RuntimeSnapshot {
+        self.0.snapshot().await
+    }
+}
+
+fn panic_message(e: tokio::task::JoinError) -> String {
+    if !e.is_panic() {
+        return e.to_string();
+    }
+    let payload = e.into_panic();
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic".into()
+    }
+}
+
+/// What a step wants to happen next
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    After(Duration),
+    At(DateTime<Utc>),
+    /// Sleep until someone calls `notify` with the key, eg. `events::CREDITS_KEY`
+    WhenNotified(String),
+    Done,
+    /// Handled like a panic, according to the step's RestartPolicy
+    Failed(String),
+}
 
 #[async_trait]
 pub trait Step {
-    async fn step(&self) -> StepResult;
+    async fn step(&self) -> StepOutcome;
+
+    /// What the next execution needs to itself, asked for each time the step is dequeued
+    fn resources(&self) -> Vec<Resource> {
+        vec![]
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn id(name: &str) -> StepId {
+        StepId::new("test", name)
+    }
+
     struct TestExecutor(Arc<Mutex<i64>>);
     impl TestExecutor {
         fn new(x: i64) -> Self {
@@ -173,23 +991,553 @@ mod test {
     }
     #[async_trait]
     impl Step for TestExecutor {
-        async fn step(&self) -> StepResult {
+        async fn step(&self) -> StepOutcome {
             let mut x = self.0.lock().await;
             *x -= 1;
             println!("step: {}", x);
             if *x == 0 {
-                None
+                StepOutcome::Done
             } else {
-                Some(Duration::from_secs(0))
+                StepOutcome::After(Duration::from_secs(0))
             }
         }
     }
 
     #[tokio::test]
     async fn test() {
+        let runtime = Runtime::new(1);
+        runtime
+            .add(id("s1"), Box::new(TestExecutor::new(3)), 0)
+            .await;
+        runtime
+            .add(id("s2"), Box::new(TestExecutor::new(4)), 0)
+            .await;
+        runtime.run().await;
+    }
+
+    #[tokio::test]
+    async fn test_state() {
+        let runtime = Runtime::new(1);
+        let idx = id("idx");
+        runtime
+            .add(idx.clone(), Box::new(TestExecutor::new(2)), 0)
+            .await;
+        assert_eq!(runtime.state(&idx).await, ItemState::Queued);
+        runtime.set_concurrency(2);
+        assert_eq!(runtime.concurrency(), 2);
+        runtime.run().await;
+        assert_eq!(runtime.state(&idx).await, ItemState::Finished);
+    }
+
+    struct PanickingExecutor;
+    #[async_trait]
+    impl Step for PanickingExecutor {
+        async fn step(&self) -> StepOutcome {
+            panic!("out of fuel");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_error() {
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
         let mut runtime = Runtime::new(1);
-        runtime.add(Box::new(TestExecutor::new(3)), 0).await;
-        runtime.add(Box::new(TestExecutor::new(4)), 0).await;
+        runtime.set_default_restart_policy(RestartPolicy::GiveUp);
+        runtime
+            .add(id("s3"), Box::new(TestExecutor::new(1)), 0)
+            .await;
+        runtime.add(id("s4"), Box::new(PanickingExecutor), 0).await;
+        let errors1 = errors.clone();
+        runtime.on_error(move |id, e| errors1.lock().unwrap().push((id.clone(), e)));
         runtime.run().await;
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec![(id("s4"), "out of fuel".into())]
+        );
+    }
+
+    // adds a TestExecutor through the handle on its first step
+    struct SpawningExecutor(RuntimeHandle, Arc<Mutex<Option<StepId>>>);
+    #[async_trait]
+    impl Step for SpawningExecutor {
+        async fn step(&self) -> StepOutcome {
+            let spawned = id("spawned");
+            assert!(
+                self.0
+                    .add(spawned.clone(), Box::new(TestExecutor::new(2)), 10)
+                    .await
+            );
+            // ids are unique
+            assert!(
+                !self
+                    .0
+                    .add(spawned.clone(), Box::new(TestExecutor::new(2)), 10)
+                    .await
+            );
+            *self.1.lock().await = Some(spawned);
+            StepOutcome::Done
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_add() {
+        let runtime = Arc::new(Runtime::new(1));
+        let spawned = Arc::new(Mutex::new(None));
+        let handle = runtime.handle();
+        runtime
+            .add(
+                id("s5"),
+                Box::new(SpawningExecutor(handle, spawned.clone())),
+                0,
+            )
+            .await;
+        runtime.run().await;
+        let idx = spawned.lock().await.clone().unwrap();
+        assert_eq!(idx, id("spawned"));
+        assert_eq!(runtime.state(&idx).await, ItemState::Finished);
+        assert_eq!(runtime.priority(&idx).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_remove_and_priority() {
+        let runtime = Arc::new(Runtime::new(1));
+        let handle = runtime.handle();
+        let counter = Arc::new(Mutex::new(i64::MAX));
+        let idx0 = id("idx0");
+        handle
+            .add(idx0.clone(), Box::new(TestExecutor(counter.clone())), 0)
+            .await;
+        let idx1 = id("idx1");
+        handle
+            .add(idx1.clone(), Box::new(TestExecutor::new(1)), 0)
+            .await;
+        assert!(handle.set_priority(&idx0, 5).await);
+        assert_eq!(handle.priority(&idx0).await, Some(5));
+        assert!(!handle.set_priority(&id("unknown"), 5).await);
+
+        let run = tokio::spawn({
+            let runtime = runtime.clone();
+            async move { runtime.run().await }
+        });
+        // the higher priority step loops; removing it lets run finish
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(handle.remove(&idx0).await);
+        run.await.unwrap();
+        assert!(*counter.lock().await < i64::MAX);
+        assert_eq!(handle.state(&idx0).await, ItemState::Finished);
+        assert_eq!(handle.state(&idx1).await, ItemState::Finished);
+        assert!(!handle.remove(&idx0).await);
+    }
+
+    struct SlowExecutor(Duration);
+    #[async_trait]
+    impl Step for SlowExecutor {
+        async fn step(&self) -> StepOutcome {
+            tokio::time::sleep(self.0).await;
+            StepOutcome::After(Duration::from_secs(0))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let runtime = Arc::new(Runtime::new(2));
+        let handle = runtime.handle();
+        let fast = id("fast");
+        handle
+            .add(
+                fast.clone(),
+                Box::new(SlowExecutor(Duration::from_millis(10))),
+                1,
+            )
+            .await;
+        let slow = id("slow");
+        handle
+            .add(
+                slow.clone(),
+                Box::new(SlowExecutor(Duration::from_secs(3600))),
+                2,
+            )
+            .await;
+        let waiting = id("waiting");
+        handle
+            .add(
+                waiting.clone(),
+                Box::new(SlowExecutor(Duration::from_millis(10))),
+                0,
+            )
+            .await;
+
+        let run = tokio::spawn({
+            let runtime = runtime.clone();
+            async move { runtime.run().await }
+        });
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        handle.shutdown(Duration::from_millis(100));
+        let report = run.await.unwrap();
+
+        // the fast step got to finish, the slow one was aborted at the deadline,
+        // and nothing new was started
+        assert_eq!(report.interrupted, vec![slow.clone()]);
+        assert!(matches!(handle.state(&fast).await, ItemState::Sleeping(_)));
+        assert_eq!(handle.state(&slow).await, ItemState::Queued);
+        assert_eq!(handle.state(&waiting).await, ItemState::Queued);
+    }
+
+    // panics the first n times it is stepped, then finishes
+    struct FlakyExecutor(std::sync::Mutex<u32>);
+    #[async_trait]
+    impl Step for FlakyExecutor {
+        async fn step(&self) -> StepOutcome {
+            let remaining = {
+                let mut n = self.0.lock().unwrap();
+                *n = n.saturating_sub(1);
+                *n
+            };
+            if remaining > 0 {
+                panic!("flaky: {}", remaining);
+            }
+            StepOutcome::Done
+        }
+    }
+
+    fn backoff(max_failures: Option<u32>) -> RestartPolicy {
+        RestartPolicy::Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+            max_failures,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart_backoff() {
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut runtime = Runtime::new(1);
+        runtime.set_default_restart_policy(backoff(None));
+        let errors1 = errors.clone();
+        runtime.on_error(move |_, e| errors1.lock().unwrap().push(e));
+        let idx = id("idx");
+        runtime
+            .add(
+                idx.clone(),
+                Box::new(FlakyExecutor(std::sync::Mutex::new(4))),
+                0,
+            )
+            .await;
+        let report = runtime.run().await;
+        assert_eq!(report, RunReport::default());
+        assert_eq!(
+            *errors.lock().unwrap(),
+            vec!["flaky: 3", "flaky: 2", "flaky: 1"]
+        );
+        assert_eq!(runtime.state(&idx).await, ItemState::Finished);
+    }
+
+    #[tokio::test]
+    async fn test_restart_give_up() {
+        let runtime = Runtime::new(1);
+        let idx = id("idx");
+        runtime
+            .add(idx.clone(), Box::new(PanickingExecutor), 0)
+            .await;
+        assert!(runtime.set_restart_policy(&idx, backoff(Some(3))).await);
+        runtime.run().await;
+        assert_eq!(runtime.state(&idx).await, ItemState::Failed);
+        assert_eq!(
+            runtime.failures(&idx).await,
+            Some(Failures {
+                consecutive: 3,
+                total: 3,
+                last_error: Some("out of fuel".into()),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restart_escalate() {
+        let mut runtime = Runtime::new(2);
+        runtime.set_default_restart_policy(RestartPolicy::Escalate);
+        let slow = id("slow");
+        runtime
+            .add(
+                slow.clone(),
+                Box::new(SlowExecutor(Duration::from_secs(3600))),
+                1,
+            )
+            .await;
+        let panicking = id("panicking");
+        runtime
+            .add(panicking.clone(), Box::new(PanickingExecutor), 0)
+            .await;
+        let report = runtime.run().await;
+        assert_eq!(report.escalated, Some(panicking));
+        assert_eq!(report.interrupted, vec![slow.clone()]);
+    }
+
+    // waits on "sold" once, after running for the duration, then is done
+    struct WaitingExecutor(std::sync::Mutex<u32>, Duration);
+    #[async_trait]
+    impl Step for WaitingExecutor {
+        async fn step(&self) -> StepOutcome {
+            let n = {
+                let mut n = self.0.lock().unwrap();
+                *n += 1;
+                *n
+            };
+            match n {
+                1 => {
+                    tokio::time::sleep(self.1).await;
+                    StepOutcome::WhenNotified("sold".into())
+                }
+                _ => StepOutcome::Done,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_when_notified() {
+        let runtime = Arc::new(Runtime::new(1));
+        let handle = runtime.handle();
+        let idx = id("idx");
+        handle
+            .add(
+                idx.clone(),
+                Box::new(WaitingExecutor(std::sync::Mutex::new(0), Duration::ZERO)),
+                0,
+            )
+            .await;
+        let run = tokio::spawn({
+            let runtime = runtime.clone();
+            async move { runtime.run().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(handle.state(&idx).await, ItemState::Waiting("sold".into()));
+        handle.notify("extracted").await;
+        assert_eq!(handle.state(&idx).await, ItemState::Waiting("sold".into()));
+        handle.notify("sold").await;
+        run.await.unwrap();
+        assert_eq!(handle.state(&idx).await, ItemState::Finished);
+    }
+
+    #[tokio::test]
+    async fn test_notified_before_waiting() {
+        // the notification arrives while the step is still running
+        let runtime = Arc::new(Runtime::new(1));
+        let step = WaitingExecutor(std::sync::Mutex::new(0), Duration::from_millis(50));
+        runtime.add(id("s6"), Box::new(step), 0).await;
+        let run = tokio::spawn({
+            let runtime = runtime.clone();
+            async move { runtime.run().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        runtime.notify("sold").await;
+        run.await.unwrap();
+        assert_eq!(runtime.state(&id("s6")).await, ItemState::Finished);
+
+        // before the step ran at all: it's stale by the time the step waits
+        let runtime = Arc::new(Runtime::new(1));
+        runtime.notify("sold").await;
+        let step = WaitingExecutor(std::sync::Mutex::new(0), Duration::ZERO);
+        runtime.add(id("s7"), Box::new(step), 0).await;
+        let run = tokio::spawn({
+            let runtime = runtime.clone();
+            async move { runtime.run().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            runtime.state(&id("s7")).await,
+            ItemState::Waiting("sold".into())
+        );
+        runtime.notify("sold").await;
+        run.await.unwrap();
+        assert_eq!(runtime.state(&id("s7")).await, ItemState::Finished);
+    }
+
+    struct OutcomeExecutor(std::sync::Mutex<Vec<StepOutcome>>);
+    #[async_trait]
+    impl Step for OutcomeExecutor {
+        async fn step(&self) -> StepOutcome {
+            self.0.lock().unwrap().pop().unwrap_or(StepOutcome::Done)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_at_and_failed() {
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut runtime = Runtime::new(1);
+        runtime.set_default_restart_policy(backoff(None));
+        let errors1 = errors.clone();
+        runtime.on_error(move |_, e| errors1.lock().unwrap().push(e));
+        let outcomes = vec![
+            StepOutcome::Failed("no fuel".into()),
+            StepOutcome::At(Utc::now() + chrono::Duration::milliseconds(20)),
+            // in the past
+            StepOutcome::At(Utc::now() - chrono::Duration::seconds(60)),
+        ];
+        runtime
+            .add(
+                id("s7"),
+                Box::new(OutcomeExecutor(std::sync::Mutex::new(outcomes))),
+                0,
+            )
+            .await;
+        let start = Instant::now();
+        runtime.run().await;
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert_eq!(*errors.lock().unwrap(), vec!["no fuel"]);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::default();
+        assert_eq!(h.quantile(0.5), Duration::ZERO);
+        for ms in [3, 4, 8, 40, 2_000] {
+            h.record(Duration::from_millis(ms));
+        }
+        assert_eq!(h.count, 5);
+        assert_eq!(h.mean(), Duration::from_millis(411));
+        assert_eq!(h.quantile(0.5), Duration::from_millis(10));
+        assert_eq!(h.quantile(0.95), Duration::from_millis(2_000));
+        assert_eq!(h.max, Duration::from_millis(2_000));
+    }
+
+    // runs for 10ms, then sleeps for 15ms
+    struct PacedExecutor;
+    #[async_trait]
+    impl Step for PacedExecutor {
+        async fn step(&self) -> StepOutcome {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            StepOutcome::After(Duration::from_millis(15))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot() {
+        // one slot, two steps: b waits in the queue while a runs
+        let runtime = Runtime::new(1);
+        let a = id("a");
+        runtime.add(a.clone(), Box::new(PacedExecutor), 1).await;
+        let b = id("b");
+        runtime.add(b.clone(), Box::new(PacedExecutor), 0).await;
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            runtime.shutdown(Duration::from_secs(1));
+        };
+        let (report, _) = tokio::join!(runtime.run(), stop);
+        assert!(report.interrupted.is_empty());
+
+        let metrics = runtime.metrics(&b).await.unwrap();
+        assert!(metrics.runs >= 2);
+        assert_eq!(metrics.duration.count, metrics.runs);
+        assert!(metrics.duration.max >= Duration::from_millis(10));
+        assert!(metrics.queue_wait.max >= Duration::from_millis(10));
+        assert_eq!(metrics.lateness.count, metrics.runs - 1);
+
+        let snapshot = runtime.snapshot().await;
+        assert_eq!(snapshot.concurrency, 1);
+        assert_eq!(snapshot.running, 0);
+        let ids: Vec<StepId> = snapshot.items.iter().map(|i| i.id.clone()).collect();
+        assert_eq!(ids, vec![a, b]);
+        assert_eq!(snapshot.items[0].priority, 1);
+        assert!(matches!(snapshot.items[0].state, ItemState::Sleeping(_)));
+    }
+
+    // navigates for an hour, three times over
+    struct NavigatingExecutor(Arc<dyn Clock>, std::sync::Mutex<u32>);
+    #[async_trait]
+    impl Step for NavigatingExecutor {
+        async fn step(&self) -> StepOutcome {
+            let mut legs = self.1.lock().unwrap();
+            if *legs == 3 {
+                return StepOutcome::Done;
+            }
+            *legs += 1;
+            StepOutcome::At(self.0.utc() + chrono::Duration::hours(1))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_clock() {
+        let start: DateTime<Utc> = "2023-08-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(crate::clock::SimClock::new(start));
+        let mut runtime = Runtime::new(1);
+        runtime.set_clock(clock.clone());
+        let idx = id("idx");
+        runtime
+            .add(
+                idx.clone(),
+                Box::new(NavigatingExecutor(clock.clone(), std::sync::Mutex::new(0))),
+                0,
+            )
+            .await;
+        runtime.run().await;
+        assert_eq!(clock.utc(), start + chrono::Duration::hours(3));
+        assert_eq!(runtime.state(&idx).await, ItemState::Finished);
+    }
+
+    // runs three times, recording who else holds its resources meanwhile
+    struct ClaimingExecutor {
+        resources: Vec<Resource>,
+        held: Arc<std::sync::Mutex<Vec<Resource>>>,
+        conflicts: Arc<AtomicI64>,
+        runs: std::sync::Mutex<u32>,
+    }
+    #[async_trait]
+    impl Step for ClaimingExecutor {
+        async fn step(&self) -> StepOutcome {
+            {
+                let mut held = self.held.lock().unwrap();
+                if self.resources.iter().any(|r| held.contains(r)) {
+                    self.conflicts.fetch_add(1, Ordering::SeqCst);
+                }
+                held.extend(self.resources.iter().cloned());
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.held
+                .lock()
+                .unwrap()
+                .retain(|r| !self.resources.contains(r));
+
+            let mut runs = self.runs.lock().unwrap();
+            *runs += 1;
+            if *runs == 3 {
+                return StepOutcome::Done;
+            }
+            StepOutcome::After(Duration::from_secs(0))
+        }
+
+        fn resources(&self) -> Vec<Resource> {
+            self.resources.clone()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resources() {
+        let held = Arc::new(std::sync::Mutex::new(vec![]));
+        let conflicts = Arc::new(AtomicI64::new(0));
+        let claiming = |resources: Vec<Resource>| {
+            Box::new(ClaimingExecutor {
+                resources,
+                held: held.clone(),
+                conflicts: conflicts.clone(),
+                runs: std::sync::Mutex::new(0),
+            })
+        };
+        let runtime = Runtime::new(3);
+        let ship = |s: &str| Resource::Ship(s.into());
+        runtime.add(id("a"), claiming(vec![ship("A")]), 2).await;
+        runtime
+            .add(
+                id("b"),
+                claiming(vec![ship("A"), Resource::Market("M".into())]),
+                1,
+            )
+            .await;
+        runtime.add(id("c"), claiming(vec![ship("B")]), 0).await;
+        let start = Instant::now();
+        runtime.run().await;
+
+        assert_eq!(conflicts.load(Ordering::SeqCst), 0);
+        // a and b take turns on ship A, while c runs alongside them
+        assert_eq!(start.elapsed(), Duration::from_millis(60));
+        assert!(runtime.claims.lock().unwrap().is_empty());
     }
 }
@@ -1,18 +1,18 @@
-use crate::runtime::Step;
+use crate::runtime::{Step, StepOutcome};
 use async_trait::async_trait;
-use std::time::Duration;
 
-struct AutoBuy {}
+#[derive(Default)]
+pub struct AutoBuy {}
 
 impl AutoBuy {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {}
     }
 }
 
 #[async_trait]
 impl Step for AutoBuy {
-    async fn step(&self) -> Option<Duration> {
-        None
+    async fn step(&self) -> StepOutcome {
+        StepOutcome::Done
     }
 }
@@ -0,0 +1,377 @@
+use crate::decision_tree::EdgeType;
+use crate::scripts::mining::PreparedGraph;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+
+///
+/// Export of an evaluated PreparedGraph, to see the policy `evaluate` chose
+///
+/// With `collapse_surveys`, the sampled survey_i and extract_survey_i nodes become survey_*
+/// and extract_survey_*, and their edges are summed up: what fraction of the sampled surveys
+/// get extracted, and where the cargo they yield goes.
+///
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ExportOptions {
+    pub collapse_surveys: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedGraph {
+    pub x0: f64,
+    pub nodes: Vec<ExportedNode>,
+    pub edges: Vec<ExportedEdge>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedNode {
+    pub name: String,
+    // how many nodes of the graph this one stands for
+    pub count: usize,
+    // mean over those nodes, if evaluated
+    pub fx: Option<(f64, f64)>,
+    // credits per second from here: x0 - f / df
+    pub cps: Option<f64>,
+    // the most chosen successor, and how often each was chosen
+    pub successor: Option<String>,
+    pub successors: BTreeMap<String, usize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExportedEdgeType {
+    Decision { repeats: u32 },
+    // weight, summed over collapsed edges, and as a share of the node's total weight
+    Probability { weight: f64, probability: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedEdge {
+    pub from: String,
+    pub to: String,
+    pub edge_type: ExportedEdgeType,
+    // credits and seconds, averaged over collapsed edges
+    pub value: f64,
+    pub duration: f64,
+    pub count: usize,
+    // how many of the collapsed edges are their node's chosen successor
+    pub chosen: usize,
+}
+
+lazy_static! {
+    static ref SURVEY_REGEX: Regex = Regex::new(r"^(?P<prefix>(extract_)?survey_)\d+$").unwrap();
+}
+
+fn collapsed_name(name: &str, options: &ExportOptions) -> String {
+    if options.collapse_surveys {
+        if let Some(captures) = SURVEY_REGEX.captures(name) {
+            return format!("{}*", &captures["prefix"]);
+        }
+    }
+    name.to_string()
+}
+
+impl PreparedGraph {
+    pub fn export(&self, options: &ExportOptions) -> ExportedGraph {
+        // nodes, in the order the edges first mention them
+        let mut order: Vec<String> = vec![];
+        let mut members: HashMap<String, Vec<&str>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for name in self.edges.iter().flat_map(|(from, to, _)| [from, to]) {
+            if !seen.insert(name) {
+                continue;
+            }
+            let collapsed = collapsed_name(name, options);
+            members
+                .entry(collapsed.clone())
+                .or_insert_with(|| {
+                    order.push(collapsed);
+                    vec![]
+                })
+                .push(name);
+        }
+
+        let nodes = order
+            .iter()
+            .map(|name| {
+                let members = &members[name];
+                let states: Vec<_> = members.iter().filter_map(|m| self.state.get(*m)).collect();
+                let fx = (!states.is_empty()).then(|| {
+                    let n = states.len() as f64;
+                    let sum = states
+                        .iter()
+                        .fold((0.0, 0.0), |s, state| (s.0 + state.fx.0, s.1 + state.fx.1));
+                    (sum.0 / n, sum.1 / n)
+                });
+                let mut successors = BTreeMap::new();
+                for state in states.iter() {
+                    if let Some(successor) = &state.successor {
+                        *successors
+                            .entry(collapsed_name(successor, options))
+                            .or_insert(0) += 1;
+                    }
+                }
+                let successor = successors
+                    .iter()
+                    .max_by_key(|(_, &count)| count)
+                    .map(|(name, _)| name.clone());
+                ExportedNode {
+                    name: name.clone(),
+                    count: members.len(),
+                    fx,
+                    cps: fx.filter(|fx| fx.1 != 0.0).map(|fx| self.x0 - fx.0 / fx.1),
+                    successor,
+                    successors,
+                }
+            })
+            .collect();
+
+        // sum up edges between the same (collapsed) nodes
+        let mut total_weight: HashMap<&str, f64> = HashMap::new();
+        for (from, _, edge) in self.edges.iter() {
+            if let EdgeType::Probability(weight) = edge.edge_type {
+                *total_weight.entry(from.as_str()).or_insert(0.0) += weight;
+            }
+        }
+        let mut keys = vec![];
+        let mut sums: HashMap<(String, String, bool), ExportedEdge> = HashMap::new();
+        for (from, to, edge) in self.edges.iter() {
+            let is_decision = matches!(edge.edge_type, EdgeType::Decision(_));
+            let key = (
+                collapsed_name(from, options),
+                collapsed_name(to, options),
+                is_decision,
+            );
+            let chosen = self.state.get(from).and_then(|s| s.successor.as_ref()) == Some(to);
+            let sum = sums.entry(key.clone()).or_insert_with(|| {
+                keys.push(key.clone());
+                let edge_type = match edge.edge_type {
+                    EdgeType::Decision(repeats) => ExportedEdgeType::Decision { repeats },
+                    EdgeType::Probability(_) => ExportedEdgeType::Probability {
+                        weight: 0.0,
+                        probability: 0.0,
+                    },
+                };
+                ExportedEdge {
+                    from: key.0.clone(),
+                    to: key.1.clone(),
+                    edge_type,
+                    value: 0.0,
+                    duration: 0.0,
+                    count: 0,
+                    chosen: 0,
+                }
+            });
+            sum.value += edge.metric.0;
+            sum.duration += edge.metric.1;
+            sum.count += 1;
+            sum.chosen += chosen as usize;
+            if let (
+                EdgeType::Probability(w),
+                ExportedEdgeType::Probability {
+                    weight,
+                    probability,
+                },
+            ) = (edge.edge_type, &mut sum.edge_type)
+            {
+                *weight += w;
+                *probability += w / total_weight[from.as_str()];
+            }
+        }
+        let edges = keys
+            .into_iter()
+            .map(|key| {
+                let mut edge = sums.remove(&key).unwrap();
+                edge.value /= edge.count as f64;
+                edge.duration /= edge.count as f64;
+                // mean over the collapsed nodes, counting those without the edge
+                if let ExportedEdgeType::Probability { probability, .. } = &mut edge.edge_type {
+                    *probability /= members[&key.0].len() as f64;
+                }
+                edge
+            })
+            .collect();
+
+        ExportedGraph {
+            x0: self.x0,
+            nodes,
+            edges,
+        }
+    }
+
+    pub fn to_json(&self, options: &ExportOptions) -> String {
+        serde_json::to_string_pretty(&self.export(options)).unwrap()
+    }
+
+    /// Graphviz, eg. `dot -Tsvg mining.dot > mining.svg`. Chosen edges are bold
+    pub fn to_dot(&self, options: &ExportOptions) -> String {
+        let graph = self.export(options);
+        let mut dot = String::new();
+        writeln!(dot, "digraph mining {{").unwrap();
+        writeln!(dot, "  label=\"x0 = {:.4} cps\";", graph.x0).unwrap();
+        writeln!(dot, "  node [fontname=\"monospace\"];").unwrap();
+
+        let probability_nodes: Vec<&str> = graph
+            .edges
+            .iter()
+            .filter(|e| matches!(e.edge_type, ExportedEdgeType::Probability { .. }))
+            .map(|e| e.from.as_str())
+            .collect();
+        for node in graph.nodes.iter() {
+            let mut label = node.name.clone();
+            if node.count > 1 {
+                write!(label, " (x{})", node.count).unwrap();
+            }
+            if let Some(fx) = node.fx {
+                write!(label, "\\nfx = ({:.2}, {:.2})", fx.0, fx.1).unwrap();
+            }
+            if let Some(cps) = node.cps {
+                write!(label, "\\n{:.4} cps", cps).unwrap();
+            }
+            if node.successors.len() > 1 {
+                for (successor, count) in node.successors.iter() {
+                    write!(label, "\\n-> {}: {}", successor, count).unwrap();
+                }
+            }
+            let shape = match probability_nodes.contains(&node.name.as_str()) {
+                true => "ellipse",
+                false => "box",
+            };
+            writeln!(
+                dot,
+                "  \"{}\" [shape={}, label=\"{}\"];",
+                node.name, shape, label
+            )
+            .unwrap();
+        }
+
+        for edge in graph.edges.iter() {
+            let mut label = format!("{:.2}c / {:.2}s", edge.value, edge.duration);
+            match edge.edge_type {
+                ExportedEdgeType::Decision { repeats } if repeats > 1 => {
+                    write!(label, "\\nx{}", repeats).unwrap()
+                }
+                ExportedEdgeType::Decision { .. } => {}
+                ExportedEdgeType::Probability { probability, .. } => {
+                    write!(label, "\\np = {:.3}", probability).unwrap()
+                }
+            }
+            if edge.count > 1 {
+                write!(label, "\\n{} edges, {} chosen", edge.count, edge.chosen).unwrap();
+            }
+            let style = match edge.chosen > 0 {
+                true => ", style=bold",
+                false => "",
+            };
+            writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{}\"{}];",
+                edge.from, edge.to, label, style
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decision_tree::{DecisionGraphBuilder, Metric};
+
+    fn graph() -> PreparedGraph {
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .decision("start", "extract", Metric(0.0, 0.0))
+            .probability("extract", "cargo_A", Metric(0.0, 10.0), 3.0)
+            .probability("extract", "cargo_B", Metric(0.0, 10.0), 1.0)
+            .decision("cargo_A", "sell_M", Metric(100.0, 5.0))
+            .decision("cargo_A", "finish", Metric(0.0, 0.0))
+            .decision("cargo_B", "finish", Metric(0.0, 0.0))
+            .decision("sell_M", "finish", Metric(0.0, 0.0))
+            .decision("start", "survey", Metric(0.0, 0.0));
+        for (i, cargo) in ["cargo_A", "cargo_B"].iter().enumerate() {
+            let survey = format!("survey_{}", i);
+            let extract_survey = format!("extract_survey_{}", i);
+            builder
+                .probability("survey", &survey, Metric(0.0, 30.0), 1.0)
+                .decision(&survey, "finish", Metric(0.0, 0.0))
+                .repeated_decision(&survey, &extract_survey, Metric(0.0, 0.0), 10)
+                .probability(&extract_survey, cargo, Metric(0.0, 10.0), 1.0);
+        }
+        PreparedGraph::new(builder)
+    }
+
+    #[test]
+    fn test_export() {
+        let g = graph();
+        let exported = g.export(&ExportOptions::default());
+        assert_eq!(exported.nodes.len(), g.nodes.len());
+        let cargo_a = exported.nodes.iter().find(|n| n.name == "cargo_A").unwrap();
+        assert_eq!(cargo_a.successor.as_deref(), Some("sell_M"));
+        assert_eq!(cargo_a.count, 1);
+        let extract_a = (exported.edges.iter())
+            .find(|e| e.from == "extract" && e.to == "cargo_A")
+            .unwrap();
+        assert_eq!(
+            extract_a.edge_type,
+            ExportedEdgeType::Probability {
+                weight: 3.0,
+                probability: 0.75
+            }
+        );
+
+        let json = g.to_json(&ExportOptions::default());
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["nodes"][2]["name"], "cargo_A");
+        assert_eq!(parsed["nodes"][2]["successor"], "sell_M");
+        assert_eq!(
+            parsed["edges"][1]["edge_type"]["Probability"]["probability"],
+            0.75
+        );
+    }
+
+    #[test]
+    fn test_export_collapsed() {
+        let g = graph();
+        let options = ExportOptions {
+            collapse_surveys: true,
+        };
+        let exported = g.export(&options);
+        let names: Vec<&str> = exported.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert!(names.contains(&"survey_*"));
+        assert!(names.contains(&"extract_survey_*"));
+        assert!(!names.contains(&"survey_0"));
+
+        let surveys = exported
+            .nodes
+            .iter()
+            .find(|n| n.name == "survey_*")
+            .unwrap();
+        assert_eq!(surveys.count, 2);
+        // only the survey with cargo_A is worth extracting
+        assert_eq!(
+            surveys.successors,
+            BTreeMap::from([("extract_survey_*".into(), 1), ("finish".into(), 1)])
+        );
+        let edge = (exported.edges.iter())
+            .find(|e| e.from == "survey" && e.to == "survey_*")
+            .unwrap();
+        assert_eq!(edge.count, 2);
+        assert_eq!(
+            edge.edge_type,
+            ExportedEdgeType::Probability {
+                weight: 2.0,
+                probability: 1.0
+            }
+        );
+
+        let dot = g.to_dot(&options);
+        assert!(dot.starts_with("digraph mining {"));
+        assert!(dot.contains("\"survey_*\" [shape=box, label=\"survey_* (x2)"));
+        assert!(dot.contains("\"cargo_A\" -> \"sell_M\" [label=\"100.00c / 5.00s\", style=bold];"));
+        assert!(dot.contains("\"cargo_A\" -> \"finish\" [label=\"0.00c / 0.00s\"];"));
+    }
+}
@@ -1,10 +1,13 @@
-use crate::decision_tree::{self, evaluate, Edge, EdgeType, Metric};
+use crate::decision_tree::{
+    self, DecisionGraph, DecisionGraphBuilder, Edge, EvaluateOptions, Metric, Reevaluation,
+};
 use crate::models::*;
-use crate::runtime::Step;
+use crate::runtime::{Resource, Step, StepOutcome};
+use crate::scripts::graph_export::ExportOptions;
 use crate::{controller::Controller, util};
 use async_trait::async_trait;
-use graph_builder::{DirectedCsrGraph, GraphBuilder};
-use log::debug;
+use graph_builder::DirectedCsrGraph;
+use log::{debug, warn};
 use rand::prelude::*;
 use rand::Rng;
 use regex::Regex;
@@ -28,19 +31,73 @@ pub struct MiningExecutor {
     pub ship_symbol: String,
     pub ship_arc: Arc<AsyncRwLock<Ship>>,
     pub asteroid_symbol: String,
-    pub graph: PreparedGraph,
+    // swapped whole for a re-evaluated graph
+    pub graph: std::sync::RwLock<Arc<PreparedGraph>>,
+    // the markets the graph was last evaluated with, from Controller::markets
+    markets: std::sync::Mutex<HashMap<String, Arc<Market>>>,
 }
 impl MiningExecutor {
+    // re-evaluate the graph with any markets refreshed since. Polled at the start of each
+    // step: a refresh puts a new Arc in Controller::markets, so a changed market is one that
+    // isn't the same Arc as last time
+    async fn update_markets(&self, mining_strength: f64) {
+        let changed: Vec<Arc<Market>> = (self.markets.lock().unwrap().iter())
+            .filter_map(|(symbol, market)| {
+                let latest = self.par.markets.get(symbol)?;
+                (!Arc::ptr_eq(latest.value(), market)).then(|| latest.value().clone())
+            })
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        let asteroid_symbol = self.asteroid_symbol.clone();
+        let mut graph = self.graph.read().unwrap().clone();
+        let markets = changed.clone();
+        // rebuilding is cpu bound: off the executor threads
+        let rebuild = tokio::task::spawn_blocking(move || {
+            for market in markets {
+                let before = graph.x0;
+                let (updated, reevaluation) =
+                    graph.with_market(&market, &asteroid_symbol, mining_strength);
+                debug!(
+                    "Market {} changed: {} -> {} cps, recomputing {} nodes{}",
+                    market.symbol,
+                    before,
+                    updated.x0,
+                    reevaluation.recomputed,
+                    match reevaluation.partial {
+                        true => "",
+                        false => " (the rate moved)",
+                    }
+                );
+                graph = Arc::new(updated);
+            }
+            graph
+        });
+        match rebuild.await {
+            Ok(graph) => {
+                *self.graph.write().unwrap() = graph;
+                // only now: after a failure, the next step tries these markets again
+                let mut markets = self.markets.lock().unwrap();
+                for market in changed {
+                    markets.insert(market.symbol.clone(), market);
+                }
+            }
+            Err(e) => warn!("Re-evaluating the mining graph failed: {}", e),
+        }
+    }
+
     fn judge(&self, survey: &Survey) -> bool {
         use graph_builder::DirectedNeighborsWithValues as _;
 
-        // we are at a transient decision node in the decision tree like survey_x, which leads to extract_survey_x, or discard_survey_x
+        // we are at a transient decision node in the decision tree like survey_x, which leads to extract_survey_x, or finish
         // extract_survey_x is a transient probability node which leads to cargo_{symbol} for each deposit
 
         // steal the 'extract' duration weight
-        let extract_survey_0_idx = self.graph.nodes["extract_survey_0"];
-        let example_edge = self
-            .graph
+        let graph = self.graph.read().unwrap();
+        let extract_survey_0_idx = graph.nodes["extract_survey_0"];
+        let example_edge = graph
             .graph
             .out_neighbors_with_values(extract_survey_0_idx)
             .next()
@@ -48,74 +105,81 @@ impl MiningExecutor {
             .value;
         let extract_duration = example_edge.metric.1;
 
-        let (f_b, df_b) = {
-            // extract_survey_x
-            let mut sum = (0.0, 0.0);
-            let mut weight_sum = 0.0;
-            for t in survey.deposits.iter() {
-                let y = format!("cargo_{}", &t.symbol);
-                let edge = Metric(0.0, extract_duration);
-                let edge_weight = 1.0;
-                let (g, dg) = self.graph.state[&y].fx;
-                let f = g + (edge.0 - self.graph.x0 * edge.1);
-                let df = dg - edge.1;
-                sum.0 += f * edge_weight;
-                sum.1 += df * edge_weight;
-                weight_sum += edge_weight;
-            }
-            (sum.0 / weight_sum, sum.1 / weight_sum)
-        };
-        // debug!("fB: {:?} dfB: {:?}", f_b, df_b);
+        let mut subtree = DecisionGraphBuilder::new();
+        subtree.repeated_decision(
+            "survey_x",
+            "extract_survey_x",
+            Metric(0.0, 0.0),
+            EXPECTED_NUM_EXTRACTS,
+        );
+        subtree.decision("survey_x", "finish", Metric(0.0, 0.0));
+        for deposit in survey.deposits.iter() {
+            subtree.probability(
+                "extract_survey_x",
+                &format!("cargo_{}", deposit.symbol),
+                Metric(0.0, extract_duration),
+                1.0,
+            );
+        }
+        let state = graph.hypothetical(&subtree, "survey_x");
+        let (f_b, df_b) = state["extract_survey_x"].fx;
         debug!(
             "Survey judge: {:?} cps over {} seconds",
-            self.graph.x0 - f_b / df_b,
+            graph.x0 - f_b / df_b,
             -df_b
         );
+        state["survey_x"].successor.as_deref() == Some("extract_survey_x")
+    }
+}
 
-        let mut successor = None;
-        let (_f_a, _df_a) = {
-            let edges = vec![
-                (
-                    Edge::new_repeatable_decision(Metric(0.0, 0.0), EXPECTED_NUM_EXTRACTS),
-                    (f_b, df_b),
-                ),
-                (
-                    Edge::new_decision(Metric(0.0, 0.0)),
-                    self.graph.state["finish"].fx,
-                ),
-            ];
-
-            let mut max = (f64::MIN, f64::MIN);
-            for (idx, &t) in edges.iter().enumerate() {
-                let edge = t.0.metric;
-                let repeats = match t.0.edge_type {
-                    EdgeType::Decision(repeats) => repeats,
-                    _ => panic!(),
-                } as f64;
-                let (g, dg) = t.1;
-                let f = repeats * g + (edge.0 - self.graph.x0 * edge.1);
-                let df = repeats * dg - edge.1;
-                if f > max.0 || f == max.0 && df > max.1 {
-                    max.0 = f;
-                    max.1 = df;
-                    successor = Some(idx);
-                }
-            }
-            max
-        };
-        // debug!("fA: {:?} dfA: {:?} successor: {:?}", fA, dfA, successor);
-        match successor.unwrap() {
-            0 => true,
-            1 => false,
-            _ => panic!(),
-        }
+// the sell edges for full and stripped cargo of `symbol`, if `market` buys it
+fn sell_metrics(
+    market: &Market,
+    symbol: &str,
+    asteroid_field_symbol: &str,
+    mining_strength: f64,
+) -> Option<(Metric, Metric)> {
+    let unit_sell_price = market
+        .trade_goods
+        .iter()
+        .find(|g| g.symbol == symbol)?
+        .sell_price;
+    let mut duration = 0.0;
+    let mut profit = unit_sell_price as f64 * mining_strength;
+    let mut profit_stripped = unit_sell_price as f64 * mining_strength / 2.0;
+    if market.symbol != asteroid_field_symbol {
+        duration += 10.0; // crude estimate of travel and return time
+        profit -= 50.0; // crude estimate of fuel cost
+        profit_stripped -= 50.0;
+    }
+    Some((Metric(profit, duration), Metric(profit_stripped, duration)))
+}
+
+fn mining_strength(ship_mounts: &[ShipMount]) -> f64 {
+    ship_mounts
+        .iter()
+        .filter(|m| m.symbol.starts_with("MOUNT_MINING_LASER_"))
+        .map(|m| m.strength.unwrap() as f64)
+        .sum()
+}
+
+fn cargo_state(ship: &Ship) -> String {
+    let item = &ship.cargo.inventory[0];
+    if item.units >= 20 {
+        // @@ should be tied to mining laser strength
+        format!("cargo_{}", item.symbol)
+    } else {
+        format!("cargo_{}_stripped", item.symbol)
     }
 }
 
 #[async_trait]
 impl Step for MiningExecutor {
-    async fn step(&self) -> Option<Duration> {
+    async fn step(&self) -> StepOutcome {
         // identify mining state
+        // not holding the ship across the rebuild
+        let strength = mining_strength(&self.ship_arc.read().await.mounts);
+        self.update_markets(strength).await;
         let ship = self.ship_arc.read().await;
 
         // Work out our current state at the start of the step
@@ -130,7 +194,7 @@ impl Step for MiningExecutor {
                 .or_insert(vec![])
                 .clone();
             for survey in surveys.iter() {
-                if survey.inner().expiration < chrono::Utc::now() {
+                if survey.inner().expiration < self.par.clock.utc() {
                     continue;
                 }
                 let usuable = self.judge(survey.inner());
@@ -150,43 +214,43 @@ impl Step for MiningExecutor {
             }
         } else {
             debug!("Holding cargo: {:?}", ship.cargo);
-            let item = &ship.cargo.inventory[0];
-            if item.units >= 20 {
-                // @@ should be tied to mining laser strength
-                format!("cargo_{}", item.symbol)
-            } else {
-                format!("cargo_{}_stripped", item.symbol)
-            }
+            cargo_state(&ship)
         };
         debug!("Mining state: {}", state);
 
         let successor = match state.as_str() {
             "survey_x" => Some("extract_survey_x".into()),
-            _ => self.graph.state[&state].successor.clone(),
+            _ => self.graph.read().unwrap().state[&state].successor.clone(),
         };
         drop(ship);
 
         debug!("Successor: {:?}", successor);
         match &successor.as_deref() {
             Some("survey") => {
-                let mut ship_controller = self.par.ship_controller(&self.ship_symbol).await;
+                let mut ship_controller = match self.par.ship_controller(&self.ship_symbol).await {
+                    Ok(ship_controller) => ship_controller,
+                    Err(e) => return StepOutcome::Failed(e.to_string()),
+                };
                 ship_controller.navigate(&self.asteroid_symbol).await;
                 if let Some(cooldown) = ship_controller.navigation_cooldown() {
-                    return Some(cooldown);
+                    return StepOutcome::After(cooldown);
                 }
                 if let Some(cooldown) = ship_controller.reactor_cooldown() {
-                    return Some(cooldown);
+                    return StepOutcome::After(cooldown);
                 }
                 ship_controller.survey().await;
             }
             Some("extract_survey_x") => {
-                let mut ship_controller = self.par.ship_controller(&self.ship_symbol).await;
+                let mut ship_controller = match self.par.ship_controller(&self.ship_symbol).await {
+                    Ok(ship_controller) => ship_controller,
+                    Err(e) => return StepOutcome::Failed(e.to_string()),
+                };
                 ship_controller.navigate(&self.asteroid_symbol).await;
                 if let Some(cooldown) = ship_controller.navigation_cooldown() {
-                    return Some(cooldown);
+                    return StepOutcome::After(cooldown);
                 }
                 if let Some(cooldown) = ship_controller.reactor_cooldown() {
-                    return Some(cooldown);
+                    return StepOutcome::After(cooldown);
                 }
                 ship_controller.extract_survey(&usable_surveys[0]).await;
             }
@@ -198,10 +262,14 @@ impl Step for MiningExecutor {
                 // check if s matches sell regex:
                 if let Some(captures) = SELL_REGEX.captures(s) {
                     let market_symbol = captures.name("market").unwrap().as_str();
-                    let mut ship_controller = self.par.ship_controller(&self.ship_symbol).await;
+                    let mut ship_controller =
+                        match self.par.ship_controller(&self.ship_symbol).await {
+                            Ok(ship_controller) => ship_controller,
+                            Err(e) => return StepOutcome::Failed(e.to_string()),
+                        };
                     ship_controller.navigate(market_symbol).await;
                     if let Some(cooldown) = ship_controller.navigation_cooldown() {
-                        return Some(cooldown);
+                        return StepOutcome::After(cooldown);
                     }
                     let item = ship_controller.ship.cargo.inventory[0].clone();
                     ship_controller.sell(&item.symbol, item.units).await;
@@ -213,7 +281,26 @@ impl Step for MiningExecutor {
                 panic!("Unexpected successor: {:?}", successor);
             }
         };
-        Some(Duration::from_secs(0))
+        StepOutcome::After(Duration::from_secs(0))
+    }
+
+    fn resources(&self) -> Vec<Resource> {
+        // claimed from the runtime's loop: never block here
+        let selling = match (self.ship_arc.try_read(), self.graph.try_read()) {
+            (Ok(ship), Ok(graph)) => graph.selling_at(&ship),
+            _ => None,
+        };
+        match selling {
+            // only selling: other ships can extract at the field meanwhile
+            Some(market) => vec![
+                Resource::Ship(self.ship_symbol.clone()),
+                Resource::Market(market),
+            ],
+            None => vec![
+                Resource::Ship(self.ship_symbol.clone()),
+                Resource::Surveys(self.asteroid_symbol.clone()),
+            ],
+        }
     }
 }
 
@@ -256,10 +343,18 @@ impl MiningController {
 
         // 2. load markets
         let mut markets: Vec<Market> = vec![];
+        let mut market_arcs = HashMap::new();
         for waypoint in waypoints.iter() {
             if util::is_market(waypoint) {
                 let market = self.par.db_client.load_market(&waypoint.symbol).await;
-                markets.push(market);
+                let market = self
+                    .par
+                    .markets
+                    .entry(market.symbol.clone())
+                    .or_insert_with(|| Arc::new(market))
+                    .clone();
+                markets.push((*market).clone());
+                market_arcs.insert(market.symbol.clone(), market);
             }
         }
 
@@ -287,12 +382,27 @@ impl MiningController {
             -g.state["survey"].fx.1
         );
 
+        // eg. MINING_GRAPH_DIR=graphs, then dot -Tsvg graphs/SHIP.dot > SHIP.svg
+        if let Ok(dir) = std::env::var("MINING_GRAPH_DIR") {
+            let options = ExportOptions {
+                collapse_surveys: true,
+            };
+            let path = std::path::Path::new(&dir).join(&ship.symbol);
+            let written = std::fs::write(path.with_extension("dot"), g.to_dot(&options))
+                .and_then(|_| std::fs::write(path.with_extension("json"), g.to_json(&options)));
+            match written {
+                Ok(()) => debug!("Exported mining graph to {}", path.display()),
+                Err(e) => warn!("Failed to export mining graph to {}: {}", dir, e),
+            }
+        }
+
         let e = MiningExecutor {
             par: self.par.clone(),
             ship_symbol: ship.symbol.clone(),
             ship_arc: self.ship_arc.clone(),
             asteroid_symbol: self.asteroid_symbol.clone(),
-            graph: g,
+            graph: std::sync::RwLock::new(Arc::new(g)),
+            markets: std::sync::Mutex::new(market_arcs),
         };
         drop(ship);
         e
@@ -300,13 +410,13 @@ impl MiningController {
 
     pub fn mining_prep(
         asteroid_field_symbol: &str,
-        asteroid_field_traits: &Vec<String>,
-        markets: &Vec<Market>,
-        ship_mounts: &Vec<ShipMount>,
+        asteroid_field_traits: &[String],
+        markets: &[Market],
+        ship_mounts: &[ShipMount],
     ) -> PreparedGraph {
         // construct decision tree
 
-        let mut edges: Vec<(String, String, Edge<Metric>)> = vec![];
+        let mut builder = DecisionGraphBuilder::new();
 
         let deposits = asteroid_yields(asteroid_field_traits);
         let is_stripped = asteroid_field_traits.contains(&"STRIPPED".to_string());
@@ -317,11 +427,10 @@ impl MiningController {
         let mut surveyor_cooldown: f64 = 60.0;
         let mut surveyors: Vec<_> = vec![];
         let mut extract_cooldown: f64 = 60.0;
-        let mut mining_strength: f64 = 0.0;
+        let mining_strength = mining_strength(ship_mounts);
         for mount in ship_mounts {
             if mount.symbol.starts_with("MOUNT_MINING_LASER_") {
                 extract_cooldown += 10.0 * mount.requirements.power as f64;
-                mining_strength += mount.strength.unwrap() as f64;
             }
             if mount.symbol.starts_with("MOUNT_SURVEYOR_") {
                 surveyor_cooldown += 10.0 * mount.requirements.power as f64;
@@ -338,82 +447,43 @@ impl MiningController {
         }
         let surveys_per_operation = surveyors.iter().map(|(strength, _)| *strength).sum::<u32>();
 
-        edges.push((
-            "start".into(),
-            "extract".into(),
-            Edge::new_decision(Metric(0.0, 0.0)),
-        ));
+        builder.decision("start", "extract", Metric(0.0, 0.0));
         // extract edges
         for (symbol, &weight) in deposits.iter() {
             let node = match is_stripped {
                 true => format!("cargo_{}_stripped", symbol),
                 false => format!("cargo_{}", symbol),
             };
-            edges.push((
-                "extract".into(),
-                node,
-                Edge::new_probability(Metric(0.0, extract_cooldown), weight as f64),
-            ));
+            builder.probability(
+                "extract",
+                &node,
+                Metric(0.0, extract_cooldown),
+                weight as f64,
+            );
         }
         // sell + jettison edges
         for (&symbol, _weight) in deposits.iter() {
             let cargo_node = format!("cargo_{}", symbol);
             let cargo_node_stripped = format!("cargo_{}_stripped", symbol);
             // jettison
-            edges.push((
-                cargo_node.clone(),
-                "finish".into(),
-                Edge::new_decision(Metric(0.0, 0.0)),
-            ));
-            edges.push((
-                cargo_node_stripped.clone(),
-                "finish".into(),
-                Edge::new_decision(Metric(0.0, 0.0)),
-            ));
+            builder.decision(&cargo_node, "finish", Metric(0.0, 0.0));
+            builder.decision(&cargo_node_stripped, "finish", Metric(0.0, 0.0));
 
             // sell
             for market in markets.iter() {
                 let sell_node = format!("sell_{}", market.symbol);
-                let sell_price = market
-                    .trade_goods
-                    .iter()
-                    .find(|g| g.symbol == symbol)
-                    .map(|g| g.sell_price);
-                if let Some(unit_sell_price) = sell_price {
-                    let mut duration = 0.0;
-                    let mut profit = unit_sell_price as f64 * mining_strength;
-                    let mut profit_stripped = unit_sell_price as f64 * mining_strength / 2.0;
-                    if market.symbol != asteroid_field_symbol {
-                        duration += 10.0; // crude estimate of travel and return time
-                        profit -= 50.0; // crude estimate of fuel cost
-                        profit_stripped -= 50.0;
-                    }
-                    edges.push((
-                        cargo_node.clone(),
-                        sell_node.clone(),
-                        Edge::new_decision(Metric(profit, duration)),
-                    ));
-                    edges.push((
-                        cargo_node_stripped.clone(),
-                        sell_node.clone(),
-                        Edge::new_decision(Metric(profit_stripped, duration)),
-                    ));
+                let metrics = sell_metrics(market, symbol, asteroid_field_symbol, mining_strength);
+                if let Some((metric, metric_stripped)) = metrics {
+                    builder.decision(&cargo_node, &sell_node, metric);
+                    builder.decision(&cargo_node_stripped, &sell_node, metric_stripped);
+                    // mark sell_node as a terminal node
+                    builder.decision(&sell_node, "finish", Metric(0.0, 0.0));
                 }
-                // mark sell_node as a terminal node
-                edges.push((
-                    sell_node,
-                    "finish".into(),
-                    Edge::new_decision(Metric(0.0, 0.0)),
-                ));
             }
         }
 
         // survey edges
-        edges.push((
-            "start".into(),
-            "survey".into(),
-            Edge::new_decision(Metric(0.0, 0.0)),
-        ));
+        builder.decision("start", "survey", Metric(0.0, 0.0));
 
         // for the probability edges, there are too many combinations to fully enumerate,
         // so we'll generate a sample of 10k, and that should be good enough to accurately calculate rate,
@@ -446,85 +516,164 @@ impl MiningController {
             let survey_node = format!("survey_{}", survey_idx);
             let extract_survey_node = format!("extract_survey_{}", survey_idx);
             let duration = surveyor_cooldown / (surveys_per_operation as f64);
-            edges.push((
-                "survey".into(),
-                survey_node.clone(),
-                Edge::new_probability(Metric(0.0, duration), 1.0),
-            ));
-            edges.push((
-                survey_node.clone(),
-                "finish".into(),
-                Edge::new_decision(Metric(0.0, 0.0)),
-            ));
-            edges.push((
-                survey_node.clone(),
-                extract_survey_node.clone(),
-                Edge::new_repeatable_decision(Metric(0.0, 0.0), EXPECTED_NUM_EXTRACTS),
-            ));
+            builder.probability("survey", &survey_node, Metric(0.0, duration), 1.0);
+            builder.decision(&survey_node, "finish", Metric(0.0, 0.0));
+            builder.repeated_decision(
+                &survey_node,
+                &extract_survey_node,
+                Metric(0.0, 0.0),
+                EXPECTED_NUM_EXTRACTS,
+            );
             for deposit in survey.iter() {
-                edges.push((
-                    extract_survey_node.clone(),
-                    format!("cargo_{}", deposit),
-                    Edge::new_probability(Metric(0.0, extract_cooldown), 1.0),
-                ));
+                builder.probability(
+                    &extract_survey_node,
+                    &format!("cargo_{}", deposit),
+                    Metric(0.0, extract_cooldown),
+                    1.0,
+                );
             }
         }
 
-        {
-            let mut edges1: Vec<(usize, usize, Edge<Metric>)> = vec![];
-            let mut nodes: HashMap<String, usize> = HashMap::new();
-            let mut nodes_inv: Vec<String> = vec![];
-            nodes.insert("start".into(), 0);
-            nodes_inv.push("start".into());
-            for (from, to, ref edge) in edges.iter() {
-                let from_idx = match nodes.get(from) {
-                    Some(&idx) => idx,
-                    None => {
-                        let len = nodes.len();
-                        nodes.insert(from.into(), len);
-                        nodes_inv.push(from.into());
-                        len
-                    }
-                };
-                let to_idx = match nodes.get(to) {
-                    Some(&idx) => idx,
-                    None => {
-                        let len = nodes.len();
-                        nodes.insert(to.into(), len);
-                        nodes_inv.push(to.into());
-                        len
-                    }
-                };
-                edges1.push((from_idx, to_idx, *edge));
-            }
+        PreparedGraph::new(builder)
+    }
+}
 
-            let graph: DirectedCsrGraph<usize, (), Edge<Metric>> =
-                GraphBuilder::new().edges_with_values(edges1).build();
-            let g1 = evaluate(&graph, nodes["start"]);
-
-            let mut g = HashMap::new();
-            for (node_name, node_idx) in nodes.iter() {
-                if let Some(entry) = g1.1.get(node_idx) {
-                    let entry1 = decision_tree::State {
-                        fx: entry.fx,
-                        successor: entry.successor.map(|s| nodes_inv[s].clone()),
-                    };
-                    g.insert(node_name.to_string(), entry1);
-                }
+impl PreparedGraph {
+    /// Validates and evaluates the graph, from "start". Mining also enters the graph at the
+    /// cargo_ nodes, when a ship is holding cargo
+    pub fn new(builder: DecisionGraphBuilder) -> Self {
+        let g = builder.build();
+        validate_mining_graph(&g);
+        let evaluation = g.evaluate("start", &EvaluateOptions::default());
+        debug!(
+            "Mining graph: {} cps after {} iterations of {:?}, residual {}",
+            evaluation.x0, evaluation.iterations, evaluation.method, evaluation.residual
+        );
+        if !evaluation.converged {
+            warn!("Mining graph didn't converge: {:?}", evaluation.problems);
+        }
+
+        PreparedGraph {
+            x0: evaluation.x0,
+            state: g.named_state(&evaluation),
+            edges: g.named_edges(),
+            nodes: g.nodes,
+            graph: g.graph,
+        }
+    }
+
+    /// The market the policy sells a ship's cargo at next, if it's holding cargo the graph
+    /// knows, eg. not ore from another asteroid field
+    pub fn selling_at(&self, ship: &Ship) -> Option<String> {
+        if ship.cargo.units == 0 || ship.cargo.inventory.is_empty() {
+            return None;
+        }
+        let successor = self.state.get(&cargo_state(ship))?.successor.as_deref()?;
+        successor.strip_prefix("sell_").map(String::from)
+    }
+
+    /// Replaces the sell edges to `market` with its current prices, keeping the sampled
+    /// surveys, and re-evaluates only as much of the graph as the new prices can move
+    pub fn update_market(
+        &mut self,
+        market: &Market,
+        asteroid_field_symbol: &str,
+        mining_strength: f64,
+    ) -> Reevaluation {
+        let (updated, reevaluation) =
+            self.with_market(market, asteroid_field_symbol, mining_strength);
+        *self = updated;
+        reevaluation
+    }
+
+    /// `update_market`, on a copy
+    pub fn with_market(
+        &self,
+        market: &Market,
+        asteroid_field_symbol: &str,
+        mining_strength: f64,
+    ) -> (PreparedGraph, Reevaluation) {
+        let sell_node = format!("sell_{}", market.symbol);
+        let mut changed: Vec<String> = vec![];
+        let mut builder = DecisionGraphBuilder::new();
+        for (from, to, edge) in self.edges.iter() {
+            if *to == sell_node {
+                changed.push(from.clone());
+            } else if *from != sell_node {
+                builder.edge(from, to, *edge);
             }
-            PreparedGraph {
-                nodes,
-                x0: g1.0,
-                state: g,
-                edges,
-                graph,
+        }
+
+        let mut symbols: Vec<&str> = (self.nodes.keys())
+            .filter_map(|name| name.strip_prefix("cargo_"))
+            .filter(|symbol| !symbol.ends_with("_stripped"))
+            .collect();
+        symbols.sort();
+        let mut buys = false;
+        for symbol in symbols {
+            let metrics = sell_metrics(market, symbol, asteroid_field_symbol, mining_strength);
+            if let Some((metric, metric_stripped)) = metrics {
+                let cargo_node = format!("cargo_{}", symbol);
+                let cargo_node_stripped = format!("cargo_{}_stripped", symbol);
+                builder.decision(&cargo_node, &sell_node, metric);
+                builder.decision(&cargo_node_stripped, &sell_node, metric_stripped);
+                changed.push(cargo_node);
+                changed.push(cargo_node_stripped);
+                buys = true;
             }
         }
+        if buys {
+            builder.decision(&sell_node, "finish", Metric(0.0, 0.0));
+        }
+
+        let g = builder.build();
+        validate_mining_graph(&g);
+        let changed: Vec<&str> = changed.iter().map(|s| s.as_str()).collect();
+        let reevaluation = g.reevaluate(
+            "start",
+            &changed,
+            self.x0,
+            &self.state,
+            &EvaluateOptions::default(),
+        );
+        let evaluation = &reevaluation.evaluation;
+        if !evaluation.converged {
+            warn!("Mining graph didn't converge: {:?}", evaluation.problems);
+        }
+
+        let updated = PreparedGraph {
+            x0: evaluation.x0,
+            state: g.named_state(evaluation),
+            edges: g.named_edges(),
+            nodes: g.nodes,
+            graph: g.graph,
+        };
+        (updated, reevaluation)
+    }
+
+    /// Evaluates `subtree` from `root` at the graph's rate, leading back into the graph's nodes
+    pub fn hypothetical(
+        &self,
+        subtree: &DecisionGraphBuilder,
+        root: &str,
+    ) -> HashMap<String, decision_tree::State<String>> {
+        decision_tree::evaluate_subtree(subtree, root, self.x0, &self.state)
+    }
+}
+
+// the executor also enters the graph at the cargo nodes, holding cargo
+fn validate_mining_graph(g: &DecisionGraph) {
+    let roots: Vec<&str> = (g.names.iter())
+        .filter(|name| *name == "start" || name.starts_with("cargo_"))
+        .map(|name| name.as_str())
+        .collect();
+    if let Err(errors) = g.validate(&roots) {
+        panic!("Invalid mining graph: {}", errors.join(", "));
     }
 }
 
 // get yields for a given set of traits
-fn asteroid_yields(traits: &Vec<String>) -> HashMap<&'static str, usize> {
+fn asteroid_yields(traits: &[String]) -> HashMap<&'static str, usize> {
     let mut s = HashSet::new();
     for trait_name in traits.iter() {
         let yields = TRAIT_YIELDS.get(trait_name.as_str());
@@ -649,3 +798,123 @@ lazy_static::lazy_static! {
         m
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn market(symbol: &str, goods: &[(&str, u32)]) -> Market {
+        Market {
+            symbol: symbol.into(),
+            trade_goods: goods
+                .iter()
+                .map(|&(symbol, sell_price)| MarketTradeGood {
+                    symbol: symbol.into(),
+                    sell_price,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mining_prep() {
+        let markets = vec![
+            market("X1-HY12-60905F", &[("ICE_WATER", 10), ("QUARTZ_SAND", 20)]),
+            market("X1-HY12-A1", &[("QUARTZ_SAND", 25), ("IRON_ORE", 40)]),
+            // buys nothing we mine
+            market("X1-HY12-B2", &[("FUEL", 70)]),
+        ];
+        let g = MiningController::mining_prep(
+            "X1-HY12-60905F",
+            &["MINERAL_DEPOSITS".into()],
+            &markets,
+            &[MOUNT_SURVEYOR_II.clone(), MINING_LASER_II.clone()],
+        );
+        assert!(g.x0 > 0.0);
+        assert!(!g.nodes.contains_key("sell_X1-HY12-B2"));
+        // the stripped cargo nodes are only entered by holding cargo
+        let roots: Vec<usize> = vec![g.nodes["start"]];
+        assert!(decision_tree::validate(&g.graph, &roots).is_err());
+    }
+
+    #[test]
+    fn test_update_market() {
+        let mut markets = vec![
+            market("X1-HY12-60905F", &[("ICE_WATER", 10), ("QUARTZ_SAND", 20)]),
+            market("X1-HY12-A1", &[("QUARTZ_SAND", 25), ("IRON_ORE", 40)]),
+            market("X1-HY12-B2", &[("FUEL", 70)]),
+        ];
+        let mounts = [MOUNT_SURVEYOR_II.clone(), MINING_LASER_II.clone()];
+        let mut g = MiningController::mining_prep(
+            "X1-HY12-60905F",
+            &["MINERAL_DEPOSITS".into()],
+            &markets,
+            &mounts,
+        );
+        let strength = mining_strength(&mounts);
+        let x0 = g.x0;
+
+        // the same prices: the rate holds, and the surveys aren't touched
+        let reevaluation = g.update_market(&markets[1], "X1-HY12-60905F", strength);
+        assert!(reevaluation.partial);
+        assert!(reevaluation.recomputed < g.nodes.len() / 10);
+        assert!((g.x0 - x0).abs() < 1e-9);
+
+        // quartz sand is worth the trip
+        markets[1].trade_goods[0].sell_price = 200;
+        let reevaluation = g.update_market(&markets[1], "X1-HY12-60905F", strength);
+        assert!(!reevaluation.partial);
+        assert!(g.x0 > x0);
+        let full = decision_tree::evaluate(&g.graph, g.nodes["start"]);
+        assert!((g.x0 - full.x0).abs() < 1e-6);
+        assert_eq!(
+            g.state["cargo_QUARTZ_SAND"].successor.as_deref(),
+            Some("sell_X1-HY12-A1")
+        );
+
+        // a market that starts buying gets a sell node, and one that stops loses it
+        markets[2] = market("X1-HY12-B2", &[("QUARTZ_SAND", 400)]);
+        g.update_market(&markets[2], "X1-HY12-60905F", strength);
+        markets[1] = market("X1-HY12-A1", &[]);
+        g.update_market(&markets[1], "X1-HY12-60905F", strength);
+        assert!(!g.nodes.contains_key("sell_X1-HY12-A1"));
+        assert_eq!(
+            g.state["cargo_QUARTZ_SAND"].successor.as_deref(),
+            Some("sell_X1-HY12-B2")
+        );
+    }
+
+    #[test]
+    fn test_selling_at() {
+        let markets = vec![
+            market("X1-HY12-60905F", &[("ICE_WATER", 10), ("QUARTZ_SAND", 20)]),
+            market("X1-HY12-A1", &[("QUARTZ_SAND", 200), ("IRON_ORE", 40)]),
+        ];
+        let g = MiningController::mining_prep(
+            "X1-HY12-60905F",
+            &["MINERAL_DEPOSITS".into()],
+            &markets,
+            &[MOUNT_SURVEYOR_II.clone(), MINING_LASER_II.clone()],
+        );
+        let holding = |symbol: &str, units: u32| Ship {
+            cargo: ShipCargo {
+                capacity: 60,
+                units,
+                inventory: vec![ShipCargoGood {
+                    symbol: symbol.into(),
+                    units,
+                }],
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            g.selling_at(&holding("QUARTZ_SAND", 60)).as_deref(),
+            Some("X1-HY12-A1")
+        );
+        assert_eq!(g.selling_at(&Ship::default()), None);
+        // mined elsewhere: no node for it
+        assert_eq!(g.selling_at(&holding("GOLD_ORE", 60)), None);
+    }
+}
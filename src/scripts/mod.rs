@@ -1,2 +1,7 @@
 pub mod autobuy;
+pub mod graph_export;
 pub mod mining;
+pub mod modules;
+pub mod route;
+pub mod ship;
+pub mod simulate;
@@ -0,0 +1,421 @@
+use crate::controller::Controller;
+use crate::events::CREDITS_KEY;
+use crate::models::*;
+use crate::runtime::{Resource, Step, StepOutcome};
+use crate::shipconfig::ModulesConfig;
+use async_trait::async_trait;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock as AsyncRwLock;
+
+/// The next thing to do to bring a ship's mounts in line with its `ModulesConfig`.
+///
+/// The action is derived from the ship's current mounts and cargo, and the mounts this
+/// reconciliation put in cargo, so a crash at any point is recovered from by simply
+/// planning again: the mounts it loses track of are left in cargo.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModulesAction {
+    /// purchase one unit of `module` at `source`
+    Buy {
+        module: String,
+        source: String,
+    },
+    /// uninstall a mount that isn't in the config
+    Remove {
+        module: String,
+    },
+    /// install a mount that is sitting in cargo
+    Install {
+        module: String,
+    },
+    /// get rid of a mount we removed or bought, and have no use for
+    Dispose {
+        module: String,
+        units: u32,
+    },
+    /// a missing mount with no source to buy it from
+    Unobtainable {
+        module: String,
+    },
+    Done,
+}
+
+/// `carried` is the mounts this reconciliation put in cargo, by removing or buying them:
+/// only those are disposed of, whatever else is in cargo
+pub fn plan(
+    config: &ModulesConfig,
+    mounts: &[ShipMount],
+    cargo: &ShipCargo,
+    carried: &HashMap<String, u32>,
+) -> ModulesAction {
+    // desired and installed are multisets: a ship can carry two of the same laser
+    let mut desired: Vec<(&str, usize)> = vec![];
+    for m in config.modules.iter() {
+        match desired.iter_mut().find(|(s, _)| *s == m.module) {
+            Some((_, n)) => *n += 1,
+            None => desired.push((&m.module, 1)),
+        }
+    }
+    let mut installed: HashMap<&str, usize> = HashMap::new();
+    for m in mounts.iter() {
+        *installed.entry(&m.symbol).or_insert(0) += 1;
+    }
+    let in_cargo = |symbol: &str| -> usize {
+        cargo
+            .inventory
+            .iter()
+            .filter(|g| g.symbol == symbol)
+            .map(|g| g.units as usize)
+            .sum()
+    };
+    let missing: Vec<(&str, usize)> = desired
+        .iter()
+        .map(|&(s, n)| (s, n.saturating_sub(*installed.get(s).unwrap_or(&0))))
+        .filter(|&(_, n)| n > 0)
+        .collect();
+
+    // 1. buy whatever is missing and not already in cargo
+    for &(symbol, n) in missing.iter() {
+        if in_cargo(symbol) >= n {
+            continue;
+        }
+        let source = config
+            .modules
+            .iter()
+            .filter(|m| m.module == symbol)
+            .find_map(|m| m.source.clone());
+        if let Some(source) = source {
+            return ModulesAction::Buy {
+                module: symbol.into(),
+                source,
+            };
+        }
+        // no source: nothing we can do about it
+    }
+
+    // 2. free up slots by removing extras
+    for m in mounts.iter() {
+        let wanted = desired
+            .iter()
+            .find(|(s, _)| *s == m.symbol)
+            .map(|(_, n)| *n)
+            .unwrap_or(0);
+        if installed[m.symbol.as_str()] > wanted {
+            return ModulesAction::Remove {
+                module: m.symbol.clone(),
+            };
+        }
+    }
+
+    // 3. install from cargo
+    for &(symbol, _n) in missing.iter() {
+        if in_cargo(symbol) > 0 {
+            return ModulesAction::Install {
+                module: symbol.into(),
+            };
+        }
+    }
+
+    // 4. left over mounts of ours in cargo (removed extras, or over-purchases)
+    for good in cargo.inventory.iter() {
+        let units = good.units.min(*carried.get(&good.symbol).unwrap_or(&0));
+        if units > 0 {
+            return ModulesAction::Dispose {
+                module: good.symbol.clone(),
+                units,
+            };
+        }
+    }
+
+    // 5. anything still missing can't be bought
+    if let Some(&(symbol, _n)) = missing.iter().find(|&&(s, _)| in_cargo(s) == 0) {
+        return ModulesAction::Unobtainable {
+            module: symbol.into(),
+        };
+    }
+
+    ModulesAction::Done
+}
+
+pub struct ModulesExecutor {
+    pub par: Controller,
+    pub ship_symbol: String,
+    pub ship_arc: Arc<AsyncRwLock<Ship>>,
+    pub config: ModulesConfig,
+    // mounts removed or bought here, and not yet installed
+    carried: std::sync::Mutex<HashMap<String, u32>>,
+}
+
+impl ModulesExecutor {
+    pub fn new(par: &Controller, ship_symbol: &str, config: &ModulesConfig) -> Self {
+        Self {
+            par: par.clone(),
+            ship_symbol: ship_symbol.into(),
+            ship_arc: par.ships.get(ship_symbol).unwrap().clone(),
+            config: config.clone(),
+            carried: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn is_satisfied(&self) -> bool {
+        self.plan().await == ModulesAction::Done
+    }
+
+    async fn plan(&self) -> ModulesAction {
+        let ship = self.ship_arc.read().await;
+        let carried = self.carried.lock().unwrap();
+        plan(&self.config, &ship.mounts, &ship.cargo, &carried)
+    }
+
+    fn carry(&self, module: &str, units: i64) {
+        let mut carried = self.carried.lock().unwrap();
+        let n = carried.entry(module.into()).or_insert(0);
+        *n = (*n as i64 + units).max(0) as u32;
+    }
+}
+
+#[async_trait]
+impl Step for ModulesExecutor {
+    async fn step(&self) -> StepOutcome {
+        let action = self.plan().await;
+        debug!("Modules action: {:?}", action);
+
+        let target = match &action {
+            ModulesAction::Buy { source, .. } => Some(source),
+            ModulesAction::Done => {
+                let ship = self.ship_arc.read().await;
+                let mounts: Vec<&str> = ship.mounts.iter().map(|m| m.symbol.as_str()).collect();
+                debug!("Modules reconciled: {:?}", mounts);
+                return StepOutcome::Done;
+            }
+            ModulesAction::Unobtainable { module } => {
+                return StepOutcome::Failed(format!(
+                    "{} is missing {}, and has no source for it",
+                    self.ship_symbol, module
+                ));
+            }
+            _ => self.config.install_location.as_ref(),
+        };
+
+        let mut ship_controller = match self.par.ship_controller(&self.ship_symbol).await {
+            Ok(ship_controller) => ship_controller,
+            Err(e) => return StepOutcome::Failed(e.to_string()),
+        };
+        if let Some(target) = target {
+            if &ship_controller.ship.nav.waypoint_symbol != target {
+                ship_controller.navigate(target).await;
+            }
+            if let Some(cooldown) = ship_controller.navigation_cooldown() {
+                return StepOutcome::After(cooldown);
+            }
+        }
+
+        match action {
+            ModulesAction::Buy { module, source } => {
+                let price = (self.par.markets.get(&source))
+                    .and_then(|m| m.trade_goods.iter().find(|g| g.symbol == module).cloned())
+                    .map(|g| g.purchase_price as i64);
+                let credits = self.par.agent.lock().unwrap().as_ref().map(|a| a.credits);
+                if let (Some(price), Some(credits)) = (price, credits) {
+                    if credits < price {
+                        debug!(
+                            "Can't afford {} at {}: {} < {}",
+                            module, source, credits, price
+                        );
+                        return StepOutcome::WhenNotified(CREDITS_KEY.into());
+                    }
+                }
+                ship_controller.purchase(&module, 1).await;
+                self.carry(&module, 1);
+            }
+            ModulesAction::Remove { module } => {
+                ship_controller.remove_mount(&module).await;
+                self.carry(&module, 1);
+            }
+            ModulesAction::Install { module } => {
+                ship_controller.install_mount(&module).await;
+                self.carry(&module, -1);
+            }
+            ModulesAction::Dispose { module, units } => {
+                let waypoint_symbol = ship_controller.ship.nav.waypoint_symbol.clone();
+                let sellable = self
+                    .par
+                    .markets
+                    .get(&waypoint_symbol)
+                    .map(|m| m.trade_goods.iter().any(|g| g.symbol == module))
+                    .unwrap_or(false);
+                if sellable {
+                    ship_controller.sell(&module, units).await;
+                } else {
+                    debug!("No market for {} here: keeping it in cargo", module);
+                }
+                self.carry(&module, -(units as i64));
+            }
+            ModulesAction::Done | ModulesAction::Unobtainable { .. } => unreachable!(),
+        }
+        StepOutcome::After(Duration::from_secs(0))
+    }
+
+    fn resources(&self) -> Vec<Resource> {
+        vec![Resource::Ship(self.ship_symbol.clone())]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shipconfig::ModuleConfig;
+
+    fn config() -> ModulesConfig {
+        ModulesConfig {
+            install_location: Some("X1-HY12-22347Z".into()),
+            modules: vec![
+                ModuleConfig {
+                    module: "MOUNT_SURVEYOR_I".into(),
+                    source: None,
+                },
+                ModuleConfig {
+                    module: "MOUNT_MINING_LASER_II".into(),
+                    source: Some("X1-HY12-22347Z".into()),
+                },
+                ModuleConfig {
+                    module: "MOUNT_MINING_LASER_II".into(),
+                    source: Some("X1-HY12-22347Z".into()),
+                },
+            ],
+        }
+    }
+
+    fn mounts(symbols: &[&str]) -> Vec<ShipMount> {
+        symbols
+            .iter()
+            .map(|s| ShipMount {
+                symbol: s.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn cargo(goods: &[(&str, u32)]) -> ShipCargo {
+        ShipCargo {
+            capacity: 60,
+            units: goods.iter().map(|g| g.1).sum(),
+            inventory: goods
+                .iter()
+                .map(|&(symbol, units)| ShipCargoGood {
+                    symbol: symbol.into(),
+                    units,
+                })
+                .collect(),
+        }
+    }
+
+    fn plan(config: &ModulesConfig, mounts: &[ShipMount], cargo: &ShipCargo) -> ModulesAction {
+        super::plan(config, mounts, cargo, &HashMap::new())
+    }
+
+    #[test]
+    fn test_plan_ore_hound() {
+        // starting ore hound: one surveyor, one laser I
+        let installed = mounts(&["MOUNT_SURVEYOR_I", "MOUNT_MINING_LASER_I"]);
+        assert_eq!(
+            plan(&config(), &installed, &cargo(&[])),
+            ModulesAction::Buy {
+                module: "MOUNT_MINING_LASER_II".into(),
+                source: "X1-HY12-22347Z".into()
+            }
+        );
+        // one bought, still need a second
+        assert_eq!(
+            plan(
+                &config(),
+                &installed,
+                &cargo(&[("MOUNT_MINING_LASER_II", 1)])
+            ),
+            ModulesAction::Buy {
+                module: "MOUNT_MINING_LASER_II".into(),
+                source: "X1-HY12-22347Z".into()
+            }
+        );
+        // both bought: remove the extra before installing
+        let bought = cargo(&[("MOUNT_MINING_LASER_II", 2)]);
+        assert_eq!(
+            plan(&config(), &installed, &bought),
+            ModulesAction::Remove {
+                module: "MOUNT_MINING_LASER_I".into()
+            }
+        );
+        let installed = mounts(&["MOUNT_SURVEYOR_I"]);
+        let removed = cargo(&[("MOUNT_MINING_LASER_II", 2), ("MOUNT_MINING_LASER_I", 1)]);
+        assert_eq!(
+            plan(&config(), &installed, &removed),
+            ModulesAction::Install {
+                module: "MOUNT_MINING_LASER_II".into()
+            }
+        );
+        let installed = mounts(&[
+            "MOUNT_SURVEYOR_I",
+            "MOUNT_MINING_LASER_II",
+            "MOUNT_MINING_LASER_II",
+        ]);
+        let removed = HashMap::from([("MOUNT_MINING_LASER_I".to_string(), 1)]);
+        assert_eq!(
+            super::plan(
+                &config(),
+                &installed,
+                &cargo(&[("MOUNT_MINING_LASER_I", 1)]),
+                &removed
+            ),
+            ModulesAction::Dispose {
+                module: "MOUNT_MINING_LASER_I".into(),
+                units: 1
+            }
+        );
+        assert_eq!(
+            plan(&config(), &installed, &cargo(&[])),
+            ModulesAction::Done
+        );
+    }
+
+    #[test]
+    fn test_plan_keeps_cargo() {
+        let installed = mounts(&[
+            "MOUNT_SURVEYOR_I",
+            "MOUNT_MINING_LASER_II",
+            "MOUNT_MINING_LASER_II",
+        ]);
+        // mounts hauled as cargo aren't ours to get rid of
+        let hauled = cargo(&[("MOUNT_MINING_LASER_I", 5), ("MOUNT_MINING_LASER_II", 2)]);
+        assert_eq!(plan(&config(), &installed, &hauled), ModulesAction::Done);
+        // only the one we removed
+        let removed = HashMap::from([("MOUNT_MINING_LASER_I".to_string(), 1)]);
+        assert_eq!(
+            super::plan(&config(), &installed, &hauled, &removed),
+            ModulesAction::Dispose {
+                module: "MOUNT_MINING_LASER_I".into(),
+                units: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_plan_without_source() {
+        // the surveyor has no source, so a ship without one can't be fixed up
+        let installed = mounts(&["MOUNT_MINING_LASER_II", "MOUNT_MINING_LASER_II"]);
+        assert_eq!(
+            plan(&config(), &installed, &cargo(&[])),
+            ModulesAction::Unobtainable {
+                module: "MOUNT_SURVEYOR_I".into()
+            }
+        );
+        // .. unless it happens to be in cargo already
+        assert_eq!(
+            plan(&config(), &installed, &cargo(&[("MOUNT_SURVEYOR_I", 1)])),
+            ModulesAction::Install {
+                module: "MOUNT_SURVEYOR_I".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use crate::controller::Controller;
+use crate::models::*;
+use crate::pathfinding::{Edge, L2Graph, Leg, Route, ShipProfile};
+use crate::runtime::{Resource, Step, StepOutcome};
+use crate::util;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// how much later than planned a leg can arrive before the rest of the route is re-planned
+const ARRIVAL_SLACK_SECS: i64 = 30;
+const ARRIVAL_SLACK_RATIO: f64 = 0.1;
+
+/// How far along its route a ship is
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteProgress {
+    pub route: Route,
+    // legs flown
+    pub next: usize,
+    // when the last leg was planned to arrive
+    pub expected_arrival: Option<DateTime<Utc>>,
+    // at the end of the last leg
+    pub refuelled: bool,
+}
+
+impl RouteProgress {
+    pub fn new(route: Route) -> Self {
+        Self {
+            route,
+            next: 0,
+            expected_arrival: None,
+            refuelled: false,
+        }
+    }
+}
+
+/// The next thing to do to follow a route, from where the ship actually is
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteAction {
+    /// in transit until then
+    Wait(DateTime<Utc>),
+    /// there's no route, or the ship has strayed from it
+    Replan {
+        reason: String,
+    },
+    /// dock and fill up, as planned at the end of the last leg
+    Refuel,
+    Fly(Leg),
+    Arrived,
+}
+
+pub fn next_action(
+    progress: Option<&RouteProgress>,
+    ship: &Ship,
+    now: DateTime<Utc>,
+) -> RouteAction {
+    if ship.nav.status == "IN_TRANSIT" && ship.nav.route.arrival > now {
+        return RouteAction::Wait(ship.nav.route.arrival);
+    }
+    let Some(progress) = progress else {
+        return RouteAction::Replan {
+            reason: "no route".into(),
+        };
+    };
+    let location = &ship.nav.waypoint_symbol;
+    let route = &progress.route;
+
+    if progress.next == 0 {
+        if *location != route.from {
+            return RouteAction::Replan {
+                reason: format!("at {}, not {}", location, route.from),
+            };
+        }
+    } else {
+        let last = &route.legs[progress.next - 1];
+        if *location != last.to {
+            return RouteAction::Replan {
+                reason: format!("at {}, not {}", location, last.to),
+            };
+        }
+        if !progress.refuelled && (ship.fuel.current as i32) < last.fuel_after {
+            return RouteAction::Replan {
+                reason: format!("{} fuel, not {}", ship.fuel.current, last.fuel_after),
+            };
+        }
+        if let Some(expected) = progress.expected_arrival {
+            let slack = ARRIVAL_SLACK_SECS + (last.duration as f64 * ARRIVAL_SLACK_RATIO) as i64;
+            let late = (ship.nav.route.arrival - expected).num_seconds();
+            if late > slack {
+                return RouteAction::Replan {
+                    reason: format!("arrived {}s late", late),
+                };
+            }
+        }
+        if last.refuel && !progress.refuelled {
+            return RouteAction::Refuel;
+        }
+    }
+
+    match route.legs.get(progress.next) {
+        Some(leg) => RouteAction::Fly(leg.clone()),
+        None => RouteAction::Arrived,
+    }
+}
+
+///
+/// RouteExecutor flies a ship to `destination` along a route from the pathfinder, a leg per
+/// step: it sleeps through each leg via the runtime, and re-plans from wherever the ship is
+/// when it isn't where, or when, or with the fuel, the route said it would be
+///
+pub struct RouteExecutor {
+    pub par: Controller,
+    pub ship_symbol: String,
+    pub destination: String,
+    // eg. L2Graph::with_markets, to only plan refuels where there's FUEL
+    graph: Arc<L2Graph>,
+    progress: Mutex<Option<RouteProgress>>,
+}
+
+impl RouteExecutor {
+    pub fn new(
+        par: &Controller,
+        ship_symbol: &str,
+        destination: &str,
+        graph: Arc<L2Graph>,
+    ) -> Self {
+        Self {
+            par: par.clone(),
+            ship_symbol: ship_symbol.into(),
+            destination: destination.into(),
+            graph,
+            progress: Mutex::new(None),
+        }
+    }
+
+    /// Follow `route`, rather than planning one at the first step
+    pub fn with_route(self, route: Route) -> Self {
+        Self {
+            progress: Mutex::new(Some(RouteProgress::new(route))),
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl Step for RouteExecutor {
+    async fn step(&self) -> StepOutcome {
+        let mut ship_controller = match self.par.ship_controller(&self.ship_symbol).await {
+            Ok(ship_controller) => ship_controller,
+            Err(e) => return StepOutcome::Failed(e.to_string()),
+        };
+        let now = self.par.clock.utc();
+        let mut progress = self.progress.lock().await;
+        let action = next_action(progress.as_ref(), &ship_controller.ship, now);
+        debug!("Route action: {:?}", action);
+
+        match action {
+            RouteAction::Wait(arrival) => StepOutcome::At(arrival),
+            RouteAction::Arrived => {
+                debug!("Arrived at {}", self.destination);
+                StepOutcome::Done
+            }
+            RouteAction::Replan { reason } => {
+                let ship = &ship_controller.ship;
+                debug!("Planning a route to {}: {}", self.destination, reason);
+                let profile = ShipProfile::from_ship(ship);
+                let source = &ship.nav.waypoint_symbol;
+                match self.graph.astar(source, &self.destination, &profile) {
+                    Some(route) => {
+                        debug!("{}", route);
+                        *progress = Some(RouteProgress::new(route));
+                        StepOutcome::After(Duration::from_secs(0))
+                    }
+                    None => StepOutcome::Failed(format!(
+                        "No route from {} to {}",
+                        source, self.destination
+                    )),
+                }
+            }
+            RouteAction::Refuel => {
+                // the graph took any market to sell fuel
+                let location = &ship_controller.ship.nav.waypoint_symbol;
+                let sells_fuel = (self.par.markets.get(location))
+                    .is_none_or(|m| m.trade_goods.iter().any(|g| g.symbol == "FUEL"));
+                if !sells_fuel {
+                    debug!("No FUEL at {}: re-planning", location);
+                    *progress = None;
+                    return StepOutcome::After(Duration::from_secs(0));
+                }
+                ship_controller.fill_up().await;
+                progress.as_mut().unwrap().refuelled = true;
+                StepOutcome::After(Duration::from_secs(0))
+            }
+            RouteAction::Fly(leg) => {
+                if let Some(mode) = leg.edge.flight_mode() {
+                    ship_controller.flight_mode(mode.as_str()).await;
+                }
+                match leg.edge {
+                    Edge::Nav(_) => ship_controller.navigate(&leg.to).await,
+                    Edge::Warp(_) => ship_controller.warp(&leg.to).await,
+                    Edge::Jumpgate | Edge::JumpDrive => {
+                        if let Some(cooldown) = ship_controller.reactor_cooldown() {
+                            return StepOutcome::After(cooldown);
+                        }
+                        ship_controller.jump(&util::system_symbol(&leg.to)).await;
+                    }
+                }
+                let progress = progress.as_mut().unwrap();
+                progress.next += 1;
+                progress.refuelled = false;
+                progress.expected_arrival =
+                    Some(now + chrono::Duration::seconds(leg.duration as i64));
+                StepOutcome::At(ship_controller.ship.nav.route.arrival)
+            }
+        }
+    }
+
+    fn resources(&self) -> Vec<Resource> {
+        vec![Resource::Ship(self.ship_symbol.clone())]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clients::fake_api::FakeApi;
+    use crate::clock::{Clock, SimClock};
+    use crate::pathfinding::FlightMode;
+    use crate::runtime::{ItemState, Runtime, StepId};
+    use chrono::TimeZone;
+    use hyper::StatusCode;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn leg(from: &str, to: &str, edge: Edge, duration: i32, fuel_after: i32, refuel: bool) -> Leg {
+        Leg {
+            from: from.into(),
+            to: to.into(),
+            edge,
+            duration,
+            fuel: 0,
+            fuel_after,
+            refuel,
+        }
+    }
+
+    fn route() -> Route {
+        let legs = vec![
+            leg(
+                "X1-A-M",
+                "X1-A-G",
+                Edge::Nav(FlightMode::Burn),
+                28,
+                1400,
+                false,
+            ),
+            leg("X1-A-G", "X1-B-G", Edge::Jumpgate, 100, 1400, false),
+            leg(
+                "X1-B-G",
+                "X1-B-M",
+                Edge::Nav(FlightMode::Burn),
+                30,
+                1280,
+                true,
+            ),
+        ];
+        Route::new("X1-A-M", "X1-B-M", 1500, 1500, legs)
+    }
+
+    fn ship(waypoint: &str, status: &str, arrival: DateTime<Utc>, fuel: u32) -> Ship {
+        Ship {
+            nav: ShipNav {
+                waypoint_symbol: waypoint.into(),
+                status: status.into(),
+                route: ShipNavRoute {
+                    departure_time: arrival,
+                    arrival,
+                },
+                ..Default::default()
+            },
+            fuel: ShipFuel {
+                current: fuel,
+                capacity: 1500,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_next_action() {
+        let now = at(0);
+        let docked = ship("X1-A-M", "DOCKED", at(-100), 1500);
+        assert!(matches!(
+            next_action(None, &docked, now),
+            RouteAction::Replan { .. }
+        ));
+
+        let mut progress = RouteProgress::new(route());
+        assert_eq!(
+            next_action(Some(&progress), &docked, now),
+            RouteAction::Fly(progress.route.legs[0].clone())
+        );
+
+        // flying the first leg
+        progress.next = 1;
+        progress.expected_arrival = Some(at(28));
+        let in_transit = ship("X1-A-G", "IN_TRANSIT", at(28), 1400);
+        assert_eq!(
+            next_action(Some(&progress), &in_transit, now),
+            RouteAction::Wait(at(28))
+        );
+        let arrived = ship("X1-A-G", "IN_ORBIT", at(28), 1400);
+        assert_eq!(
+            next_action(Some(&progress), &arrived, at(30)),
+            RouteAction::Fly(progress.route.legs[1].clone())
+        );
+
+        // refuel at the end of the last leg, then we're there
+        progress.next = 3;
+        progress.expected_arrival = Some(at(160));
+        let arrived = ship("X1-B-M", "IN_ORBIT", at(158), 1280);
+        assert_eq!(
+            next_action(Some(&progress), &arrived, at(160)),
+            RouteAction::Refuel
+        );
+        progress.refuelled = true;
+        assert_eq!(
+            next_action(Some(&progress), &arrived, at(160)),
+            RouteAction::Arrived
+        );
+    }
+
+    #[test]
+    fn test_replan() {
+        let mut progress = RouteProgress::new(route());
+        progress.next = 1;
+        progress.expected_arrival = Some(at(28));
+        let replan = |ship: &Ship| match next_action(Some(&progress), ship, at(1000)) {
+            RouteAction::Replan { reason } => reason,
+            action => panic!("{:?}", action),
+        };
+
+        // somewhere else
+        let ship1 = ship("X1-A-M", "IN_ORBIT", at(28), 1400);
+        assert_eq!(replan(&ship1), "at X1-A-M, not X1-A-G");
+        // burned more fuel than planned
+        let ship2 = ship("X1-A-G", "IN_ORBIT", at(28), 1300);
+        assert_eq!(replan(&ship2), "1300 fuel, not 1400");
+        // 30s, plus a tenth of 28s
+        let ship3 = ship("X1-A-G", "IN_ORBIT", at(28 + 33), 1400);
+        assert_eq!(replan(&ship3), "arrived 33s late");
+        let ship4 = ship("X1-A-G", "IN_ORBIT", at(28 + 32), 1400);
+        assert!(matches!(
+            next_action(Some(&progress), &ship4, at(1000)),
+            RouteAction::Fly(_)
+        ));
+    }
+
+    fn waypoint(symbol: &str, _type: &str, x: i32, market: bool) -> Waypoint {
+        let traits = match market {
+            true => vec![Symbol {
+                symbol: "MARKETPLACE".into(),
+            }],
+            false => vec![],
+        };
+        Waypoint {
+            symbol: symbol.into(),
+            _type: _type.into(),
+            x,
+            traits,
+            ..Default::default()
+        }
+    }
+
+    // a ship flying at the game's speeds and fuel costs, on `clock`
+    fn fake_api(ship: Ship, system: &System, clock: Arc<SimClock>) -> FakeApi {
+        let ship = std::sync::Mutex::new(ship);
+        let positions: HashMap<String, (i32, i32)> = (system.waypoints.iter())
+            .map(|w| (w.symbol.clone(), (w.x, w.y)))
+            .collect();
+        FakeApi::start(move |method, path, body| {
+            let mut ship = ship.lock().unwrap();
+            let data = match (method.as_str(), path.rsplit('/').next().unwrap()) {
+                ("POST", "orbit") => {
+                    ship.nav.status = "IN_ORBIT".into();
+                    json!({ "nav": ship.nav })
+                }
+                ("POST", "dock") => {
+                    ship.nav.status = "DOCKED".into();
+                    json!({ "nav": ship.nav })
+                }
+                ("PATCH", "nav") => {
+                    ship.nav.flight_mode = body["flightMode"].as_str().unwrap().into();
+                    json!(ship.nav)
+                }
+                ("POST", "navigate") => {
+                    let to = body["waypointSymbol"].as_str().unwrap();
+                    let (x0, y0) = positions[&ship.nav.waypoint_symbol];
+                    let (x1, y1) = positions[to];
+                    let distance = (((x1 - x0).pow(2) + (y1 - y0).pow(2)) as f64)
+                        .sqrt()
+                        .round();
+                    let (multiplier, fuel) = match ship.nav.flight_mode.as_str() {
+                        "BURN" => (2., distance * 2.),
+                        "DRIFT" => (0.1, 1.),
+                        _ => (1., distance),
+                    };
+                    let speed = ship.engine.speed as f64 * multiplier / 15.;
+                    let duration = 15 + (distance / speed).round() as i64;
+                    ship.fuel.current -= fuel as u32;
+                    ship.nav.waypoint_symbol = to.into();
+                    ship.nav.status = "IN_TRANSIT".into();
+                    ship.nav.route.departure_time = clock.utc();
+                    ship.nav.route.arrival = clock.utc() + chrono::Duration::seconds(duration);
+                    json!({ "nav": ship.nav, "fuel": ship.fuel })
+                }
+                ("POST", "refuel") => {
+                    let units = body["units"].as_u64().unwrap() as u32;
+                    ship.fuel.current = (ship.fuel.current + units).min(ship.fuel.capacity);
+                    json!({ "agent": Agent::default(), "fuel": ship.fuel })
+                }
+                _ => return (StatusCode::NOT_FOUND, json!({})),
+            };
+            (StatusCode::OK, json!({ "data": data }))
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_step() {
+        // X1-A-M doesn't sell fuel, though the graph doesn't know it
+        let system = System {
+            symbol: "X1-A".into(),
+            waypoints: vec![
+                waypoint("X1-A-M", "PLANET", 0, true),
+                waypoint("X1-A-D", "MOON", 100, true),
+                waypoint("X1-A-AST", "ASTEROID_FIELD", -30, false),
+            ],
+            ..Default::default()
+        };
+        let clock = Arc::new(SimClock::new(at(0)));
+        let mut ship = ship("X1-A-AST", "DOCKED", at(0), 300);
+        ship.symbol = "S-1".into();
+        ship.fuel.capacity = 400;
+        ship.engine.speed = 30;
+        let api = fake_api(ship.clone(), &system, clock.clone());
+        let graph = Arc::new(L2Graph::from_systems(&[system]));
+        let par = api.controller(clock.clone());
+        par.ships.insert(
+            ship.symbol.clone(),
+            Arc::new(tokio::sync::RwLock::new(ship)),
+        );
+        for (symbol, goods) in [("X1-A-M", "IRON_ORE"), ("X1-A-D", "FUEL")] {
+            let market = Market {
+                symbol: symbol.into(),
+                trade_goods: vec![MarketTradeGood {
+                    symbol: goods.into(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            par.markets.insert(symbol.into(), Arc::new(market));
+        }
+
+        // from off the graph, to X1-A-M, where there's no fuel after all, then re-planned
+        let executor = RouteExecutor::new(&par, "S-1", "X1-A-D", graph);
+        let mut steps = 0;
+        loop {
+            match executor.step().await {
+                StepOutcome::At(time) => clock.sleep(clock.until(time)).await,
+                StepOutcome::After(duration) => clock.sleep(duration).await,
+                StepOutcome::Done => break,
+                outcome => panic!("{:?}", outcome),
+            }
+            steps += 1;
+            assert!(steps < 10);
+        }
+        let requests = (api.requests().into_iter())
+            .filter(|r| r.ends_with("navigate") || r.ends_with("refuel"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requests,
+            [
+                "POST /v2/my/ships/S-1/navigate",
+                "POST /v2/my/ships/S-1/navigate",
+                "POST /v2/my/ships/S-1/refuel"
+            ]
+        );
+        let ship = par.ships.get("S-1").unwrap().read().await.clone();
+        assert_eq!(ship.nav.waypoint_symbol, "X1-A-D");
+        assert_eq!(ship.fuel.current, 400);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sim_route() {
+        // too far to burn, or cruise without stopping at X1-A-B for fuel,
+        // where the tank is 550 short: not a whole number of market units
+        let system = System {
+            symbol: "X1-A".into(),
+            waypoints: vec![
+                waypoint("X1-A-A", "PLANET", 0, true),
+                waypoint("X1-A-B", "MOON", 550, true),
+                waypoint("X1-A-C", "MOON", 1100, true),
+            ],
+            ..Default::default()
+        };
+        let clock = Arc::new(SimClock::new(at(0)));
+        let mut ship = ship("X1-A-A", "DOCKED", at(0), 800);
+        ship.symbol = "S-1".into();
+        ship.fuel.capacity = 800;
+        ship.engine.speed = 10;
+        let profile = ShipProfile::from_ship(&ship);
+        let api = fake_api(ship.clone(), &system, clock.clone());
+        let par = api.controller(clock.clone());
+        par.ships.insert(
+            ship.symbol.clone(),
+            Arc::new(tokio::sync::RwLock::new(ship)),
+        );
+        let graph = Arc::new(L2Graph::from_systems(&[system]));
+        let route = graph.astar("X1-A-A", "X1-A-C", &profile).unwrap();
+        assert_eq!(route.refuels, ["X1-A-B", "X1-A-C"]);
+        // 15s, and 550 at a speed of 10 / 15
+        assert_eq!(route.duration, 2 * (15 + 825));
+
+        // hours of flight, scheduled by the runtime
+        let mut runtime = Runtime::new(1);
+        runtime.set_clock(clock.clone());
+        let executor = RouteExecutor::new(&par, "S-1", "X1-A-C", graph);
+        let id = StepId::new("route", "S-1");
+        runtime.add(id.clone(), Box::new(executor), 0).await;
+        let real = std::time::Instant::now();
+        runtime.run().await;
+        assert!(real.elapsed() < Duration::from_secs(5));
+        assert_eq!(runtime.state(&id).await, ItemState::Finished);
+
+        let ship = par.ships.get("S-1").unwrap().read().await.clone();
+        assert_eq!(ship.nav.waypoint_symbol, "X1-A-C");
+        assert_eq!(ship.nav.route.arrival, at(route.duration as i64));
+        assert!(clock.utc() >= at(route.duration as i64));
+        // flown as planned, without re-planning for fuel on the way
+        assert_eq!(ship.fuel.current, 800);
+        let count = |action: &str| {
+            (api.requests().into_iter())
+                .filter(|r| r.ends_with(action))
+                .count()
+        };
+        assert_eq!(count("navigate"), 2);
+        assert_eq!(count("refuel"), 2);
+    }
+}
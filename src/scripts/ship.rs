@@ -0,0 +1,150 @@
+use crate::controller::Controller;
+use crate::runtime::{Resource, Step, StepId, StepOutcome};
+use crate::scripts::mining::MiningController;
+use crate::scripts::modules::ModulesExecutor;
+use crate::shipconfig::*;
+use async_trait::async_trait;
+use log::{debug, info};
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+
+pub fn step_id(ship_symbol: &str) -> StepId {
+    StepId::new("ship", ship_symbol)
+}
+
+/// The key a ship's executor waits on while idle: notify it when the ship's config changes
+pub fn config_key(ship_symbol: &str) -> String {
+    format!("config:{}", ship_symbol)
+}
+
+enum Phase {
+    Idle,
+    Modules(Box<ModulesExecutor>),
+    Script(Box<dyn Step + Send + Sync>),
+}
+
+///
+/// ShipExecutor runs whatever the ship's config says it should be doing
+/// Config changes are picked up at the start of the next step, so the running step
+/// always finishes under the instructions it started with
+///
+pub struct ShipExecutor {
+    par: Controller,
+    ship_symbol: String,
+    config: Mutex<watch::Receiver<ShipConfig>>,
+    phase: Mutex<Option<Phase>>,
+    // the phase's resources as of the end of the last step
+    claims: std::sync::Mutex<Vec<Resource>>,
+}
+
+impl ShipExecutor {
+    pub fn new(par: &Controller, config: watch::Receiver<ShipConfig>) -> Self {
+        let ship_symbol = config.borrow().symbol.clone();
+        Self {
+            par: par.clone(),
+            config: Mutex::new(config),
+            phase: Mutex::new(None),
+            claims: std::sync::Mutex::new(vec![Resource::Ship(ship_symbol.clone())]),
+            ship_symbol,
+        }
+    }
+
+    fn phase_resources(&self, phase: Option<&Phase>) -> Vec<Resource> {
+        let ship = Resource::Ship(self.ship_symbol.clone());
+        let mut resources = match phase {
+            Some(Phase::Modules(modules_executor)) => modules_executor.resources(),
+            Some(Phase::Script(script)) => script.resources(),
+            _ => vec![],
+        };
+        if !resources.contains(&ship) {
+            resources.push(ship);
+        }
+        resources
+    }
+
+    async fn script_phase(&self, config: &ShipConfig) -> Phase {
+        match &config.script {
+            ShipScript::None => Phase::Idle,
+            ShipScript::Mining(mining_config) => {
+                let mining_controller = MiningController::new(
+                    &self.par,
+                    &self.ship_symbol,
+                    &mining_config.asteroid_symbol,
+                );
+                Phase::Script(Box::new(mining_controller.setup().await))
+            }
+        }
+    }
+
+    async fn phase(&self, config: &ShipConfig) -> Phase {
+        if config.paused {
+            info!("{} is paused", self.ship_symbol);
+            return Phase::Idle;
+        }
+        if !self.par.ships.contains_key(&self.ship_symbol) {
+            info!("{} is not in the fleet", self.ship_symbol);
+            return Phase::Idle;
+        }
+        if let Some(module_config) = &config.module_config {
+            let modules_executor =
+                ModulesExecutor::new(&self.par, &self.ship_symbol, module_config);
+            if !modules_executor.is_satisfied().await {
+                info!("Reconciling mounts for {}", self.ship_symbol);
+                return Phase::Modules(Box::new(modules_executor));
+            }
+        }
+        self.script_phase(config).await
+    }
+
+    async fn step_phase(&self, phase: &mut Option<Phase>) -> StepOutcome {
+        let mut rx = self.config.lock().await;
+        if phase.is_none() || rx.has_changed().unwrap_or(false) {
+            let config = rx.borrow_and_update().clone();
+            debug!("{}: applying config {:?}", self.ship_symbol, config);
+            *phase = Some(self.phase(&config).await);
+            // this step was claimed for the old phase: the new one runs under its own claims
+            if !matches!(phase, Some(Phase::Idle)) {
+                return StepOutcome::After(Duration::from_secs(0));
+            }
+        }
+        drop(rx);
+
+        match phase.as_ref().unwrap() {
+            Phase::Idle => StepOutcome::WhenNotified(config_key(&self.ship_symbol)),
+            Phase::Modules(modules_executor) => match modules_executor.step().await {
+                StepOutcome::Done => {
+                    // mounts are done: move on to the script
+                    let config = self.config.lock().await.borrow().clone();
+                    *phase = Some(self.script_phase(&config).await);
+                    StepOutcome::After(Duration::from_secs(0))
+                }
+                ret => ret,
+            },
+            Phase::Script(script) => match script.step().await {
+                StepOutcome::Done => {
+                    *phase = Some(Phase::Idle);
+                    StepOutcome::WhenNotified(config_key(&self.ship_symbol))
+                }
+                ret => ret,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Step for ShipExecutor {
+    async fn step(&self) -> StepOutcome {
+        let mut phase = self.phase.lock().await;
+        let outcome = self.step_phase(&mut phase).await;
+        *self.claims.lock().unwrap() = self.phase_resources(phase.as_ref());
+        outcome
+    }
+
+    fn resources(&self) -> Vec<Resource> {
+        // claimed from the runtime's loop: never block here
+        match self.phase.try_lock() {
+            Ok(phase) => self.phase_resources(phase.as_ref()),
+            Err(_) => self.claims.lock().unwrap().clone(),
+        }
+    }
+}
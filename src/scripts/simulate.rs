@@ -0,0 +1,241 @@
+use crate::decision_tree::{EdgeType, Metric};
+use crate::scripts::mining::PreparedGraph;
+use graph_builder::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+///
+/// Monte Carlo check of a PreparedGraph's policy: plays episodes from start, taking the chosen
+/// successor at decision nodes and drawing probability edges by weight, and measures the
+/// credits per second actually earned. A repeated decision edge is taken once, and the
+/// subtree after it played that many times over, as `evaluate` models it.
+///
+#[derive(Copy, Clone, Debug)]
+pub struct SimulateOptions {
+    pub episodes: u32,
+    pub seed: Option<u64>,
+    // of the confidence interval, eg. 1.96 for 95%
+    pub z: f64,
+}
+
+impl Default for SimulateOptions {
+    fn default() -> Self {
+        Self {
+            episodes: 10_000,
+            seed: None,
+            z: 1.96,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationReport {
+    pub episodes: u32,
+    // total credits over total seconds
+    pub cps: f64,
+    pub stderr: f64,
+    pub interval: (f64, f64),
+    pub mean_reward: f64,
+    pub mean_duration: f64,
+    // what evaluate says cps should be
+    pub x0: f64,
+}
+
+impl SimulationReport {
+    pub fn contains_x0(&self) -> bool {
+        self.interval.0 <= self.x0 && self.x0 <= self.interval.1
+    }
+}
+
+enum Choice {
+    Leaf,
+    Decision {
+        target: usize,
+        metric: Metric,
+        repeats: u32,
+    },
+    Probability {
+        // cumulative weights
+        weights: Vec<f64>,
+        edges: Vec<(usize, Metric)>,
+    },
+}
+
+struct Simulator {
+    choices: Vec<Choice>,
+}
+
+impl Simulator {
+    fn new(g: &PreparedGraph) -> Self {
+        let mut successors = vec![None; g.graph.node_count()];
+        for (name, state) in g.state.iter() {
+            if let Some(successor) = &state.successor {
+                successors[g.nodes[name]] = Some(g.nodes[successor]);
+            }
+        }
+
+        let choices = (0..g.graph.node_count())
+            .map(|x| {
+                let mut edges = g.graph.out_neighbors_with_values(x).peekable();
+                match edges.peek().map(|t| t.value.edge_type) {
+                    None => Choice::Leaf,
+                    Some(EdgeType::Decision(_)) => {
+                        // not evaluated: unreachable from start
+                        let Some(successor) = successors[x] else {
+                            return Choice::Leaf;
+                        };
+                        let edge = edges.find(|t| t.target == successor).unwrap();
+                        let repeats = match edge.value.edge_type {
+                            EdgeType::Decision(repeats) => repeats,
+                            _ => panic!(),
+                        };
+                        Choice::Decision {
+                            target: successor,
+                            metric: edge.value.metric,
+                            repeats,
+                        }
+                    }
+                    Some(EdgeType::Probability(_)) => {
+                        let mut weights = vec![];
+                        let mut sum = 0.0;
+                        let edges = edges
+                            .map(|t| {
+                                if let EdgeType::Probability(w) = t.value.edge_type {
+                                    sum += w;
+                                }
+                                weights.push(sum);
+                                (t.target, t.value.metric)
+                            })
+                            .collect();
+                        Choice::Probability { weights, edges }
+                    }
+                }
+            })
+            .collect();
+        Self { choices }
+    }
+
+    // reward and duration of one play from x
+    fn play(&self, x: usize, rng: &mut StdRng) -> (f64, f64) {
+        match &self.choices[x] {
+            Choice::Leaf => (0.0, 0.0),
+            Choice::Decision {
+                target,
+                metric,
+                repeats,
+            } => {
+                let mut total = (metric.0, metric.1);
+                for _ in 0..*repeats {
+                    let (r, t) = self.play(*target, rng);
+                    total = (total.0 + r, total.1 + t);
+                }
+                total
+            }
+            Choice::Probability { weights, edges } => {
+                let draw = rng.gen::<f64>() * weights.last().unwrap();
+                let i = weights.partition_point(|&w| w <= draw).min(edges.len() - 1);
+                let (target, metric) = edges[i];
+                let (r, t) = self.play(target, rng);
+                (metric.0 + r, metric.1 + t)
+            }
+        }
+    }
+}
+
+impl PreparedGraph {
+    pub fn simulate(&self, options: &SimulateOptions) -> SimulationReport {
+        let simulator = Simulator::new(self);
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let start = self.nodes["start"];
+        let episodes: Vec<(f64, f64)> = (0..options.episodes)
+            .map(|_| simulator.play(start, &mut rng))
+            .collect();
+
+        let n = episodes.len() as f64;
+        let mean_reward = episodes.iter().map(|e| e.0).sum::<f64>() / n;
+        let mean_duration = episodes.iter().map(|e| e.1).sum::<f64>() / n;
+        let cps = mean_reward / mean_duration;
+        // ratio estimator: the variance of R - cps * T, scaled by the mean duration
+        let residuals = episodes
+            .iter()
+            .map(|(r, t)| (r - cps * t).powi(2))
+            .sum::<f64>();
+        let stderr = (residuals / (n * (n - 1.0))).sqrt() / mean_duration;
+        SimulationReport {
+            episodes: options.episodes,
+            cps,
+            stderr,
+            interval: (cps - options.z * stderr, cps + options.z * stderr),
+            mean_reward,
+            mean_duration,
+            x0: self.x0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decision_tree::DecisionGraphBuilder;
+
+    #[test]
+    fn test_simulate() {
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .decision("start", "extract", Metric(0.0, 0.0))
+            .probability("extract", "cargo_A", Metric(0.0, 10.0), 3.0)
+            .probability("extract", "cargo_B", Metric(0.0, 10.0), 1.0)
+            .decision("cargo_A", "sell_M", Metric(100.0, 5.0))
+            .decision("cargo_A", "finish", Metric(0.0, 0.0))
+            .decision("cargo_B", "sell_M", Metric(20.0, 5.0))
+            .decision("cargo_B", "finish", Metric(0.0, 0.0))
+            .decision("sell_M", "finish", Metric(0.0, 0.0));
+        let g = PreparedGraph::new(builder);
+        let options = SimulateOptions {
+            seed: Some(1),
+            ..Default::default()
+        };
+        let report = g.simulate(&options);
+        assert_eq!(report.episodes, 10_000);
+        assert!(report.contains_x0(), "{:?}", report);
+        assert!(report.stderr > 0.0 && report.stderr < 0.1);
+        // cargo_B isn't worth selling: 10 seconds, plus 5 three times out of four
+        assert_eq!(g.state["cargo_B"].successor.as_deref(), Some("finish"));
+        assert!((report.mean_duration - 13.75).abs() < 0.1);
+
+        // the same seed plays the same episodes
+        assert_eq!(g.simulate(&options), report);
+    }
+
+    #[test]
+    fn test_simulate_repeats() {
+        // survey once, then extract the survey 10 times
+        let mut builder = DecisionGraphBuilder::new();
+        builder
+            .decision("start", "survey", Metric(0.0, 0.0))
+            .probability("survey", "survey_0", Metric(0.0, 30.0), 1.0)
+            .decision("survey_0", "finish", Metric(0.0, 0.0))
+            .repeated_decision("survey_0", "extract_survey_0", Metric(0.0, 0.0), 10)
+            .probability("extract_survey_0", "cargo_A", Metric(0.0, 10.0), 1.0)
+            .probability("extract_survey_0", "cargo_B", Metric(0.0, 10.0), 1.0)
+            .decision("cargo_A", "sell_M", Metric(100.0, 5.0))
+            .decision("cargo_A", "finish", Metric(0.0, 0.0))
+            .decision("cargo_B", "finish", Metric(0.0, 0.0))
+            .decision("sell_M", "finish", Metric(0.0, 0.0));
+        let g = PreparedGraph::new(builder);
+        assert_eq!(
+            g.state["survey_0"].successor.as_deref(),
+            Some("extract_survey_0")
+        );
+        let report = g.simulate(&SimulateOptions {
+            episodes: 5_000,
+            seed: Some(2),
+            ..Default::default()
+        });
+        assert!(report.contains_x0(), "{:?}", report);
+        assert!(report.mean_duration > 30.0 + 10.0 * 10.0);
+    }
+}